@@ -0,0 +1,54 @@
+// Conversions between UI token amounts and base units
+
+use crate::error::StreamError;
+
+/// Converts a UI amount (e.g. 1.5 USDC) to base units of a mint with `decimals`
+pub fn ui_amount_to_base(ui_amount: f64, decimals: u8) -> Result<u64, StreamError> {
+
+    if ui_amount.is_nan() || ui_amount < 0.0 {
+        return Err(StreamError::InvalidArgument);
+    }
+
+    to_base_amount(ui_amount, 10f64.powi(decimals as i32))
+}
+
+/// Converts a UI amount to base units given `pow` (10 to the mint decimals), rounded to the
+/// nearest unit. Fails with `AmountOverflow` when the result does not fit in a `u64`
+pub fn to_base_amount(amount: f64, pow: f64) -> Result<u64, StreamError> {
+
+    let base_amount = (amount * pow).round();
+
+    if !base_amount.is_finite() || base_amount < 0.0 || base_amount >= u64::MAX as f64 {
+        return Err(StreamError::AmountOverflow);
+    }
+
+    Ok(base_amount as u64)
+}
+
+/// Converts base units of a mint with `decimals` to a UI amount
+pub fn base_amount_to_ui(base_amount: u64, decimals: u8) -> f64 {
+    base_amount as f64 / 10f64.powi(decimals as i32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ui_amounts_convert_to_rounded_base_units() {
+        assert_eq!(ui_amount_to_base(1.5, 6), Ok(1_500_000));
+        assert_eq!(ui_amount_to_base(0.29, 2), Ok(29));
+        assert_eq!(ui_amount_to_base(0.0, 9), Ok(0));
+        assert_eq!(ui_amount_to_base(-1.0, 6), Err(StreamError::InvalidArgument));
+        assert_eq!(ui_amount_to_base(f64::NAN, 6), Err(StreamError::InvalidArgument));
+        assert_eq!(ui_amount_to_base(f64::INFINITY, 6), Err(StreamError::AmountOverflow));
+        assert_eq!(ui_amount_to_base(2e19, 0), Err(StreamError::AmountOverflow));
+    }
+
+    #[test]
+    fn base_amounts_convert_back_to_ui_amounts() {
+        assert_eq!(base_amount_to_ui(1_500_000, 6), 1.5);
+        assert_eq!(base_amount_to_ui(29, 2), 0.29);
+        assert_eq!(base_amount_to_ui(ui_amount_to_base(123.456789, 6).unwrap(), 6), 123.456789);
+    }
+}
@@ -6,4 +6,13 @@ pub const PROPOSE_UPDATE_FLAT_FEE: f64 = 0.000010;
 pub const CLOSE_STREAM_FLAT_FEE: f64 = 0.000010;
 pub const CLOSE_STREAM_PERCENT_FEE: f64 = 0.3;
 pub const LAMPORTS_PER_SOL: u64 = 1000000000;
-pub const TREASURY_MINT_DECIMALS: u8 = 6;
\ No newline at end of file
+pub const TREASURY_MINT_DECIMALS: u8 = 6;
+pub const MAX_STREAM_NAME_LEN: usize = 32;
+pub const PROPOSAL_EXPIRATION_IN_SECONDS: u64 = 604800; // default when a proposal sets no expiration
+pub const SECONDS_PER_YEAR: u64 = 31557600; // 365.25 days
+pub const SECONDS_PER_DAY: u64 = 86400;
+pub const SECONDS_PER_MONTH: u64 = 2592000; // 30 days
+pub const MAX_START_UTC_OFFSET_IN_SECONDS: u64 = 315576000; // 10 years either side of the cluster clock
+pub const MAX_TRANSACTION_SIZE: usize = 1232; // bytes of a serialized transaction (packet data size)
+pub const MAX_BATCH_SIZE: usize = 10; // instructions of the program packed in a single transaction
+pub const MAX_MULTI_WITHDRAW_STREAMS: u8 = 4; // streams a MultiWithdraw can drain within the compute budget
\ No newline at end of file
@@ -80,12 +80,127 @@ pub enum StreamError {
     InvalidSignerAuthority,
 
     #[error("Overflow")]
-    Overflow
+    Overflow,
+
+    #[error("Invalid beneficiary token account")]
+    InvalidBeneficiaryTokenAccount,
+
+    #[error("Cliff ends after the stream funds are depleted")]
+    CliffAfterDepletion,
+
+    #[error("Insufficient token balance")]
+    InsufficientBalance,
+
+    #[error("Invalid rate interval")]
+    InvalidRateInterval,
+
+    #[error("Invalid funding increment")]
+    InvalidFundingIncrement,
+
+    #[error("Unsupported state version")]
+    UnsupportedStateVersion,
+
+    #[error("Proposal not expired")]
+    ProposalNotExpired,
+
+    #[error("Withdrawal exceeds vested amount")]
+    WithdrawalExceedsVested,
+
+    #[error("Treasury not empty")]
+    TreasuryNotEmpty,
+
+    #[error("Truncated pubkey")]
+    TruncatedPubkey,
+
+    #[error("Truncated amount")]
+    TruncatedAmount,
+
+    #[error("Bad string length")]
+    BadStringLength,
+
+    #[error("Unknown instruction tag")]
+    UnknownInstructionTag,
+
+    #[error("Account layout mismatch")]
+    AccountLayoutMismatch,
+
+    #[error("Renewal strands funds")]
+    RenewalStrandsFunds,
+
+    #[error("Invalid cliff percent")]
+    InvalidCliffPercent,
+
+    #[error("Stream already paused")]
+    StreamAlreadyPaused,
+
+    #[error("Stream already running")]
+    StreamAlreadyRunning,
+
+    #[error("Unsupported mint extension")]
+    UnsupportedMintExtension,
+
+    #[error("Invalid withdraw hook program")]
+    InvalidWithdrawHook,
+
+    #[error("Amount overflow")]
+    AmountOverflow,
+
+    #[error("Recover amount exceeds the unvested contribution")]
+    RecoverExceedsUnvested,
+
+    #[error("Stream can not vest")]
+    StreamCannotVest,
+
+    #[error("Batch too large")]
+    BatchTooLarge,
+
+    #[error("Invalid pool mint authority")]
+    InvalidPoolMintAuthority,
+
+    #[error("Funding amount not received")]
+    FundingNotReceived,
+
+    #[error("Rate too high for the funding")]
+    RateTooHighForFunding,
+
+    #[error("Too many streams in the treasury")]
+    TooManyStreams,
+
+    #[error("Stream depletes before the expected date")]
+    DepletionTooSoon,
+
+    #[error("Invalid treasurer token account")]
+    InvalidTreasurerTokenAccount,
+
+    #[error("Start time out of range")]
+    StartTimeOutOfRange,
+
+    #[error("An update is already pending")]
+    UpdateAlreadyPending,
+
+    #[error("The same account is passed for more than one role")]
+    DuplicateAccount,
+
+    #[error("The stream cliff is longer than the funded stream duration")]
+    CliffExceedsDuration
+}
+
+impl StreamError {
+    /// Stable numeric code of the error (its position in the enum), the `ProgramError::Custom` value
+    pub fn to_u32(&self) -> u32 {
+        self.clone() as u32
+    }
 }
 
 impl From<StreamError> for ProgramError {
     fn from(e: StreamError) -> Self {
-        ProgramError::Custom(e as u32)
+        ProgramError::Custom(e.to_u32())
+    }
+}
+
+impl From<std::io::Error> for StreamError {
+    fn from(_: std::io::Error) -> Self {
+        StreamError::InvalidStreamData
     }
 }
 
@@ -120,7 +235,41 @@ impl PrintProgramError for StreamError {
             Self::NotAuthorizedToWithdraw => msg!("Error: Not authorized to withdraw from the stream"),
             Self::InvalidWithdrawalDate => msg!("Error: The date to withdraw your money has not been reached yet"),
             Self::InvalidSignerAuthority => msg!("Error: InvalidSignerAuthority"),
-            Self::Overflow => msg!("Error: Overflow")
+            Self::Overflow => msg!("Error: Overflow"),
+            Self::InvalidBeneficiaryTokenAccount => msg!("Error: Beneficiary token account is not owned by the beneficiary or does not hold the stream mint"),
+            Self::CliffAfterDepletion => msg!("Error: The stream cliff ends after the estimated depletion time"),
+            Self::InsufficientBalance => msg!("Error: The source token account balance is lower than the amount to transfer"),
+            Self::InvalidRateInterval => msg!("Error: The rate interval must be greater than zero"),
+            Self::InvalidFundingIncrement => msg!("Error: The amount is not a multiple of the treasury funding increment"),
+            Self::UnsupportedStateVersion => msg!("Error: The account data layout version is not supported"),
+            Self::ProposalNotExpired => msg!("Error: The stream terms proposal has not expired yet"),
+            Self::WithdrawalExceedsVested => msg!("Error: Can not withdraw more than the vested amount not withdrawn yet"),
+            Self::TreasuryNotEmpty => msg!("Error: The treasury still has streams or tokens"),
+            Self::TruncatedPubkey => msg!("Error: The instruction data ends before a 32 bytes pubkey"),
+            Self::TruncatedAmount => msg!("Error: The instruction data ends before a fixed size field"),
+            Self::BadStringLength => msg!("Error: The string length prefix is missing, too large or longer than the data"),
+            Self::UnknownInstructionTag => msg!("Error: UnknownInstructionTag"),
+            Self::AccountLayoutMismatch => msg!("Error: The accounts do not match the ones expected by the instruction"),
+            Self::RenewalStrandsFunds => msg!("Error: The new stream terms would leave part of the remaining funds unreachable"),
+            Self::InvalidCliffPercent => msg!("Error: The cliff vest percent must be between 0 and 100"),
+            Self::StreamAlreadyPaused => msg!("Error: StreamAlreadyPaused"),
+            Self::StreamAlreadyRunning => msg!("Error: StreamAlreadyRunning"),
+            Self::UnsupportedMintExtension => msg!("Error: UnsupportedMintExtension"),
+            Self::InvalidWithdrawHook => msg!("Error: InvalidWithdrawHook"),
+            Self::AmountOverflow => msg!("Error: AmountOverflow"),
+            Self::RecoverExceedsUnvested => msg!("Error: RecoverExceedsUnvested"),
+            Self::StreamCannotVest => msg!("Error: StreamCannotVest"),
+            Self::BatchTooLarge => msg!("Error: BatchTooLarge"),
+            Self::InvalidPoolMintAuthority => msg!("Error: InvalidPoolMintAuthority"),
+            Self::FundingNotReceived => msg!("Error: FundingNotReceived"),
+            Self::RateTooHighForFunding => msg!("Error: RateTooHighForFunding"),
+            Self::TooManyStreams => msg!("Error: TooManyStreams"),
+            Self::DepletionTooSoon => msg!("Error: DepletionTooSoon"),
+            Self::InvalidTreasurerTokenAccount => msg!("Error: Treasurer token account is not owned by the treasurer or does not hold the stream mint"),
+            Self::StartTimeOutOfRange => msg!("Error: StartTimeOutOfRange"),
+            Self::UpdateAlreadyPending => msg!("Error: UpdateAlreadyPending"),
+            Self::DuplicateAccount => msg!("Error: DuplicateAccount"),
+            Self::CliffExceedsDuration => msg!("Error: CliffExceedsDuration")
         }
     }
 }
@@ -151,4 +300,64 @@ impl PrintProgramError for TreasuryError {
             Self::InvalidTreasuryData => msg!("Error: InvalidTreasuryData")
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use num_traits::FromPrimitive;
+
+    #[test]
+    fn error_codes_are_stable_and_new_variants_are_appended() {
+        let original = [
+            StreamError::IncorrectProgramId,
+            StreamError::InvalidStreamInstruction,
+            StreamError::StreamAlreadyInitialized,
+            StreamError::StreamTermsAlreadyInitialized,
+            StreamError::InvalidStreamData,
+            StreamError::InvalidTreasuryAccount,
+            StreamError::InvalidTreasuryMint,
+            StreamError::InvalidTreasuryToken,
+            StreamError::InvalidTreasuryData,
+            StreamError::InvalidTreasuryPool,
+            StreamError::InvalidContributorTreasuryToken,
+            StreamError::InvalidMspOpsToken,
+            StreamError::MissingInstructionSignature,
+            StreamError::InvalidRentException,
+            StreamError::InsufficientFunds,
+            StreamError::InstructionNotAuthorized,
+            StreamError::InvalidArgument,
+            StreamError::NotAllowedRecoverableAmount,
+            StreamError::NotAllowedWithdrawalAmount,
+            StreamError::NotAuthorizedToWithdraw,
+            StreamError::InvalidWithdrawalDate,
+            StreamError::InvalidSignerAuthority,
+            StreamError::Overflow
+        ];
+
+        for (code, error) in original.iter().enumerate() {
+            assert_eq!(ProgramError::from(error.clone()), ProgramError::Custom(code as u32));
+            assert_eq!(StreamError::from_u32(code as u32).as_ref(), Some(error));
+        }
+
+        assert_eq!(StreamError::InvalidBeneficiaryTokenAccount as u32, StreamError::Overflow as u32 + 1);
+        assert!(StreamError::TruncatedPubkey as u32 > StreamError::Overflow as u32);
+        assert!(StreamError::UnknownInstructionTag as u32 > StreamError::Overflow as u32);
+    }
+
+    #[test]
+    fn to_u32_is_the_custom_program_error_code() {
+        assert_eq!(StreamError::IncorrectProgramId.to_u32(), 0);
+        assert_eq!(StreamError::Overflow.to_u32(), 22);
+        assert_eq!(
+            ProgramError::from(StreamError::AmountOverflow),
+            ProgramError::Custom(StreamError::AmountOverflow.to_u32())
+        );
+    }
+
+    #[test]
+    fn io_errors_are_invalid_stream_data() {
+        let error = std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "unexpected end of data");
+
+        assert_eq!(StreamError::from(error), StreamError::InvalidStreamData);
+    }
+}
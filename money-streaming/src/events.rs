@@ -0,0 +1,72 @@
+// Structured program events, logged with `sol_log_data` for indexers
+
+use solana_program::{
+    log::sol_log_data,
+    program_error::ProgramError,
+    pubkey::Pubkey
+};
+
+use borsh::{ BorshDeserialize, BorshSerialize };
+
+use crate::error::StreamError;
+
+/// Stream state transitions. Each event is logged as a single borsh encoded data buffer
+#[derive(Clone, Debug, PartialEq, BorshSerialize, BorshDeserialize)]
+pub enum StreamEvent {
+    StreamCreated {
+        stream_address: Pubkey,
+        treasurer_address: Pubkey,
+        beneficiary_address: Pubkey,
+        total_deposits: f64
+    },
+
+    FundsAdded {
+        stream_address: Pubkey,
+        contributor_address: Pubkey,
+        amount: f64,
+        total_deposits: f64
+    },
+
+    Withdrawn {
+        stream_address: Pubkey,
+        beneficiary_address: Pubkey,
+        amount: f64,
+        total_withdrawals: f64
+    }
+}
+
+impl StreamEvent {
+
+    pub fn emit(&self) -> Result<(), ProgramError> {
+        let data = self.try_to_vec().map_err(StreamError::from)?;
+        sol_log_data(&[&data]);
+
+        Ok(())
+    }
+
+    /// Decodes a data buffer logged by `emit`
+    pub fn decode(input: &[u8]) -> Result<Self, ProgramError> {
+        Ok(Self::try_from_slice(input).map_err(StreamError::from)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn logged_events_decode_back() {
+        let event = StreamEvent::FundsAdded {
+            stream_address: Pubkey::new_unique(),
+            contributor_address: Pubkey::new_unique(),
+            amount: 2.5,
+            total_deposits: 12.5
+        };
+        let data = event.try_to_vec().unwrap();
+
+        // The variant index leads the data
+        assert_eq!(data[0], 1);
+        assert_eq!(StreamEvent::decode(&data).unwrap(), event);
+        assert_eq!(StreamEvent::decode(&data[..data.len() - 1]), Err(StreamError::InvalidStreamData.into()));
+    }
+}
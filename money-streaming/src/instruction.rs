@@ -1,6 +1,6 @@
 // Program API, (de)serializing instruction data
 
-use std::{ mem::size_of, convert::TryInto };
+use std::{ fmt, convert::TryInto, str::FromStr, collections::HashSet };
 
 use solana_program::{
     pubkey::Pubkey,
@@ -9,21 +9,72 @@ use solana_program::{
 
 use crate::{
     check_program_account,
-    error::StreamError
+    check_token_program_account,
+    error::StreamError,
+    pda::{ find_treasury_address, find_treasury_mint_address },
+    constants::{ 
+        MAX_STREAM_NAME_LEN, 
+        SECONDS_PER_YEAR, 
+        SECONDS_PER_DAY, 
+        MAX_BATCH_SIZE, 
+        MAX_TRANSACTION_SIZE,
+        MAX_MULTI_WITHDRAW_STREAMS,
+        MAX_START_UTC_OFFSET_IN_SECONDS
+    }
 };
 
+// Instruction tags, the first byte of the instruction data
+pub const TAG_CREATE_STREAM: u8 = 0;
+pub const TAG_ADD_FUNDS: u8 = 1;
+pub const TAG_RECOVER_FUNDS: u8 = 2;
+pub const TAG_WITHDRAW: u8 = 3;
+pub const TAG_PAUSE_STREAM: u8 = 4;
+pub const TAG_RESUME_STREAM: u8 = 5;
+pub const TAG_PROPOSE_UPDATE: u8 = 6;
+pub const TAG_ANSWER_UPDATE: u8 = 7;
+pub const TAG_CLOSE_STREAM: u8 = 8;
+pub const TAG_CREATE_TREASURY: u8 = 9;
+pub const TAG_TRANSFER: u8 = 10;
+pub const TAG_TRANSFER_STREAM_OWNERSHIP: u8 = 11;
+pub const TAG_CLOSE_TREASURY: u8 = 12;
+pub const TAG_SET_AUTO_PAUSE_THRESHOLD: u8 = 13;
+pub const TAG_WITHDRAW_MAX: u8 = 14;
+pub const TAG_RENAME_STREAM: u8 = 15;
+pub const TAG_MULTI_WITHDRAW: u8 = 16;
+pub const TAG_TOP_UP: u8 = 17;
+pub const TAG_SET_BENEFICIARY_TOKEN_ACCOUNT: u8 = 44;
+pub const TAG_EXPIRE_PROPOSAL: u8 = 45;
+pub const TAG_GET_VERSION: u8 = 46;
+pub const TAG_RENEW_STREAM: u8 = 47;
+pub const TAG_DONATE_STREAM: u8 = 48;
+pub const TAG_SET_WITHDRAW_HOOK: u8 = 49;
+
 pub enum StreamInstruction {
 
     /// Initialize a new stream contract
     ///
     /// 0. `[signer]` The treasurer account (The creator of the money stream).
-    /// 1. `[]` The treasury account (The stream contract treasury account).
+    /// 1. `[writable]` The treasury account (The stream contract treasury account).
     /// 2. `[]` The beneficiary associated token mint account.
     /// 3. `[writable]` The stream account (The stream contract account).
     /// 4.  [writable] The Money Streaming Program operating account (Fees account).
     /// 5.  [] The Money Streaming Program account.
     /// 6. `[]` The System Program account.
     /// 7. `[]` Rent sysvar account.
+    ///
+    /// When `funding_amount` is greater than zero the stream is funded on creation:
+    ///
+    /// 8. `[writable]` The treasurer token account.
+    /// 9. `[writable]` The treasury token account.
+    /// 10. `[writable]` The Money Streaming Program operating token account.
+    /// 11. `[]` The Token Program account.
+    /// 12. `[writable]` The treasurer treasury token account (receives the treasury pool tokens, default key for no pool).
+    /// 13. `[writable]` The treasury mint account (the mint of the treasury pool token, default key for no pool).
+    /// 14. `[]` The Associated Token Program account.
+    ///
+    /// With a non zero `client_ref` the stream account is the program address derived from the
+    /// treasury and `client_ref` (not a signer), so retrying the same creation fails instead of
+    /// creating a duplicate stream.
     CreateStream {
         beneficiary_address: Pubkey,
         stream_name: String,        
@@ -33,7 +84,9 @@ pub enum StreamInstruction {
         rate_cliff_in_seconds: u64,
         cliff_vest_amount: f64, // OPTIONAL
         cliff_vest_percent: f64, // OPTIONAL
-        auto_pause_in_seconds: u64
+        auto_pause_in_seconds: u64,
+        funding_amount: f64, // OPTIONAL
+        client_ref: [u8; 16] // OPTIONAL (zeroes for none)
     },
 
     /// Adds a specific amount of funds to a stream
@@ -44,13 +97,13 @@ pub enum StreamInstruction {
     /// 3. `[]` The beneficiary mint account
     /// 4. `[]` The treasury account (Stream treasury account).
     /// 5. `[writable]` The treasury token account.    
-    /// 6. `[]` The treasury mint account (the mint of the treasury pool token)
+    /// 6. `[writable]` The treasury mint account (the mint of the treasury pool token)
     /// 7. `[writable]` The stream account (The stream contract account).
     /// 8.  [writable] The Money Streaming Program operating account (Fees account).
     /// 9.  [writable] The Money Streaming Program operating token account.
     /// 10.  [] The Money Streaming Program account.
-    /// 11. `[]` The Associated Token Program account.
-    /// 12. `[]` The Token Program account.
+    /// 11. `[]` The Token Program account (SPL Token or Token-2022).
+    /// 12. `[]` The Associated Token Program account.
     /// 13. `[]` The System Program account.
     /// 14. `[]` Rent sysvar account.
     AddFunds {
@@ -59,7 +112,8 @@ pub enum StreamInstruction {
         resume: bool
     },
 
-    /// Recovers a specific amount of funds from a previously funded stream
+    /// Recovers a specific amount of funds from a previously funded stream. The contributor can not
+    /// recover more than their share of the unvested funds (see `Stream::recoverable_amount`)
     ///
     /// 0. `[signer]` The contributor account
     /// 1. `[writable]` The contributor token account
@@ -72,7 +126,7 @@ pub enum StreamInstruction {
     /// 8.  [writable] The Money Streaming Program operating account (Fees account).
     /// 9.  [writable] The Money Streaming Program operating token account.
     /// 10.  [] The Money Streaming Program account.
-    /// 11. `[]` The Token Program account.
+    /// 11. `[]` The Token Program account (SPL Token or Token-2022, the treasury mint is burned with it too).
     RecoverFunds {
         recover_amount: f64
     },
@@ -86,7 +140,9 @@ pub enum StreamInstruction {
     /// 6.  [writable] The Money Streaming Program operating account (Fees account).
     /// 7.  [writable] The Money Streaming Program operating token account.
     /// 8. `[]` The Money Streaming Program account.
-    /// 9. `[]` The Token Program account.
+    /// 9. `[]` The Token Program account (SPL Token or Token-2022).
+    /// 10. `[]` The withdraw hook program account (only when the stream has a withdraw hook).
+    ///     It is invoked last, once the stream is saved, with the `withdraw_hook` instruction
     Withdraw { 
         withdrawal_amount: f64
     },
@@ -120,12 +176,16 @@ pub enum StreamInstruction {
         rate_cliff_in_seconds: u64,
         cliff_vest_amount: f64, // OPTIONAL
         cliff_vest_percent: f64, // OPTIONAL
-        auto_pause_in_seconds: u64
+        auto_pause_in_seconds: u64,
+        expiration_in_seconds: u64 // OPTIONAL (0 for PROPOSAL_EXPIRATION_IN_SECONDS)
     },
 
+    /// Approving copies the proposed terms into the stream. Either way the stream terms account is
+    /// closed and its rent goes back to the proposer
+    ///
     /// 0. `[signer]` The initializer of the transaction (treasurer or beneficiary)
     /// 1. `[writable]` The stream terms account (Update proposal account).
-    /// 2. `[]` The counterparty's account (if the initializer is the treasurer then it would be the beneficiary or vice versa)
+    /// 2. `[writable]` The counterparty's account (if the initializer is the treasurer then it would be the beneficiary or vice versa)
     /// 3. `[writable]` The stream account (The stream contract account). 
     /// 4.  [writable] The Money Streaming Program operating account (Fees account).
     /// 5. `[]` System Program account.
@@ -133,9 +193,12 @@ pub enum StreamInstruction {
         approve: bool
     },
 
+    /// Closes a stream: the vested and not withdrawn amount goes to the beneficiary, the unvested
+    /// amount back to the treasurer and the stream account rent to the initializer
+    ///
     /// 0. `[signer]` The initializer account (treasurer/beneficiary)
-    /// 1. `[writable]` The treasurer account (the creator of the treasury)
-    /// 2. `[writable]` The beneficiary token account (the recipient of the money)
+    /// 1. `[writable]` The treasurer token account (the recipient of the unvested funds)
+    /// 2. `[writable]` The beneficiary token account (the recipient of the vested funds)
     /// 3. `[]` The beneficiary token mint account
     /// 4. `[writable]` The treasury account
     /// 5. `[writable]` The treasury token account
@@ -143,24 +206,26 @@ pub enum StreamInstruction {
     /// 7. `[writable]` The Money Streaming Program operating account (Fees account).
     /// 8. `[writable]` The Money Streaming Program operating token account.
     /// 9. `[writable]` The Money Streaming Program account
-    /// 10. `[]` The Token Program account.
+    /// 10. `[]` The Token Program account (SPL Token or Token-2022).
     /// 11. `[]` System Program account.
     CloseStream,
 
     /// 0. `[signer]` The treasurer account (the creator of the treasury)
     /// 1. `[writable]` The treasury account
     /// 2. `[writable]` The treasury token account (The token account of the treasury which the funds are going to be payed for)
-    /// 3. `[writable]` The treasury token mint account (The mint account of the treasury token which the funds are going to be payed for).
-    /// 4. `[writable]` The treasury mint account (The mint account of the treasury pool token issued by the treasury).
-    /// 5. `[writable]` The Money Streaming Program operating ccount (Fees account).
-    /// 6. `[writable]` The Money Streaming Protocol operating token account.
-    /// 7. `[]` The Associated Token Program account.
-    /// 8. `[]` The Token Program account.    
+    /// 3. `[]` The treasury token mint account (The mint account of the treasury token which the funds are going to be payed for).
+    /// 4. `[writable]` The treasury mint account (The mint account of the treasury pool token issued by the treasury, default key for no pool token).
+    /// 5. `[]` The Money Streaming Program account.
+    /// 6. `[writable]` The Money Streaming Program operating account (Fees account).
+    /// 7. `[]` The Token Program account (SPL Token or Token-2022, the treasury mint is created with it too).
+    /// 8. `[]` The Associated Token Program account.
     /// 9. `[]` System Program account.
     /// 10. `[]` SysvarRent account.
     CreateTreasury {
         treasury_block_height: u64,
-        treasury_base_address: Pubkey
+        treasury_base_address: Pubkey, // the treasurer
+        funding_increment: u64, // OPTIONAL (base units, 0 for no restriction)
+        max_streams: u64 // OPTIONAL (0 for unlimited)
     },
 
     /// Transfers a specific amount of tokens between 2 accounts
@@ -174,6 +239,200 @@ pub enum StreamInstruction {
     /// 6. `[]` The Token Program account.
     Transfer {
         amount: f64
+    },
+
+    /// Sets the token account the beneficiary wants to receive the withdrawals in
+    ///
+    /// 0. `[signer]` The beneficiary account
+    /// 1. `[]` The beneficiary token account (owned by the beneficiary and holding the stream mint)
+    /// 2. `[writable]` The stream account (The stream contract account).
+    /// 3. `[]` The Money Streaming Program operating account.
+    SetBeneficiaryTokenAccount {
+        token_account: Pubkey
+    },
+
+    /// Transfers the treasurer role of a stream to a new account
+    ///
+    /// 0. `[signer]` The current treasurer account
+    /// 1. `[writable]` The stream account (The stream contract account).
+    /// 2. `[]` The Money Streaming Program operating account.
+    TransferStreamOwnership {
+        new_treasurer: Pubkey
+    },
+
+    /// Closes a stream terms proposal nobody answered before it expired (`expiration_in_seconds` after it
+    /// was proposed), refunding the rent to the proposer
+    ///
+    /// 0. `[writable]` The stream terms account
+    /// 1. `[writable]` The proposer account (The account that proposed the stream terms).
+    /// 2. `[]` The Money Streaming Program operating account.
+    ExpireProposal,
+
+    /// Sets the program version (`[major, minor, patch]`) as the instruction return data
+    ///
+    /// 0. `[]` The Money Streaming Program operating account.
+    GetVersion,
+
+    /// Closes a treasury with no streams left and an empty token account, refunding the rent to the treasurer.
+    /// Treasuries of the legacy layout do not count their streams and can not be closed
+    ///
+    /// 0. `[signer]` The treasurer account (the base address of the treasury)
+    /// 1. `[writable]` The treasury account
+    /// 2. `[]` The treasury token account (SPL Token or Token-2022)
+    /// 3. `[]` The Money Streaming Program operating account.
+    CloseTreasury,
+
+    /// Sets the seconds of streaming left below which the stream auto-pauses (0 disables auto-pause), the funds
+    /// left have to cover one such window. A stream paused for low funds is resumed if it would not be auto-paused
+    /// again under the new threshold
+    ///
+    /// 0. `[signer]` The treasurer account
+    /// 1. `[writable]` The stream account (The stream contract account).
+    /// 2. `[]` The Money Streaming Program operating account.
+    SetAutoPauseThreshold {
+        seconds: u64
+    },
+
+    /// Closes a stream and moves its remaining funds (unvested and vested not withdrawn yet)
+    /// into a new stream with the given terms
+    ///
+    /// 0. `[signer]` The treasurer account (The creator of both streams).
+    /// 1. `[writable]` The stream account being renewed.
+    /// 2. `[writable, signer]` The new stream account.
+    /// 3. `[]` The treasury account (shared by both streams).
+    /// 4. `[writable]` The Money Streaming Program operating account (Fees account).
+    /// 5. `[]` The Money Streaming Program account.
+    /// 6. `[]` The System Program account.
+    /// 7. `[]` Rent sysvar account.
+    /// 8. `[signer]` The current beneficiary, required when vested funds not withdrawn yet are carried over.
+    RenewStream {
+        beneficiary_address: Pubkey,
+        stream_name: String,
+        rate_amount: f64,
+        rate_interval_in_seconds: u64,
+        start_utc: u64,
+        rate_cliff_in_seconds: u64,
+        cliff_vest_amount: f64, // OPTIONAL
+        cliff_vest_percent: f64, // OPTIONAL
+        auto_pause_in_seconds: u64
+    },
+
+    /// Withdraws everything vested and not withdrawn yet at execution time
+    ///
+    /// Takes the same accounts as `Withdraw`
+    WithdrawMax,
+
+    /// Sets the program invoked after every withdrawal from the stream (`None` removes it).
+    /// See `withdraw_hook` for the instruction the hook program receives
+    ///
+    /// 0. `[signer]` The beneficiary account
+    /// 1. `[writable]` The stream account (The stream contract account).
+    /// 2. `[]` The Money Streaming Program operating account.
+    /// 3. `[]` The hook program account (only when setting a hook).
+    SetWithdrawHook {
+        hook_program: Option<Pubkey>
+    },
+
+    /// Renames a stream, the treasurer does not need the beneficiary approval for it
+    ///
+    /// 0. `[signer]` The treasurer account
+    /// 1. `[writable]` The stream account (The stream contract account).
+    /// 2. `[]` The Money Streaming Program operating account.
+    RenameStream {
+        stream_name: String
+    },
+
+    /// Withdraws everything withdrawable from `count` streams of the same beneficiary and mint
+    /// (up to `MAX_MULTI_WITHDRAW_STREAMS`). Streams with a withdraw hook are rejected, they need `Withdraw`
+    ///
+    /// 0. `[signer]` The beneficiary account
+    /// 1. `[writable]` The beneficiary token account (the recipient of the money)
+    /// 2. `[]` The beneficiary token mint account
+    /// 3.  [writable] The Money Streaming Program operating account (Fees account).
+    /// 4.  [writable] The Money Streaming Program operating token account.
+    /// 5. `[]` The Money Streaming Program account.
+    /// 6. `[]` The Token Program account (SPL Token or Token-2022).
+    ///
+    /// Followed by a group of accounts per stream:
+    /// 0. `[]` The treasury account
+    /// 1. `[writable]` The treasury token account
+    /// 2. `[writable]` The stream account (The stream contract account).
+    MultiWithdraw {
+        count: u8
+    },
+
+    /// Hands a stream over to a new treasurer and a new beneficiary at once. Clears the
+    /// beneficiary token account and the withdraw hook set by the previous beneficiary
+    ///
+    /// 0. `[signer]` The treasurer account
+    /// 1. `[signer]` The beneficiary account
+    /// 2. `[writable]` The stream account (The stream contract account).
+    /// 3. `[]` The Money Streaming Program operating account.
+    DonateStream {
+        new_treasurer: Pubkey,
+        new_beneficiary: Pubkey
+    },
+
+    /// Adds `amount` (base units) to a stream resuming it, and requires the resulting estimated
+    /// depletion date to be at least `min_new_depletion_utc`
+    ///
+    /// Takes the same accounts as `AddFunds`
+    TopUp {
+        amount: u64,
+        min_new_depletion_utc: u64
+    }
+}
+
+impl fmt::Display for StreamInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::CreateStream { stream_name, rate_amount, rate_interval_in_seconds, funding_amount, .. } => write!(
+                f, "CreateStream{{name: {}, rate_amount: {}, interval: {}, funding_amount: {}}}",
+                stream_name, rate_amount, rate_interval_in_seconds, funding_amount
+            ),
+            Self::AddFunds { contribution_amount, resume, .. } => write!(
+                f, "AddFunds{{contribution_amount: {}, resume: {}}}", contribution_amount, resume
+            ),
+            Self::RecoverFunds { recover_amount } => write!(f, "RecoverFunds{{recover_amount: {}}}", recover_amount),
+            Self::Withdraw { withdrawal_amount } => write!(f, "Withdraw{{withdrawal_amount: {}}}", withdrawal_amount),
+            Self::PauseStream => write!(f, "PauseStream"),
+            Self::ResumeStream => write!(f, "ResumeStream"),
+            Self::ProposeUpdate { proposed_by, stream_name, rate_amount, rate_interval_in_seconds, .. } => write!(
+                f, "ProposeUpdate{{proposed_by: {}, name: {}, rate_amount: {}, interval: {}}}",
+                proposed_by, stream_name, rate_amount, rate_interval_in_seconds
+            ),
+            Self::AnswerUpdate { approve } => write!(f, "AnswerUpdate{{approve: {}}}", approve),
+            Self::CloseStream => write!(f, "CloseStream"),
+            Self::CreateTreasury { treasury_block_height, treasury_base_address, funding_increment, max_streams } => write!(
+                f, "CreateTreasury{{block_height: {}, base_address: {}, funding_increment: {}, max_streams: {}}}",
+                treasury_block_height, treasury_base_address, funding_increment, max_streams
+            ),
+            Self::Transfer { amount } => write!(f, "Transfer{{amount: {}}}", amount),
+            Self::SetBeneficiaryTokenAccount { token_account } => write!(
+                f, "SetBeneficiaryTokenAccount{{token_account: {}}}", token_account
+            ),
+            Self::TransferStreamOwnership { new_treasurer } => write!(
+                f, "TransferStreamOwnership{{new_treasurer: {}}}", new_treasurer
+            ),
+            Self::ExpireProposal => write!(f, "ExpireProposal"),
+            Self::GetVersion => write!(f, "GetVersion"),
+            Self::CloseTreasury => write!(f, "CloseTreasury"),
+            Self::SetAutoPauseThreshold { seconds } => write!(f, "SetAutoPauseThreshold{{seconds: {}}}", seconds),
+            Self::RenewStream { stream_name, rate_amount, rate_interval_in_seconds, .. } => write!(
+                f, "RenewStream{{name: {}, rate_amount: {}, interval: {}}}",
+                stream_name, rate_amount, rate_interval_in_seconds
+            ),
+            Self::WithdrawMax => write!(f, "WithdrawMax"),
+            Self::SetWithdrawHook { hook_program } => write!(f, "SetWithdrawHook{{hook_program: {:?}}}", hook_program),
+            Self::RenameStream { stream_name } => write!(f, "RenameStream{{name: {}}}", stream_name),
+            Self::MultiWithdraw { count } => write!(f, "MultiWithdraw{{count: {}}}", count),
+            Self::DonateStream { new_treasurer, new_beneficiary } => write!(
+                f, "DonateStream{{new_treasurer: {}, new_beneficiary: {}}}", new_treasurer, new_beneficiary
+            ),
+            Self::TopUp { amount, min_new_depletion_utc } => write!(
+                f, "TopUp{{amount: {}, min_new_depletion_utc: {}}}", amount, min_new_depletion_utc
+            )
+        }
     }
 }
 
@@ -183,28 +442,135 @@ impl StreamInstruction {
 
         let (&tag, result) = instruction_data
             .split_first()
-            .ok_or(StreamError::InvalidStreamInstruction.into())?;
+            .ok_or(StreamError::InvalidStreamInstruction)?;
                 
         Ok(match tag {
 
-            0 => Self::unpack_create_stream(result)?,
-            1 => Self::unpack_add_funds(result)?,
-            2 => Self::unpack_recover_funds(result)?,
-            3 => Self::unpack_withdraw(result)?,
-            4 => Ok(Self::PauseStream)?,
-            5 => Ok(Self::ResumeStream)?,
-            6 => Self::unpack_propose_update(result)?,
-            7 => Self::unpack_answer_update(result)?,
-            8 => Ok(Self::CloseStream)?,
-            9 => Self::unpack_create_treasury(result)?,
-            10 => Self::unpack_transfer(result)?,
-
-            _ => return Err(StreamError::InvalidStreamInstruction.into()),
+            TAG_CREATE_STREAM => Self::unpack_create_stream(result)?,
+            TAG_ADD_FUNDS => Self::unpack_add_funds(result)?,
+            TAG_RECOVER_FUNDS => Self::unpack_recover_funds(result)?,
+            TAG_WITHDRAW => Self::unpack_withdraw(result)?,
+            TAG_PAUSE_STREAM => Self::PauseStream,
+            TAG_RESUME_STREAM => Self::ResumeStream,
+            TAG_PROPOSE_UPDATE => Self::unpack_propose_update(result)?,
+            TAG_ANSWER_UPDATE => Self::unpack_answer_update(result)?,
+            TAG_CLOSE_STREAM => Self::CloseStream,
+            TAG_CREATE_TREASURY => Self::unpack_create_treasury(result)?,
+            TAG_TRANSFER => Self::unpack_transfer(result)?,
+            TAG_TRANSFER_STREAM_OWNERSHIP => Self::unpack_transfer_stream_ownership(result)?,
+            TAG_CLOSE_TREASURY => Self::CloseTreasury,
+            TAG_SET_AUTO_PAUSE_THRESHOLD => Self::unpack_set_auto_pause_threshold(result)?,
+            TAG_WITHDRAW_MAX => Self::WithdrawMax,
+            TAG_RENAME_STREAM => Self::unpack_rename_stream(result)?,
+            TAG_MULTI_WITHDRAW => Self::unpack_multi_withdraw(result)?,
+            TAG_TOP_UP => Self::unpack_top_up(result)?,
+            TAG_SET_BENEFICIARY_TOKEN_ACCOUNT => Self::unpack_set_beneficiary_token_account(result)?,
+            TAG_EXPIRE_PROPOSAL => Self::ExpireProposal,
+            TAG_GET_VERSION => Self::GetVersion,
+            TAG_RENEW_STREAM => Self::unpack_renew_stream(result)?,
+            TAG_DONATE_STREAM => Self::unpack_donate_stream(result)?,
+            TAG_SET_WITHDRAW_HOOK => Self::unpack_set_withdraw_hook(result)?,
+
+            _ => return Err(StreamError::UnknownInstructionTag),
         })
     }
 
+    /// Like `unpack` but rejects any trailing bytes left after the instruction payload
+    pub fn unpack_strict(instruction_data: &[u8]) -> Result<Self, StreamError> {
+
+        let instruction = Self::unpack(instruction_data)?;
+
+        if instruction.packed_len() != instruction_data.len() {
+            return Err(StreamError::InvalidStreamInstruction);
+        }
+
+        Ok(instruction)
+    }
+
+    /// The tag byte `pack` writes for this instruction
+    pub fn tag(&self) -> u8 {
+        match self {
+            Self::CreateStream { .. } => TAG_CREATE_STREAM,
+            Self::AddFunds { .. } => TAG_ADD_FUNDS,
+            Self::RecoverFunds { .. } => TAG_RECOVER_FUNDS,
+            Self::Withdraw { .. } => TAG_WITHDRAW,
+            Self::PauseStream => TAG_PAUSE_STREAM,
+            Self::ResumeStream => TAG_RESUME_STREAM,
+            Self::ProposeUpdate { .. } => TAG_PROPOSE_UPDATE,
+            Self::AnswerUpdate { .. } => TAG_ANSWER_UPDATE,
+            Self::CloseStream => TAG_CLOSE_STREAM,
+            Self::CreateTreasury { .. } => TAG_CREATE_TREASURY,
+            Self::Transfer { .. } => TAG_TRANSFER,
+            Self::TransferStreamOwnership { .. } => TAG_TRANSFER_STREAM_OWNERSHIP,
+            Self::CloseTreasury => TAG_CLOSE_TREASURY,
+            Self::SetAutoPauseThreshold { .. } => TAG_SET_AUTO_PAUSE_THRESHOLD,
+            Self::WithdrawMax => TAG_WITHDRAW_MAX,
+            Self::RenameStream { .. } => TAG_RENAME_STREAM,
+            Self::MultiWithdraw { .. } => TAG_MULTI_WITHDRAW,
+            Self::TopUp { .. } => TAG_TOP_UP,
+            Self::SetBeneficiaryTokenAccount { .. } => TAG_SET_BENEFICIARY_TOKEN_ACCOUNT,
+            Self::ExpireProposal => TAG_EXPIRE_PROPOSAL,
+            Self::GetVersion => TAG_GET_VERSION,
+            Self::RenewStream { .. } => TAG_RENEW_STREAM,
+            Self::DonateStream { .. } => TAG_DONATE_STREAM,
+            Self::SetWithdrawHook { .. } => TAG_SET_WITHDRAW_HOOK
+        }
+    }
+
+    // Packed lengths (tag included). For the variants with a stream name it is the length
+    // without the name bytes, the name being packed as a u32 length followed by its bytes
+    pub const CREATE_STREAM_LEN: usize = 1 + 32 + 4 + 8 * 8 + 16;
+    pub const ADD_FUNDS_LEN: usize = 1 + 8 + 8 + 1;
+    pub const RECOVER_FUNDS_LEN: usize = 1 + 8;
+    pub const WITHDRAW_LEN: usize = 1 + 8;
+    pub const PROPOSE_UPDATE_LEN: usize = 1 + 32 + 4 + 32 * 3 + 8 * 7;
+    pub const ANSWER_UPDATE_LEN: usize = 1 + 1;
+    pub const CREATE_TREASURY_LEN: usize = 1 + 8 + 32 + 8 + 8;
+    pub const TRANSFER_LEN: usize = 1 + 8;
+    pub const SET_BENEFICIARY_TOKEN_ACCOUNT_LEN: usize = 1 + 32;
+    pub const TRANSFER_STREAM_OWNERSHIP_LEN: usize = 1 + 32;
+    pub const SET_AUTO_PAUSE_THRESHOLD_LEN: usize = 1 + 8;
+    pub const RENEW_STREAM_LEN: usize = 1 + 32 + 4 + 8 * 7;
+    pub const SET_WITHDRAW_HOOK_LEN: usize = 1 + 1; // plus 32 bytes when setting a hook
+    pub const RENAME_STREAM_LEN: usize = 1 + 4;
+    pub const MULTI_WITHDRAW_LEN: usize = 1 + 1;
+    pub const DONATE_STREAM_LEN: usize = 1 + 32 + 32;
+    pub const TOP_UP_LEN: usize = 1 + 8 + 8;
+    pub const NO_ARGS_LEN: usize = 1;
+
+    /// Length of the data `pack` writes for this instruction
+    pub fn packed_len(&self) -> usize {
+        match self {
+            Self::CreateStream { stream_name, .. } => Self::CREATE_STREAM_LEN + stream_name.len(),
+            Self::AddFunds { .. } => Self::ADD_FUNDS_LEN,
+            Self::RecoverFunds { .. } => Self::RECOVER_FUNDS_LEN,
+            Self::Withdraw { .. } => Self::WITHDRAW_LEN,
+            Self::ProposeUpdate { stream_name, .. } => Self::PROPOSE_UPDATE_LEN + stream_name.len(),
+            Self::AnswerUpdate { .. } => Self::ANSWER_UPDATE_LEN,
+            Self::CreateTreasury { .. } => Self::CREATE_TREASURY_LEN,
+            Self::Transfer { .. } => Self::TRANSFER_LEN,
+            Self::SetBeneficiaryTokenAccount { .. } => Self::SET_BENEFICIARY_TOKEN_ACCOUNT_LEN,
+            Self::TransferStreamOwnership { .. } => Self::TRANSFER_STREAM_OWNERSHIP_LEN,
+            Self::SetAutoPauseThreshold { .. } => Self::SET_AUTO_PAUSE_THRESHOLD_LEN,
+            Self::RenewStream { stream_name, .. } => Self::RENEW_STREAM_LEN + stream_name.len(),
+            Self::SetWithdrawHook { hook_program: Some(_) } => Self::SET_WITHDRAW_HOOK_LEN + 32,
+            Self::SetWithdrawHook { hook_program: None } => Self::SET_WITHDRAW_HOOK_LEN,
+            Self::RenameStream { stream_name } => Self::RENAME_STREAM_LEN + stream_name.len(),
+            Self::MultiWithdraw { .. } => Self::MULTI_WITHDRAW_LEN,
+            Self::DonateStream { .. } => Self::DONATE_STREAM_LEN,
+            Self::TopUp { .. } => Self::TOP_UP_LEN,
+            Self::PauseStream |
+            Self::ResumeStream |
+            Self::CloseStream |
+            Self::ExpireProposal |
+            Self::GetVersion |
+            Self::CloseTreasury |
+            Self::WithdrawMax => Self::NO_ARGS_LEN
+        }
+    }
+
     pub fn pack(&self) -> Vec<u8> {
-        let mut buf = Vec::with_capacity(size_of::<Self>());
+        let mut buf = Vec::with_capacity(self.packed_len());
 
         match self {
 
@@ -217,13 +583,16 @@ impl StreamInstruction {
                 rate_cliff_in_seconds,
                 cliff_vest_amount,
                 cliff_vest_percent,
-                auto_pause_in_seconds
+                auto_pause_in_seconds,
+                funding_amount,
+                client_ref
 
             } => {
 
-                buf.push(0);
+                buf.push(TAG_CREATE_STREAM);
 
                 buf.extend_from_slice(beneficiary_address.as_ref());
+                buf.extend_from_slice(&(stream_name.len() as u32).to_le_bytes());
                 buf.extend_from_slice(stream_name.as_ref());
                 buf.extend_from_slice(&rate_amount.to_le_bytes());
                 buf.extend_from_slice(&rate_interval_in_seconds.to_le_bytes());
@@ -231,7 +600,9 @@ impl StreamInstruction {
                 buf.extend_from_slice(&rate_cliff_in_seconds.to_le_bytes());
                 buf.extend_from_slice(&cliff_vest_amount.to_le_bytes());
                 buf.extend_from_slice(&cliff_vest_percent.to_le_bytes());
-                buf.extend_from_slice(&auto_pause_in_seconds.to_le_bytes());               
+                buf.extend_from_slice(&auto_pause_in_seconds.to_le_bytes());
+                buf.extend_from_slice(&funding_amount.to_le_bytes());
+                buf.extend_from_slice(client_ref);
             },
 
             &Self::AddFunds { 
@@ -240,7 +611,7 @@ impl StreamInstruction {
                 resume
 
             } => {
-                buf.push(1);
+                buf.push(TAG_ADD_FUNDS);
 
                 buf.extend_from_slice(&contribution_amount.to_le_bytes());
                 buf.extend_from_slice(&funded_on_utc.to_le_bytes());
@@ -254,18 +625,18 @@ impl StreamInstruction {
             },
 
             &Self::RecoverFunds { recover_amount } => {
-                buf.push(2);
+                buf.push(TAG_RECOVER_FUNDS);
                 buf.extend_from_slice(&recover_amount.to_le_bytes());
             },
 
             &Self::Withdraw { withdrawal_amount } => {
-                buf.push(3);
+                buf.push(TAG_WITHDRAW);
                 buf.extend_from_slice(&withdrawal_amount.to_le_bytes());
             },
 
-            &Self::PauseStream => buf.push(4),
+            &Self::PauseStream => buf.push(TAG_PAUSE_STREAM),
 
-            &Self::ResumeStream => buf.push(5),
+            &Self::ResumeStream => buf.push(TAG_RESUME_STREAM),
 
             Self::ProposeUpdate {
                 proposed_by,
@@ -278,12 +649,14 @@ impl StreamInstruction {
                 rate_cliff_in_seconds,
                 cliff_vest_amount,
                 cliff_vest_percent,
-                auto_pause_in_seconds
+                auto_pause_in_seconds,
+                expiration_in_seconds
 
             } => {
-                buf.push(6);
+                buf.push(TAG_PROPOSE_UPDATE);
 
                 buf.extend_from_slice(proposed_by.as_ref());
+                buf.extend_from_slice(&(stream_name.len() as u32).to_le_bytes());
                 buf.extend_from_slice(stream_name.as_ref());
                 buf.extend_from_slice(treasurer_address.as_ref());
                 buf.extend_from_slice(beneficiary_address.as_ref());
@@ -293,11 +666,12 @@ impl StreamInstruction {
                 buf.extend_from_slice(&rate_cliff_in_seconds.to_le_bytes());
                 buf.extend_from_slice(&cliff_vest_amount.to_le_bytes());
                 buf.extend_from_slice(&cliff_vest_percent.to_le_bytes());
-                buf.extend_from_slice(&auto_pause_in_seconds.to_le_bytes());                
+                buf.extend_from_slice(&auto_pause_in_seconds.to_le_bytes());
+                buf.extend_from_slice(&expiration_in_seconds.to_le_bytes());
             },
 
             &Self::AnswerUpdate { approve } => { 
-                buf.push(7);
+                buf.push(TAG_ANSWER_UPDATE);
 
                 let approve = match approve {
                     false => [0],
@@ -307,22 +681,111 @@ impl StreamInstruction {
                 buf.push(approve[0] as u8);
             },
 
-            Self::CloseStream => buf.push(8),
+            Self::CloseStream => buf.push(TAG_CLOSE_STREAM),
             
             Self::CreateTreasury {
                 treasury_block_height,
-                treasury_base_address
+                treasury_base_address,
+                funding_increment,
+                max_streams
 
             } => {
-                buf.push(9);
+                buf.push(TAG_CREATE_TREASURY);
 
                 buf.extend_from_slice(&treasury_block_height.to_le_bytes());
                 buf.extend_from_slice(treasury_base_address.as_ref());
+                buf.extend_from_slice(&funding_increment.to_le_bytes());
+                buf.extend_from_slice(&max_streams.to_le_bytes());
             },
 
             &Self::Transfer { amount } => {
-                buf.push(10);
+                buf.push(TAG_TRANSFER);
+                buf.extend_from_slice(&amount.to_le_bytes());
+            },
+
+            Self::SetBeneficiaryTokenAccount { token_account } => {
+                buf.push(TAG_SET_BENEFICIARY_TOKEN_ACCOUNT);
+                buf.extend_from_slice(token_account.as_ref());
+            },
+
+            Self::TransferStreamOwnership { new_treasurer } => {
+                buf.push(TAG_TRANSFER_STREAM_OWNERSHIP);
+                buf.extend_from_slice(new_treasurer.as_ref());
+            },
+
+            Self::ExpireProposal => buf.push(TAG_EXPIRE_PROPOSAL),
+
+            Self::GetVersion => buf.push(TAG_GET_VERSION),
+
+            Self::CloseTreasury => buf.push(TAG_CLOSE_TREASURY),
+
+            Self::SetAutoPauseThreshold { seconds } => {
+                buf.push(TAG_SET_AUTO_PAUSE_THRESHOLD);
+                buf.extend_from_slice(&seconds.to_le_bytes());
+            },
+
+            Self::RenewStream {
+                beneficiary_address,
+                stream_name,
+                rate_amount,
+                rate_interval_in_seconds,
+                start_utc,
+                rate_cliff_in_seconds,
+                cliff_vest_amount,
+                cliff_vest_percent,
+                auto_pause_in_seconds
+
+            } => {
+
+                buf.push(TAG_RENEW_STREAM);
+
+                buf.extend_from_slice(beneficiary_address.as_ref());
+                buf.extend_from_slice(&(stream_name.len() as u32).to_le_bytes());
+                buf.extend_from_slice(stream_name.as_ref());
+                buf.extend_from_slice(&rate_amount.to_le_bytes());
+                buf.extend_from_slice(&rate_interval_in_seconds.to_le_bytes());
+                buf.extend_from_slice(&start_utc.to_le_bytes());
+                buf.extend_from_slice(&rate_cliff_in_seconds.to_le_bytes());
+                buf.extend_from_slice(&cliff_vest_amount.to_le_bytes());
+                buf.extend_from_slice(&cliff_vest_percent.to_le_bytes());
+                buf.extend_from_slice(&auto_pause_in_seconds.to_le_bytes());
+            },
+
+            Self::WithdrawMax => buf.push(TAG_WITHDRAW_MAX),
+
+            Self::SetWithdrawHook { hook_program } => {
+                buf.push(TAG_SET_WITHDRAW_HOOK);
+
+                match hook_program {
+                    Some(hook_program) => {
+                        buf.push(1);
+                        buf.extend_from_slice(hook_program.as_ref());
+                    },
+                    None => buf.push(0)
+                }
+            },
+
+            Self::RenameStream { stream_name } => {
+                buf.push(TAG_RENAME_STREAM);
+                buf.extend_from_slice(&(stream_name.len() as u32).to_le_bytes());
+                buf.extend_from_slice(stream_name.as_ref());
+            },
+
+            &Self::MultiWithdraw { count } => {
+                buf.push(TAG_MULTI_WITHDRAW);
+                buf.push(count);
+            },
+
+            Self::DonateStream { new_treasurer, new_beneficiary } => {
+                buf.push(TAG_DONATE_STREAM);
+                buf.extend_from_slice(new_treasurer.as_ref());
+                buf.extend_from_slice(new_beneficiary.as_ref());
+            },
+
+            &Self::TopUp { amount, min_new_depletion_utc } => {
+                buf.push(TAG_TOP_UP);
                 buf.extend_from_slice(&amount.to_le_bytes());
+                buf.extend_from_slice(&min_new_depletion_utc.to_le_bytes());
             }
         };
 
@@ -334,27 +797,43 @@ impl StreamInstruction {
         let (beneficiary_address, result) = Self::unpack_pubkey(input)?;
         let (stream_name, result) = Self::unpack_string(result)?;
 
-        let (rate_amount, result) = result.split_at(8);
+        let (rate_amount, result) = Self::take_bytes(result, 8)?;
         let rate_amount = Self::unpack_f64(rate_amount)?;
 
-        let (rate_interval_in_seconds, result) = result.split_at(8);
+        let (rate_interval_in_seconds, result) = Self::take_bytes(result, 8)?;
         let rate_interval_in_seconds = Self::unpack_u64(rate_interval_in_seconds)?;
 
-        let (start_utc, result) = result.split_at(8);
+        if rate_interval_in_seconds == 0 {
+            return Err(StreamError::InvalidRateInterval.into());
+        }
+
+        let (start_utc, result) = Self::take_bytes(result, 8)?;
         let start_utc = Self::unpack_u64(start_utc)?;
 
-        let (rate_cliff_in_seconds, result) = result.split_at(8);
+        let (rate_cliff_in_seconds, result) = Self::take_bytes(result, 8)?;
         let rate_cliff_in_seconds = Self::unpack_u64(rate_cliff_in_seconds)?;
 
-        let (cliff_vest_amount, result) = result.split_at(8);
+        let (cliff_vest_amount, result) = Self::take_bytes(result, 8)?;
         let cliff_vest_amount = Self::unpack_f64(cliff_vest_amount)?;
 
-        let (cliff_vest_percent, result) = result.split_at(8);
+        let (cliff_vest_percent, result) = Self::take_bytes(result, 8)?;
         let cliff_vest_percent = Self::unpack_f64(cliff_vest_percent)?;
 
-        let (auto_pause_in_seconds, _result) = result.split_at(8);
+        if !(0.0..=100.0).contains(&cliff_vest_percent) {
+            return Err(StreamError::InvalidCliffPercent.into());
+        }
+
+        let (auto_pause_in_seconds, result) = Self::take_bytes(result, 8)?;
         let auto_pause_in_seconds = Self::unpack_u64(auto_pause_in_seconds)?;
 
+        let (funding_amount, result) = Self::take_bytes(result, 8)?;
+        let funding_amount = Self::unpack_f64(funding_amount)?;
+
+        let (client_ref, _result) = Self::take_bytes(result, 16)?;
+        let client_ref: [u8; 16] = client_ref
+            .try_into()
+            .map_err(|_| StreamError::InvalidStreamInstruction)?;
+
         Ok(Self::CreateStream {
             beneficiary_address,
             stream_name,
@@ -364,17 +843,19 @@ impl StreamInstruction {
             rate_cliff_in_seconds,
             cliff_vest_amount,
             cliff_vest_percent,
-            auto_pause_in_seconds
+            auto_pause_in_seconds,
+            funding_amount,
+            client_ref
         })
     }
 
     fn unpack_add_funds(input: &[u8]) -> Result<Self, StreamError> {
-        let (contribution_amount, result) = input.split_at(8);
+        let (contribution_amount, result) = Self::take_bytes(input, 8)?;
         let contribution_amount = Self::unpack_f64(contribution_amount)?;
-        let (funded_on_utc, result) = result.split_at(8);
+        let (funded_on_utc, result) = Self::take_bytes(result, 8)?;
         let funded_on_utc = Self::unpack_u64(funded_on_utc)?;
 
-        let (resume, _result) = result.split_at(1);
+        let (resume, _result) = Self::take_bytes(result, 1)?;
         let resume = match resume {
             [0] => false,
             [1] => true,
@@ -389,14 +870,14 @@ impl StreamInstruction {
     }
 
     fn unpack_recover_funds(input: &[u8]) -> Result<Self, StreamError> {
-        let (recover_amount, _result) = input.split_at(8);
+        let (recover_amount, _result) = Self::take_bytes(input, 8)?;
         let recover_amount = Self::unpack_f64(recover_amount)?;
 
         Ok(Self::RecoverFunds { recover_amount })
     }
 
     fn unpack_withdraw(input: &[u8]) -> Result<Self, StreamError> {
-        let (withdrawal_amount, _result) = input.split_at(8);
+        let (withdrawal_amount, _result) = Self::take_bytes(input, 8)?;
         let withdrawal_amount = Self::unpack_f64(withdrawal_amount)?;
 
         Ok(Self::Withdraw { withdrawal_amount })
@@ -409,24 +890,35 @@ impl StreamInstruction {
         let (beneficiary_address, result) = Self::unpack_pubkey(result)?;
         let (associated_token_address, result) = Self::unpack_pubkey(result)?;
 
-        let (rate_amount, result) = result.split_at(8);
+        let (rate_amount, result) = Self::take_bytes(result, 8)?;
         let rate_amount = Self::unpack_f64(rate_amount)?;
 
-        let (rate_interval_in_seconds, result) = result.split_at(8);
+        let (rate_interval_in_seconds, result) = Self::take_bytes(result, 8)?;
         let rate_interval_in_seconds = Self::unpack_u64(rate_interval_in_seconds)?;
 
-        let (rate_cliff_in_seconds, result) = result.split_at(8);
+        if rate_interval_in_seconds == 0 {
+            return Err(StreamError::InvalidRateInterval.into());
+        }
+
+        let (rate_cliff_in_seconds, result) = Self::take_bytes(result, 8)?;
         let rate_cliff_in_seconds = Self::unpack_u64(rate_cliff_in_seconds)?;
 
-        let (cliff_vest_amount, result) = result.split_at(8);
+        let (cliff_vest_amount, result) = Self::take_bytes(result, 8)?;
         let cliff_vest_amount = Self::unpack_f64(cliff_vest_amount)?;
 
-        let (cliff_vest_percent, result) = result.split_at(8);
+        let (cliff_vest_percent, result) = Self::take_bytes(result, 8)?;
         let cliff_vest_percent = Self::unpack_f64(cliff_vest_percent)?;
 
-        let (auto_pause_in_seconds, _result) = result.split_at(8);
+        if !(0.0..=100.0).contains(&cliff_vest_percent) {
+            return Err(StreamError::InvalidCliffPercent.into());
+        }
+
+        let (auto_pause_in_seconds, result) = Self::take_bytes(result, 8)?;
         let auto_pause_in_seconds = Self::unpack_u64(auto_pause_in_seconds)?;        
 
+        let (expiration_in_seconds, _result) = Self::take_bytes(result, 8)?;
+        let expiration_in_seconds = Self::unpack_u64(expiration_in_seconds)?;
+
         Ok(Self::ProposeUpdate {
             proposed_by,
             stream_name,
@@ -438,12 +930,13 @@ impl StreamInstruction {
             rate_cliff_in_seconds,
             cliff_vest_amount,
             cliff_vest_percent,
-            auto_pause_in_seconds
+            auto_pause_in_seconds,
+            expiration_in_seconds
         })
     }
 
     fn unpack_answer_update(input: &[u8]) -> Result<Self, StreamError> {
-        let (approve, _result) = input.split_at(1);
+        let (approve, _result) = Self::take_bytes(input, 1)?;
         let approve = match approve {
             [0] => false,
             [1] => true,
@@ -455,25 +948,151 @@ impl StreamInstruction {
 
     fn unpack_create_treasury(input: &[u8]) -> Result<Self, StreamError> {
 
-        let (treasury_block_height, result) = input.split_at(8);
+        let (treasury_block_height, result) = Self::take_bytes(input, 8)?;
         let treasury_block_height = Self::unpack_u64(treasury_block_height)?;
 
-        let (treasury_base_address, _result) = Self::unpack_pubkey(result)?;
+        let (treasury_base_address, result) = Self::unpack_pubkey(result)?;
+
+        let (funding_increment, result) = Self::take_bytes(result, 8)?;
+        let funding_increment = Self::unpack_u64(funding_increment)?;
+
+        let (max_streams, _result) = Self::take_bytes(result, 8)?;
+        let max_streams = Self::unpack_u64(max_streams)?;
 
         Ok(Self::CreateTreasury { 
             treasury_block_height,
-            treasury_base_address
+            treasury_base_address,
+            funding_increment,
+            max_streams
         })
     }
 
     fn unpack_transfer(input: &[u8]) -> Result<Self, StreamError> {
 
-        let (amount, _result) = input.split_at(8);
+        let (amount, _result) = Self::take_bytes(input, 8)?;
         let amount = Self::unpack_f64(amount)?;
 
         Ok(Self::Transfer { amount })
     }
 
+    fn unpack_set_beneficiary_token_account(input: &[u8]) -> Result<Self, StreamError> {
+        let (token_account, _result) = Self::unpack_pubkey(input)?;
+
+        Ok(Self::SetBeneficiaryTokenAccount { token_account })
+    }
+
+    fn unpack_transfer_stream_ownership(input: &[u8]) -> Result<Self, StreamError> {
+        let (new_treasurer, _result) = Self::unpack_pubkey(input)?;
+
+        Ok(Self::TransferStreamOwnership { new_treasurer })
+    }
+
+    fn unpack_set_auto_pause_threshold(input: &[u8]) -> Result<Self, StreamError> {
+        let (seconds, _result) = Self::take_bytes(input, 8)?;
+        let seconds = Self::unpack_u64(seconds)?;
+
+        Ok(Self::SetAutoPauseThreshold { seconds })
+    }
+
+    fn unpack_renew_stream(input: &[u8]) -> Result<Self, StreamError> {
+
+        let (beneficiary_address, result) = Self::unpack_pubkey(input)?;
+        let (stream_name, result) = Self::unpack_string(result)?;
+
+        let (rate_amount, result) = Self::take_bytes(result, 8)?;
+        let rate_amount = Self::unpack_f64(rate_amount)?;
+
+        let (rate_interval_in_seconds, result) = Self::take_bytes(result, 8)?;
+        let rate_interval_in_seconds = Self::unpack_u64(rate_interval_in_seconds)?;
+
+        if rate_interval_in_seconds == 0 {
+            return Err(StreamError::InvalidRateInterval.into());
+        }
+
+        let (start_utc, result) = Self::take_bytes(result, 8)?;
+        let start_utc = Self::unpack_u64(start_utc)?;
+
+        let (rate_cliff_in_seconds, result) = Self::take_bytes(result, 8)?;
+        let rate_cliff_in_seconds = Self::unpack_u64(rate_cliff_in_seconds)?;
+
+        let (cliff_vest_amount, result) = Self::take_bytes(result, 8)?;
+        let cliff_vest_amount = Self::unpack_f64(cliff_vest_amount)?;
+
+        let (cliff_vest_percent, result) = Self::take_bytes(result, 8)?;
+        let cliff_vest_percent = Self::unpack_f64(cliff_vest_percent)?;
+
+        if !(0.0..=100.0).contains(&cliff_vest_percent) {
+            return Err(StreamError::InvalidCliffPercent.into());
+        }
+
+        let (auto_pause_in_seconds, _result) = Self::take_bytes(result, 8)?;
+        let auto_pause_in_seconds = Self::unpack_u64(auto_pause_in_seconds)?;
+
+        Ok(Self::RenewStream {
+            beneficiary_address,
+            stream_name,
+            rate_amount,
+            rate_interval_in_seconds,
+            start_utc,
+            rate_cliff_in_seconds,
+            cliff_vest_amount,
+            cliff_vest_percent,
+            auto_pause_in_seconds
+        })
+    }
+
+    fn unpack_set_withdraw_hook(input: &[u8]) -> Result<Self, StreamError> {
+        let (presence, result) = Self::take_bytes(input, 1)?;
+        let hook_program = match presence {
+            [0] => None,
+            [1] => Some(Self::unpack_pubkey(result)?.0),
+            _ => return Err(StreamError::InvalidStreamInstruction)
+        };
+
+        Ok(Self::SetWithdrawHook { hook_program })
+    }
+
+    fn unpack_rename_stream(input: &[u8]) -> Result<Self, StreamError> {
+        let (stream_name, _result) = Self::unpack_string(input)?;
+
+        Ok(Self::RenameStream { stream_name })
+    }
+
+    fn unpack_multi_withdraw(input: &[u8]) -> Result<Self, StreamError> {
+        let (count, _result) = Self::take_bytes(input, 1)?;
+
+        if count[0] == 0 || count[0] > MAX_MULTI_WITHDRAW_STREAMS {
+            return Err(StreamError::BatchTooLarge);
+        }
+
+        Ok(Self::MultiWithdraw { count: count[0] })
+    }
+
+    fn unpack_donate_stream(input: &[u8]) -> Result<Self, StreamError> {
+        let (new_treasurer, result) = Self::unpack_pubkey(input)?;
+        let (new_beneficiary, _result) = Self::unpack_pubkey(result)?;
+
+        Ok(Self::DonateStream { new_treasurer, new_beneficiary })
+    }
+
+    fn unpack_top_up(input: &[u8]) -> Result<Self, StreamError> {
+        let (amount, result) = Self::take_bytes(input, 8)?;
+        let amount = Self::unpack_u64(amount)?;
+
+        let (min_new_depletion_utc, _result) = Self::take_bytes(result, 8)?;
+        let min_new_depletion_utc = Self::unpack_u64(min_new_depletion_utc)?;
+
+        Ok(Self::TopUp { amount, min_new_depletion_utc })
+    }
+
+    fn take_bytes(input: &[u8], n: usize) -> Result<(&[u8], &[u8]), StreamError> {
+        if input.len() < n {
+            return Err(StreamError::TruncatedAmount);
+        }
+
+        Ok(input.split_at(n))
+    }
+
     fn unpack_pubkey(input: &[u8]) -> Result<(Pubkey, &[u8]), StreamError> {
         if input.len() >= 32 {
             let (key, rest) = input.split_at(32);
@@ -481,17 +1100,40 @@ impl StreamInstruction {
 
             Ok((pk, rest))
         } else {
-            Err(StreamError::InvalidArgument.into())
+            Err(StreamError::TruncatedPubkey.into())
         }
     }
 
     fn unpack_string(input: &[u8]) -> Result<(String, &[u8]), StreamError> {
-        if input.len() >= 32 {
-            let (bytes, rest) = input.split_at(32);
-            Ok((String::from_utf8_lossy(bytes).to_string(), rest))
-        } else {
-            Err(StreamError::InvalidArgument.into())
+        if input.len() < 4 {
+            return Err(StreamError::BadStringLength.into());
+        }
+
+        // Strings are encoded as a little-endian u32 length followed by the UTF-8 bytes
+        let (len, rest) = input.split_at(4);
+        let len = Self::unpack_u32(len)? as usize;
+
+        if rest.len() < len {
+            return Err(StreamError::BadStringLength.into());
         }
+
+        let (bytes, rest) = rest.split_at(len);
+        let value = String::from_utf8(bytes.to_vec())
+            .map_err(|_| StreamError::InvalidStreamData)?;
+
+        check_stream_name(&value)?;
+
+        Ok((value, rest))
+    }
+
+    fn unpack_u32(input: &[u8]) -> Result<u32, StreamError> {
+        let value = input
+            .get(..4)
+            .and_then(|slice| slice.try_into().ok())
+            .map(u32::from_le_bytes)
+            .ok_or(StreamError::InvalidStreamInstruction)?;
+
+        Ok(value)
     }
 
     fn unpack_u64(input: &[u8]) -> Result<u64, StreamError> {
@@ -530,13 +1172,13 @@ impl StreamInstruction {
     rate_cliff_in_seconds: u64,
     cliff_vest_amount: f64,
     cliff_vest_percent: f64,
-    auto_pause_in_seconds: u64
+    auto_pause_in_seconds: u64,
+    client_ref: [u8; 16]
 
  ) -> Result<Instruction, StreamError> {
 
-    if let Err(_error) = check_program_account(program_id) {
-        return Err(StreamError::IncorrectProgramId.into());
-    }
+    check_program_account(program_id)?;
+    check_stream_name(&stream_name)?;
 
     let data = StreamInstruction::CreateStream {
         beneficiary_address,
@@ -547,16 +1189,20 @@ impl StreamInstruction {
         rate_cliff_in_seconds,
         cliff_vest_amount,
         cliff_vest_percent,
-        auto_pause_in_seconds
+        auto_pause_in_seconds,
+        funding_amount: 0.0,
+        client_ref
 
     }.pack();
 
     let accounts = vec![
-        AccountMeta::new_readonly(treasurer_address, true),
-        AccountMeta::new_readonly(treasury_address, false),
+        AccountMeta::new(treasurer_address, true),
+        AccountMeta::new(treasury_address, false),
         AccountMeta::new_readonly(beneficiary_mint_address, false),
-        AccountMeta::new(stream_address, false),
+        AccountMeta::new(stream_address, client_ref == [0; 16]),
         AccountMeta::new(msp_ops_address, false),
+        AccountMeta::new_readonly(*program_id, false),
+        AccountMeta::new_readonly(solana_program::system_program::id(), false),
         AccountMeta::new_readonly(solana_program::sysvar::rent::id(), false)
     ];
 
@@ -567,20 +1213,103 @@ impl StreamInstruction {
     })
  }
 
- pub fn add_funds(
+ /// Creates a stream and funds it with `funding_amount` tokens in the same instruction. The treasurer
+ /// gets the treasury pool tokens of the funding, pass `Pubkey::default()` as the treasurer treasury
+ /// token and treasury mint addresses for a treasury without a pool mint
+ pub fn create_stream_with_funds(
     program_id: &Pubkey,
-    stream_address: &Pubkey,
-    treasury_address: &Pubkey,
-    contribution_token_address: Pubkey,
-    contribution_amount: f64,
-    funded_on_utc: u64,
-    resume: bool
+    treasurer_address: Pubkey,
+    treasurer_token_address: Pubkey,
+    treasurer_treasury_token_address: Pubkey,
+    beneficiary_address: Pubkey,
+    beneficiary_mint_address: Pubkey,
+    treasury_address: Pubkey,
+    treasury_token_address: Pubkey,
+    treasury_mint_address: Pubkey,
+    stream_address: Pubkey,
+    msp_ops_address: Pubkey,
+    msp_ops_token_address: Pubkey,
+    token_program_id: Pubkey,
+    stream_name: String,
+    rate_amount: f64,
+    rate_interval_in_seconds: u64,
+    start_utc: u64,
+    rate_cliff_in_seconds: u64,
+    cliff_vest_amount: f64,
+    cliff_vest_percent: f64,
+    auto_pause_in_seconds: u64,
+    funding_amount: f64,
+    client_ref: [u8; 16]
 
  ) -> Result<Instruction, StreamError> {
 
-    if let Err(_error) = check_program_account(program_id) {
-        return Err(StreamError::IncorrectProgramId.into());
-    }
+    check_program_account(program_id)?;
+    check_token_program_account(&token_program_id)?;
+    check_stream_name(&stream_name)?;
+
+    let data = StreamInstruction::CreateStream {
+        beneficiary_address,
+        stream_name,
+        rate_amount,
+        rate_interval_in_seconds,
+        start_utc,
+        rate_cliff_in_seconds,
+        cliff_vest_amount,
+        cliff_vest_percent,
+        auto_pause_in_seconds,
+        funding_amount,
+        client_ref
+
+    }.pack();
+
+    let accounts = vec![
+        AccountMeta::new(treasurer_address, true),
+        AccountMeta::new(treasury_address, false),
+        AccountMeta::new_readonly(beneficiary_mint_address, false),
+        AccountMeta::new(stream_address, client_ref == [0; 16]),
+        AccountMeta::new(msp_ops_address, false),
+        AccountMeta::new_readonly(*program_id, false),
+        AccountMeta::new_readonly(solana_program::system_program::id(), false),
+        AccountMeta::new_readonly(solana_program::sysvar::rent::id(), false),
+        AccountMeta::new(treasurer_token_address, false),
+        AccountMeta::new(treasury_token_address, false),
+        AccountMeta::new(msp_ops_token_address, false),
+        AccountMeta::new_readonly(token_program_id, false),
+        AccountMeta::new(treasurer_treasury_token_address, false),
+        AccountMeta::new(treasury_mint_address, false),
+        AccountMeta::new_readonly(spl_associated_token_account::id(), false)
+    ];
+
+    check_unique_accounts(&accounts)?;
+
+    Ok(Instruction { 
+        program_id: *program_id, 
+        accounts, 
+        data 
+    })
+ }
+
+ pub fn add_funds(
+    program_id: &Pubkey,
+    contributor_address: Pubkey,
+    contributor_token_address: Pubkey,
+    contributor_treasury_token_address: Pubkey,
+    beneficiary_mint_address: Pubkey,
+    treasury_address: Pubkey,
+    treasury_token_address: Pubkey,
+    treasury_mint_address: Pubkey,
+    stream_address: Pubkey,
+    msp_ops_address: Pubkey,
+    msp_ops_token_address: Pubkey,
+    token_program_id: Pubkey,
+    contribution_amount: f64,
+    funded_on_utc: u64,
+    resume: bool
+
+ ) -> Result<Instruction, StreamError> {
+
+    check_program_account(program_id)?;
+    check_token_program_account(&token_program_id)?;
 
     let data = StreamInstruction::AddFunds { 
         contribution_amount,
@@ -590,11 +1319,25 @@ impl StreamInstruction {
     }.pack();
 
     let accounts = vec![
-        AccountMeta::new(contribution_token_address, true),
-        AccountMeta::new(*stream_address, false),
-        AccountMeta::new_readonly(*treasury_address, false)
+        AccountMeta::new(contributor_address, true),
+        AccountMeta::new(contributor_token_address, false),
+        AccountMeta::new(contributor_treasury_token_address, false),
+        AccountMeta::new_readonly(beneficiary_mint_address, false),
+        AccountMeta::new_readonly(treasury_address, false),
+        AccountMeta::new(treasury_token_address, false),
+        AccountMeta::new(treasury_mint_address, false),
+        AccountMeta::new(stream_address, false),
+        AccountMeta::new(msp_ops_address, false),
+        AccountMeta::new(msp_ops_token_address, false),
+        AccountMeta::new_readonly(*program_id, false),
+        AccountMeta::new_readonly(token_program_id, false),
+        AccountMeta::new_readonly(spl_associated_token_account::id(), false),
+        AccountMeta::new_readonly(solana_program::system_program::id(), false),
+        AccountMeta::new_readonly(solana_program::sysvar::rent::id(), false)
     ];
 
+    check_unique_accounts(&accounts)?;
+
     Ok(Instruction { 
         program_id: *program_id, 
         accounts, 
@@ -605,23 +1348,42 @@ impl StreamInstruction {
  pub fn withdraw(
     program_id: &Pubkey,
     beneficiary_account_address: Pubkey,
-    stream_account_address: Pubkey,
+    beneficiary_token_address: Pubkey,
+    beneficiary_mint_address: Pubkey,
     treasury_account_address: Pubkey,
+    treasury_token_address: Pubkey,
+    stream_account_address: Pubkey,
+    msp_ops_address: Pubkey,
+    msp_ops_token_address: Pubkey,
+    token_program_id: Pubkey,
     withdrawal_amount: f64,
+    hook_program: Option<Pubkey> // the withdraw hook of the stream, if any
 
  ) -> Result<Instruction, StreamError> {
 
-    if let Err(_error) = check_program_account(program_id) {
-        return Err(StreamError::IncorrectProgramId.into());
-    }
+    check_program_account(program_id)?;
+    check_token_program_account(&token_program_id)?;
 
     let data = StreamInstruction::Withdraw { withdrawal_amount }.pack();
-    let accounts = vec![
-        AccountMeta::new_readonly(beneficiary_account_address, false),
+    let mut accounts = vec![
+        AccountMeta::new_readonly(beneficiary_account_address, true),
+        AccountMeta::new(beneficiary_token_address, false),
+        AccountMeta::new_readonly(beneficiary_mint_address, false),
+        AccountMeta::new_readonly(treasury_account_address, false),
+        AccountMeta::new(treasury_token_address, false),
         AccountMeta::new(stream_account_address, false),
-        AccountMeta::new_readonly(treasury_account_address, false)
+        AccountMeta::new(msp_ops_address, false),
+        AccountMeta::new(msp_ops_token_address, false),
+        AccountMeta::new_readonly(*program_id, false),
+        AccountMeta::new_readonly(token_program_id, false)
     ];
 
+    if let Some(hook_program) = hook_program {
+        accounts.push(AccountMeta::new_readonly(hook_program, false));
+    }
+
+    check_unique_accounts(&accounts)?;
+
     Ok(Instruction { 
         program_id: *program_id, 
         accounts, 
@@ -630,27 +1392,46 @@ impl StreamInstruction {
  }
 
  pub fn close_stream(
-    initializer_account_key: &Pubkey,
-    stream_account_key: &Pubkey,
-    counterparty_account_key: &Pubkey,
-    treasury_account_key: &Pubkey,
     program_id: &Pubkey,
+    initializer_address: Pubkey,
+    treasurer_token_address: Pubkey,
+    beneficiary_token_address: Pubkey,
+    beneficiary_mint_address: Pubkey,
+    treasury_address: Pubkey,
+    treasury_token_address: Pubkey,
+    stream_address: Pubkey,
+    msp_ops_address: Pubkey,
+    msp_ops_token_address: Pubkey,
+    token_program_id: Pubkey
 
  ) -> Result<Instruction, StreamError> {
 
-    if let Err(_error) = check_program_account(program_id) {
-        return Err(StreamError::IncorrectProgramId.into());
-    }
+    check_program_account(program_id)?;
+    check_token_program_account(&token_program_id)?;
 
     let data = StreamInstruction::CloseStream.pack();
     let accounts = vec![
-        AccountMeta::new(*initializer_account_key, true),
-        AccountMeta::new(*stream_account_key, false),
-        AccountMeta::new_readonly(*counterparty_account_key, false),
-        AccountMeta::new_readonly(*treasury_account_key, false)
+        AccountMeta::new(initializer_address, true),
+        AccountMeta::new(treasurer_token_address, false),
+        AccountMeta::new(beneficiary_token_address, false),
+        AccountMeta::new_readonly(beneficiary_mint_address, false),
+        AccountMeta::new(treasury_address, false),
+        AccountMeta::new(treasury_token_address, false),
+        AccountMeta::new(stream_address, false),
+        AccountMeta::new(msp_ops_address, false),
+        AccountMeta::new(msp_ops_token_address, false),
+        AccountMeta::new_readonly(*program_id, false),
+        AccountMeta::new_readonly(token_program_id, false),
+        AccountMeta::new_readonly(solana_program::system_program::id(), false)
     ];
 
-    Ok(Instruction { program_id: *program_id, accounts, data })
+    check_unique_accounts(&accounts)?;
+
+    Ok(Instruction { 
+        program_id: *program_id, 
+        accounts, 
+        data 
+    })
  }
 
  pub fn transfer(
@@ -659,14 +1440,14 @@ impl StreamInstruction {
      destination_token_address: Pubkey,
      mint_address: Pubkey,
     //  msp_ops_address: Pubkey,
+     token_program_id: Pubkey,
      program_id: &Pubkey,
      amount: f64
 
  ) -> Result<Instruction, StreamError> {
 
-    if let Err(_error) = check_program_account(program_id) {
-        return Err(StreamError::IncorrectProgramId.into());
-    }
+    check_program_account(program_id)?;
+    check_token_program_account(&token_program_id)?;
 
     let data = StreamInstruction::Transfer { amount }.pack();
     let accounts = vec![
@@ -676,13 +1457,1416 @@ impl StreamInstruction {
         AccountMeta::new(mint_address, false),
         // AccountMeta::new(msp_ops_address, false),
         // AccountMeta::new_readonly(*program_id, false),
-        AccountMeta::new_readonly(spl_token::id(), false),
+        AccountMeta::new_readonly(token_program_id, false),
         // AccountMeta::new_readonly(solana_program::system_program::id(), false)
     ];
 
+    check_unique_accounts(&accounts)?;
+
+    Ok(Instruction { 
+        program_id: *program_id, 
+        accounts, 
+        data 
+    })
+ }
+
+ pub fn set_beneficiary_token_account(
+    program_id: &Pubkey,
+    beneficiary_address: &Pubkey,
+    beneficiary_token_address: &Pubkey,
+    stream_address: &Pubkey,
+    msp_ops_address: &Pubkey
+
+ ) -> Result<Instruction, StreamError> {
+
+    check_program_account(program_id)?;
+
+    let data = StreamInstruction::SetBeneficiaryTokenAccount { 
+        token_account: *beneficiary_token_address 
+
+    }.pack();
+
+    let accounts = vec![
+        AccountMeta::new_readonly(*beneficiary_address, true),
+        AccountMeta::new_readonly(*beneficiary_token_address, false),
+        AccountMeta::new(*stream_address, false),
+        AccountMeta::new_readonly(*msp_ops_address, false)
+    ];
+
+    Ok(Instruction { 
+        program_id: *program_id, 
+        accounts, 
+        data 
+    })
+ }
+
+ pub fn transfer_stream_ownership(
+    program_id: &Pubkey,
+    treasurer_address: &Pubkey,
+    stream_address: &Pubkey,
+    msp_ops_address: &Pubkey,
+    new_treasurer: Pubkey
+
+ ) -> Result<Instruction, StreamError> {
+
+    check_program_account(program_id)?;
+
+    let data = StreamInstruction::TransferStreamOwnership { new_treasurer }.pack();
+    let accounts = vec![
+        AccountMeta::new_readonly(*treasurer_address, true),
+        AccountMeta::new(*stream_address, false),
+        AccountMeta::new_readonly(*msp_ops_address, false)
+    ];
+
+    Ok(Instruction { 
+        program_id: *program_id, 
+        accounts, 
+        data 
+    })
+ }
+
+ pub fn expire_proposal(
+    program_id: &Pubkey,
+    stream_terms_address: &Pubkey,
+    proposer_address: &Pubkey,
+    msp_ops_address: &Pubkey
+
+ ) -> Result<Instruction, StreamError> {
+
+    check_program_account(program_id)?;
+
+    let data = StreamInstruction::ExpireProposal.pack();
+    let accounts = vec![
+        AccountMeta::new(*stream_terms_address, false),
+        AccountMeta::new(*proposer_address, false),
+        AccountMeta::new_readonly(*msp_ops_address, false)
+    ];
+
+    Ok(Instruction { 
+        program_id: *program_id, 
+        accounts, 
+        data 
+    })
+ }
+
+ pub fn close_treasury(
+    program_id: &Pubkey,
+    treasurer_address: &Pubkey,
+    treasury_address: &Pubkey,
+    treasury_token_address: &Pubkey,
+    msp_ops_address: &Pubkey
+
+ ) -> Result<Instruction, StreamError> {
+
+    check_program_account(program_id)?;
+
+    let data = StreamInstruction::CloseTreasury.pack();
+    let accounts = vec![
+        AccountMeta::new(*treasurer_address, true),
+        AccountMeta::new(*treasury_address, false),
+        AccountMeta::new_readonly(*treasury_token_address, false),
+        AccountMeta::new_readonly(*msp_ops_address, false)
+    ];
+
+    Ok(Instruction { 
+        program_id: *program_id, 
+        accounts, 
+        data 
+    })
+ }
+
+ pub fn set_auto_pause_threshold(
+    program_id: &Pubkey,
+    treasurer_address: &Pubkey,
+    stream_address: &Pubkey,
+    msp_ops_address: &Pubkey,
+    seconds: u64
+
+ ) -> Result<Instruction, StreamError> {
+
+    check_program_account(program_id)?;
+
+    let data = StreamInstruction::SetAutoPauseThreshold { seconds }.pack();
+    let accounts = vec![
+        AccountMeta::new_readonly(*treasurer_address, true),
+        AccountMeta::new(*stream_address, false),
+        AccountMeta::new_readonly(*msp_ops_address, false)
+    ];
+
+    Ok(Instruction { 
+        program_id: *program_id, 
+        accounts, 
+        data 
+    })
+ }
+
+ pub fn renew_stream(
+    program_id: &Pubkey,
+    treasurer_address: Pubkey,
+    stream_address: Pubkey,
+    new_stream_address: Pubkey,
+    treasury_address: Pubkey,
+    msp_ops_address: Pubkey,
+    beneficiary_address: Pubkey,
+    stream_name: String,
+    rate_amount: f64,
+    rate_interval_in_seconds: u64,
+    start_utc: u64,
+    rate_cliff_in_seconds: u64,
+    cliff_vest_amount: f64,
+    cliff_vest_percent: f64,
+    auto_pause_in_seconds: u64,
+    current_beneficiary_address: Option<Pubkey>
+
+ ) -> Result<Instruction, StreamError> {
+
+    check_program_account(program_id)?;
+    check_stream_name(&stream_name)?;
+
+    let data = StreamInstruction::RenewStream {
+        beneficiary_address,
+        stream_name,
+        rate_amount,
+        rate_interval_in_seconds,
+        start_utc,
+        rate_cliff_in_seconds,
+        cliff_vest_amount,
+        cliff_vest_percent,
+        auto_pause_in_seconds
+
+    }.pack();
+
+    let mut accounts = vec![
+        AccountMeta::new(treasurer_address, true),
+        AccountMeta::new(stream_address, false),
+        AccountMeta::new(new_stream_address, true),
+        AccountMeta::new_readonly(treasury_address, false),
+        AccountMeta::new(msp_ops_address, false),
+        AccountMeta::new_readonly(*program_id, false),
+        AccountMeta::new_readonly(solana_program::system_program::id(), false),
+        AccountMeta::new_readonly(solana_program::sysvar::rent::id(), false)
+    ];
+
+    if let Some(current_beneficiary_address) = current_beneficiary_address {
+        accounts.push(AccountMeta::new_readonly(current_beneficiary_address, true));
+    }
+
+    Ok(Instruction { 
+        program_id: *program_id, 
+        accounts, 
+        data 
+    })
+ }
+
+ pub fn withdraw_max(
+    program_id: &Pubkey,
+    beneficiary_account_address: Pubkey,
+    beneficiary_token_address: Pubkey,
+    beneficiary_mint_address: Pubkey,
+    treasury_account_address: Pubkey,
+    treasury_token_address: Pubkey,
+    stream_account_address: Pubkey,
+    msp_ops_address: Pubkey,
+    msp_ops_token_address: Pubkey,
+    token_program_id: Pubkey,
+    hook_program: Option<Pubkey>
+
+ ) -> Result<Instruction, StreamError> {
+
+    let mut instruction = withdraw(
+        program_id,
+        beneficiary_account_address,
+        beneficiary_token_address,
+        beneficiary_mint_address,
+        treasury_account_address,
+        treasury_token_address,
+        stream_account_address,
+        msp_ops_address,
+        msp_ops_token_address,
+        token_program_id,
+        0.0,
+        hook_program
+    )?;
+
+    instruction.data = StreamInstruction::WithdrawMax.pack();
+
+    Ok(instruction)
+ }
+
+ pub fn set_withdraw_hook(
+    program_id: &Pubkey,
+    beneficiary_address: &Pubkey,
+    stream_address: &Pubkey,
+    msp_ops_address: &Pubkey,
+    hook_program: Option<Pubkey>
+
+ ) -> Result<Instruction, StreamError> {
+
+    check_program_account(program_id)?;
+
+    let data = StreamInstruction::SetWithdrawHook { hook_program }.pack();
+    let mut accounts = vec![
+        AccountMeta::new_readonly(*beneficiary_address, true),
+        AccountMeta::new(*stream_address, false),
+        AccountMeta::new_readonly(*msp_ops_address, false)
+    ];
+
+    if let Some(hook_program) = hook_program {
+        accounts.push(AccountMeta::new_readonly(hook_program, false));
+    }
+
     Ok(Instruction { 
         program_id: *program_id, 
         accounts, 
         data 
     })
- }
\ No newline at end of file
+ }
+
+ /// Instruction the program invokes on a stream withdraw hook after a withdrawal. The data is
+ /// the amount received by the beneficiary token account (in token base units, u64 little endian)
+ ///
+ /// 0. `[]` The stream account
+ /// 1. `[]` The beneficiary account
+ /// 2. `[]` The beneficiary token account (the recipient of the withdrawal)
+ pub fn withdraw_hook(
+    hook_program_id: &Pubkey,
+    stream_address: &Pubkey,
+    beneficiary_address: &Pubkey,
+    beneficiary_token_address: &Pubkey,
+    received_amount: u64
+
+ ) -> Instruction {
+
+    let accounts = vec![
+        AccountMeta::new_readonly(*stream_address, false),
+        AccountMeta::new_readonly(*beneficiary_address, false),
+        AccountMeta::new_readonly(*beneficiary_token_address, false)
+    ];
+
+    Instruction { 
+        program_id: *hook_program_id, 
+        accounts, 
+        data: received_amount.to_le_bytes().to_vec()
+    }
+ }
+
+ pub fn rename_stream(
+    program_id: &Pubkey,
+    treasurer_address: &Pubkey,
+    stream_address: &Pubkey,
+    msp_ops_address: &Pubkey,
+    stream_name: String
+
+ ) -> Result<Instruction, StreamError> {
+
+    check_program_account(program_id)?;
+    check_stream_name(&stream_name)?;
+
+    let data = StreamInstruction::RenameStream { stream_name }.pack();
+    let accounts = vec![
+        AccountMeta::new_readonly(*treasurer_address, true),
+        AccountMeta::new(*stream_address, false),
+        AccountMeta::new_readonly(*msp_ops_address, false)
+    ];
+
+    Ok(Instruction { 
+        program_id: *program_id, 
+        accounts, 
+        data 
+    })
+ }
+
+ pub fn multi_withdraw(
+    program_id: &Pubkey,
+    beneficiary_account_address: Pubkey,
+    beneficiary_token_address: Pubkey,
+    beneficiary_mint_address: Pubkey,
+    msp_ops_address: Pubkey,
+    msp_ops_token_address: Pubkey,
+    token_program_id: Pubkey,
+    streams: &[(Pubkey, Pubkey, Pubkey)] // (treasury, treasury token, stream) of each stream
+
+ ) -> Result<Instruction, StreamError> {
+
+    check_program_account(program_id)?;
+    check_token_program_account(&token_program_id)?;
+
+    if streams.is_empty() || streams.len() > MAX_MULTI_WITHDRAW_STREAMS as usize {
+        return Err(StreamError::BatchTooLarge);
+    }
+
+    let data = StreamInstruction::MultiWithdraw { count: streams.len() as u8 }.pack();
+    let mut accounts = vec![
+        AccountMeta::new_readonly(beneficiary_account_address, true),
+        AccountMeta::new(beneficiary_token_address, false),
+        AccountMeta::new_readonly(beneficiary_mint_address, false),
+        AccountMeta::new(msp_ops_address, false),
+        AccountMeta::new(msp_ops_token_address, false),
+        AccountMeta::new_readonly(*program_id, false),
+        AccountMeta::new_readonly(token_program_id, false)
+    ];
+
+    for (treasury_account_address, treasury_token_address, stream_account_address) in streams {
+        accounts.push(AccountMeta::new_readonly(*treasury_account_address, false));
+        accounts.push(AccountMeta::new(*treasury_token_address, false));
+        accounts.push(AccountMeta::new(*stream_account_address, false));
+    }
+
+    Ok(Instruction { 
+        program_id: *program_id, 
+        accounts, 
+        data 
+    })
+ }
+
+ pub fn donate_stream(
+    program_id: &Pubkey,
+    treasurer_address: &Pubkey,
+    beneficiary_address: &Pubkey,
+    stream_address: &Pubkey,
+    msp_ops_address: &Pubkey,
+    new_treasurer: Pubkey,
+    new_beneficiary: Pubkey
+
+ ) -> Result<Instruction, StreamError> {
+
+    check_program_account(program_id)?;
+
+    let data = StreamInstruction::DonateStream { new_treasurer, new_beneficiary }.pack();
+    let accounts = vec![
+        AccountMeta::new_readonly(*treasurer_address, true),
+        AccountMeta::new_readonly(*beneficiary_address, true),
+        AccountMeta::new(*stream_address, false),
+        AccountMeta::new_readonly(*msp_ops_address, false)
+    ];
+
+    Ok(Instruction { 
+        program_id: *program_id, 
+        accounts, 
+        data 
+    })
+ }
+
+ /// Creates a treasury based on the treasurer, deriving the treasury, its token account and its pool mint
+ /// (only when `with_pool_mint`) addresses
+ pub fn create_treasury(
+    program_id: &Pubkey,
+    treasurer_address: &Pubkey,
+    treasury_token_mint_address: &Pubkey,
+    msp_ops_address: &Pubkey,
+    token_program_id: &Pubkey,
+    treasury_block_height: u64,
+    funding_increment: u64,
+    max_streams: u64,
+    with_pool_mint: bool
+
+ ) -> Result<Instruction, StreamError> {
+
+    check_program_account(program_id)?;
+    check_token_program_account(token_program_id)?;
+
+    let (treasury_address, _treasury_bump_seed) = find_treasury_address(
+        treasurer_address,
+        treasury_block_height,
+        program_id
+    );
+
+    let treasury_token_address = spl_associated_token_account::get_associated_token_address_with_program_id(
+        &treasury_address,
+        treasury_token_mint_address,
+        token_program_id
+    );
+
+    let treasury_mint_address = if with_pool_mint {
+        find_treasury_mint_address(treasurer_address, &treasury_address, treasury_block_height, program_id).0
+    } else {
+        Pubkey::default()
+    };
+
+    let data = StreamInstruction::CreateTreasury {
+        treasury_block_height,
+        treasury_base_address: *treasurer_address,
+        funding_increment,
+        max_streams
+
+    }.pack();
+
+    let accounts = vec![
+        AccountMeta::new(*treasurer_address, true),
+        AccountMeta::new(treasury_address, false),
+        AccountMeta::new(treasury_token_address, false),
+        AccountMeta::new_readonly(*treasury_token_mint_address, false),
+        AccountMeta::new(treasury_mint_address, false),
+        AccountMeta::new_readonly(*program_id, false),
+        AccountMeta::new(*msp_ops_address, false),
+        AccountMeta::new_readonly(*token_program_id, false),
+        AccountMeta::new_readonly(spl_associated_token_account::id(), false),
+        AccountMeta::new_readonly(solana_program::system_program::id(), false),
+        AccountMeta::new_readonly(solana_program::sysvar::rent::id(), false)
+    ];
+
+    Ok(Instruction { 
+        program_id: *program_id, 
+        accounts, 
+        data 
+    })
+ }
+
+ /// Tops up a stream with `amount` base units, failing if it would run out before `min_new_depletion_utc`
+ pub fn top_up(
+    program_id: &Pubkey,
+    contributor_address: Pubkey,
+    contributor_token_address: Pubkey,
+    contributor_treasury_token_address: Pubkey,
+    beneficiary_mint_address: Pubkey,
+    treasury_address: Pubkey,
+    treasury_token_address: Pubkey,
+    treasury_mint_address: Pubkey,
+    stream_address: Pubkey,
+    msp_ops_address: Pubkey,
+    msp_ops_token_address: Pubkey,
+    token_program_id: Pubkey,
+    amount: u64,
+    min_new_depletion_utc: u64
+
+ ) -> Result<Instruction, StreamError> {
+
+    let mut instruction = add_funds(
+        program_id,
+        contributor_address,
+        contributor_token_address,
+        contributor_treasury_token_address,
+        beneficiary_mint_address,
+        treasury_address,
+        treasury_token_address,
+        treasury_mint_address,
+        stream_address,
+        msp_ops_address,
+        msp_ops_token_address,
+        token_program_id,
+        0.0,
+        0,
+        true
+    )?;
+
+    instruction.data = StreamInstruction::TopUp { amount, min_new_depletion_utc }.pack();
+
+    Ok(instruction)
+ }
+
+ /// Parameters shared by the streams created with `create_streams_bulk`
+ #[derive(Clone, Debug)]
+ pub struct CreateStreamParams {
+    pub treasurer_address: Pubkey,
+    pub beneficiary_mint_address: Pubkey,
+    pub treasury_address: Pubkey,
+    pub msp_ops_address: Pubkey,
+    pub stream_name: String,
+    pub rate_amount: f64,
+    pub rate_interval_in_seconds: u64,
+    pub start_utc: u64,
+    pub rate_cliff_in_seconds: u64,
+    pub cliff_vest_amount: f64,
+    pub cliff_vest_percent: f64,
+    pub auto_pause_in_seconds: u64
+ }
+
+ /// One `CreateStream` instruction per `(beneficiary_address, stream_address)`, all of them sharing
+ /// the treasury and the rate parameters. Fails with `BatchTooLarge` when they don't fit in a single
+ /// transaction paid by the treasurer (see `check_batch_size`)
+ pub fn create_streams_bulk(
+    program_id: &Pubkey,
+    params: &CreateStreamParams,
+    beneficiaries: &[(Pubkey, Pubkey)]
+
+ ) -> Result<Vec<Instruction>, StreamError> {
+
+    if beneficiaries.is_empty() {
+        return Err(StreamError::InvalidArgument);
+    }
+
+    let instructions = beneficiaries
+        .iter()
+        .map(|(beneficiary_address, stream_address)| create_stream(
+            program_id,
+            params.treasurer_address,
+            *beneficiary_address,
+            params.beneficiary_mint_address,
+            params.treasury_address,
+            *stream_address,
+            params.msp_ops_address,
+            params.stream_name.clone(),
+            params.rate_amount,
+            params.rate_interval_in_seconds,
+            params.start_utc,
+            params.rate_cliff_in_seconds,
+            params.cliff_vest_amount,
+            params.cliff_vest_percent,
+            params.auto_pause_in_seconds,
+            [0; 16]
+        ))
+        .collect::<Result<Vec<Instruction>, StreamError>>()?;
+
+    check_batch_size(&instructions, &params.treasurer_address)?;
+
+    Ok(instructions)
+ }
+
+ pub fn get_version(
+    program_id: &Pubkey,
+    msp_ops_address: &Pubkey
+
+ ) -> Result<Instruction, StreamError> {
+
+    check_program_account(program_id)?;
+
+    let data = StreamInstruction::GetVersion.pack();
+    let accounts = vec![
+        AccountMeta::new_readonly(*msp_ops_address, false)
+    ];
+
+    Ok(Instruction { 
+        program_id: *program_id, 
+        accounts, 
+        data 
+    })
+ }
+
+ /// The `rate_amount` per `interval_seconds` that streams `funding` over `duration_seconds`
+ pub fn rate_for(
+    funding: f64,
+    duration_seconds: u64,
+    interval_seconds: u64
+
+ ) -> f64 {
+
+    if duration_seconds == 0 {
+        return 0.0;
+    }
+
+    funding * (interval_seconds as f64) / (duration_seconds as f64)
+ }
+
+ /// Checks that `funding_amount` streamed at `rate_amount` per `rate_interval_in_seconds` lasts at least
+ /// one full `auto_pause_in_seconds` window. Nothing to check without funding or auto pause
+ pub fn check_rate_for_funding(
+    funding_amount: f64,
+    rate_amount: f64,
+    rate_interval_in_seconds: u64,
+    auto_pause_in_seconds: u64
+
+ ) -> Result<(), StreamError> {
+
+    if funding_amount <= 0.0 || auto_pause_in_seconds == 0 || rate_interval_in_seconds == 0 {
+        return Ok(());
+    }
+
+    // funding / (rate / interval) >= window, without dividing by the rate
+    if funding_amount * (rate_interval_in_seconds as f64) < rate_amount * (auto_pause_in_seconds as f64) {
+        return Err(StreamError::RateTooHighForFunding);
+    }
+
+    Ok(())
+ }
+
+ /// Checks that the cliff ends within the time `funding_amount` lasts when streamed at `rate_amount` per
+ /// `rate_interval_in_seconds`, since past that point nothing vests. Nothing to check without funding or rate
+ pub fn check_cliff_within_duration(
+    funding_amount: f64,
+    rate_amount: f64,
+    rate_interval_in_seconds: u64,
+    rate_cliff_in_seconds: u64
+
+ ) -> Result<(), StreamError> {
+
+    if funding_amount <= 0.0 || rate_amount <= 0.0 || rate_interval_in_seconds == 0 {
+        return Ok(());
+    }
+
+    // cliff <= funding / (rate / interval), without dividing by the rate
+    if (rate_cliff_in_seconds as f64) * rate_amount > funding_amount * (rate_interval_in_seconds as f64) {
+        return Err(StreamError::CliffExceedsDuration);
+    }
+
+    Ok(())
+ }
+
+ /// Fails with `BadStringLength` on a name longer than the `MAX_STREAM_NAME_LEN` bytes a stream stores
+ pub fn check_stream_name(stream_name: &str) -> Result<(), StreamError> {
+
+    if stream_name.len() > MAX_STREAM_NAME_LEN {
+        return Err(StreamError::BadStringLength);
+    }
+
+    Ok(())
+ }
+
+ /// Checks that `start_utc` is within `MAX_START_UTC_OFFSET_IN_SECONDS` of `now_utc`, either way
+ pub fn check_start_utc(start_utc: u64, now_utc: u64) -> Result<(), StreamError> {
+
+    let offset = if start_utc > now_utc { start_utc - now_utc } else { now_utc - start_utc };
+
+    if offset > MAX_START_UTC_OFFSET_IN_SECONDS {
+        return Err(StreamError::StartTimeOutOfRange);
+    }
+
+    Ok(())
+ }
+
+ /// Parses a base58 encoded pubkey for the builders
+ pub fn parse_pubkey(s: &str) -> Result<Pubkey, StreamError> {
+    Pubkey::from_str(s).map_err(|_| StreamError::InvalidArgument)
+ }
+
+ /// `(rate_amount, rate_interval_in_seconds)` streaming `annual_amount` over a 365.25 days year
+ /// (`SECONDS_PER_YEAR`, so leap years average out), paid per day
+ pub fn rate_from_annual(annual_amount: f64) -> (f64, u64) {
+    (rate_for(annual_amount, SECONDS_PER_YEAR, SECONDS_PER_DAY), SECONDS_PER_DAY)
+ }
+
+ /// Signed gain (or loss) in base units of a treasury pool contribution redeemable for `redeemable_now`
+ pub fn contributor_pnl(
+    original_contribution: u64,
+    redeemable_now: u64
+
+ ) -> i64 {
+
+    let pnl = (redeemable_now as i128) - (original_contribution as i128);
+
+    pnl.max(i64::MIN as i128).min(i64::MAX as i128) as i64
+ }
+
+ /// Fails with `DuplicateAccount` when a pubkey fills more than one role of an instruction. The
+ /// handlers read their accounts by position, so a wallet used as e.g. both treasurer and
+ /// contributor has to be caught before the transaction is built. Unset optional accounts
+ /// (`Pubkey::default()`) are ignored
+ pub fn check_unique_accounts(accounts: &[AccountMeta]) -> Result<(), StreamError> {
+
+    let mut keys = HashSet::new();
+
+    for account in accounts {
+        if account.pubkey != Pubkey::default() && !keys.insert(account.pubkey) {
+            return Err(StreamError::DuplicateAccount);
+        }
+    }
+
+    Ok(())
+ }
+
+ /// Checks that a batch of instructions paid by `payer` fits in a single transaction: no more than
+ /// `MAX_BATCH_SIZE` instructions and a serialized size within `MAX_TRANSACTION_SIZE`.
+ /// The program has no batch instructions of its own (no `WithdrawBatch`, `CreateStreamBatch` or
+ /// `PauseAll`), a batch is a transaction of several of its instructions and only the client can
+ /// check it. `MultiWithdraw` is the one instruction over many streams, bounded by `MAX_MULTI_WITHDRAW_STREAMS`
+ pub fn check_batch_size(
+    instructions: &[Instruction],
+    payer: &Pubkey
+
+ ) -> Result<(), StreamError> {
+
+    if instructions.len() > MAX_BATCH_SIZE {
+        return Err(StreamError::BatchTooLarge);
+    }
+
+    // Bytes taken by a compact-u16 length prefix
+    let compact_len = |len: usize| if len < 0x80 { 1 } else if len < 0x4000 { 2 } else { 3 };
+
+    let mut keys = HashSet::new();
+    let mut signers = HashSet::new();
+    let mut instructions_size = compact_len(instructions.len());
+
+    keys.insert(*payer);
+    signers.insert(*payer);
+
+    for instruction in instructions {
+        keys.insert(instruction.program_id);
+
+        for account in &instruction.accounts {
+            keys.insert(account.pubkey);
+
+            if account.is_signer {
+                signers.insert(account.pubkey);
+            }
+        }
+
+        instructions_size += 1 + 
+            compact_len(instruction.accounts.len()) + instruction.accounts.len() +
+            compact_len(instruction.data.len()) + instruction.data.len();
+    }
+
+    // Signatures, message header, account keys, recent blockhash and instructions
+    let transaction_size = compact_len(signers.len()) + signers.len() * 64 + 
+        3 +
+        compact_len(keys.len()) + keys.len() * 32 + 
+        32 + 
+        instructions_size;
+
+    if transaction_size > MAX_TRANSACTION_SIZE {
+        return Err(StreamError::BatchTooLarge);
+    }
+
+    Ok(())
+ }
+
+ /// JSON description of the instruction data layouts (tag and fields in packing order) for client
+ /// code generators. Has to be updated along with `StreamInstruction::pack`
+ pub fn instruction_schema() -> &'static str {
+    INSTRUCTION_SCHEMA
+ }
+
+ const INSTRUCTION_SCHEMA: &str = r#"{
+    "encoding": { "tag": "u8", "u8": "1 byte", "pubkey": "32 bytes", "u64": "8 bytes LE", "f64": "8 bytes LE", "bool": "1 byte", "string": "u32 LE length + UTF-8 bytes", "option<pubkey>": "1 presence byte + 32 bytes when present", "bytes16": "16 bytes" },
+    "instructions": [
+        { "name": "CreateStream", "tag": 0, "fields": [ { "name": "beneficiary_address", "type": "pubkey" }, { "name": "stream_name", "type": "string" }, { "name": "rate_amount", "type": "f64" }, { "name": "rate_interval_in_seconds", "type": "u64" }, { "name": "start_utc", "type": "u64" }, { "name": "rate_cliff_in_seconds", "type": "u64" }, { "name": "cliff_vest_amount", "type": "f64" }, { "name": "cliff_vest_percent", "type": "f64" }, { "name": "auto_pause_in_seconds", "type": "u64" }, { "name": "funding_amount", "type": "f64" }, { "name": "client_ref", "type": "bytes16" } ] },
+        { "name": "AddFunds", "tag": 1, "fields": [ { "name": "contribution_amount", "type": "f64" }, { "name": "funded_on_utc", "type": "u64" }, { "name": "resume", "type": "bool" } ] },
+        { "name": "RecoverFunds", "tag": 2, "fields": [ { "name": "recover_amount", "type": "f64" } ] },
+        { "name": "Withdraw", "tag": 3, "fields": [ { "name": "withdrawal_amount", "type": "f64" } ] },
+        { "name": "PauseStream", "tag": 4, "fields": [] },
+        { "name": "ResumeStream", "tag": 5, "fields": [] },
+        { "name": "ProposeUpdate", "tag": 6, "fields": [ { "name": "proposed_by", "type": "pubkey" }, { "name": "stream_name", "type": "string" }, { "name": "treasurer_address", "type": "pubkey" }, { "name": "beneficiary_address", "type": "pubkey" }, { "name": "associated_token_address", "type": "pubkey" }, { "name": "rate_amount", "type": "f64" }, { "name": "rate_interval_in_seconds", "type": "u64" }, { "name": "rate_cliff_in_seconds", "type": "u64" }, { "name": "cliff_vest_amount", "type": "f64" }, { "name": "cliff_vest_percent", "type": "f64" }, { "name": "auto_pause_in_seconds", "type": "u64" }, { "name": "expiration_in_seconds", "type": "u64" } ] },
+        { "name": "AnswerUpdate", "tag": 7, "fields": [ { "name": "approve", "type": "bool" } ] },
+        { "name": "CloseStream", "tag": 8, "fields": [] },
+        { "name": "CreateTreasury", "tag": 9, "fields": [ { "name": "treasury_block_height", "type": "u64" }, { "name": "treasury_base_address", "type": "pubkey" }, { "name": "funding_increment", "type": "u64" }, { "name": "max_streams", "type": "u64" } ] },
+        { "name": "Transfer", "tag": 10, "fields": [ { "name": "amount", "type": "f64" } ] },
+        { "name": "TransferStreamOwnership", "tag": 11, "fields": [ { "name": "new_treasurer", "type": "pubkey" } ] },
+        { "name": "CloseTreasury", "tag": 12, "fields": [] },
+        { "name": "SetAutoPauseThreshold", "tag": 13, "fields": [ { "name": "seconds", "type": "u64" } ] },
+        { "name": "WithdrawMax", "tag": 14, "fields": [] },
+        { "name": "RenameStream", "tag": 15, "fields": [ { "name": "stream_name", "type": "string" } ] },
+        { "name": "MultiWithdraw", "tag": 16, "fields": [ { "name": "count", "type": "u8" } ] },
+        { "name": "TopUp", "tag": 17, "fields": [ { "name": "amount", "type": "u64" }, { "name": "min_new_depletion_utc", "type": "u64" } ] },
+        { "name": "SetBeneficiaryTokenAccount", "tag": 44, "fields": [ { "name": "token_account", "type": "pubkey" } ] },
+        { "name": "ExpireProposal", "tag": 45, "fields": [] },
+        { "name": "GetVersion", "tag": 46, "fields": [] },
+        { "name": "RenewStream", "tag": 47, "fields": [ { "name": "beneficiary_address", "type": "pubkey" }, { "name": "stream_name", "type": "string" }, { "name": "rate_amount", "type": "f64" }, { "name": "rate_interval_in_seconds", "type": "u64" }, { "name": "start_utc", "type": "u64" }, { "name": "rate_cliff_in_seconds", "type": "u64" }, { "name": "cliff_vest_amount", "type": "f64" }, { "name": "cliff_vest_percent", "type": "f64" }, { "name": "auto_pause_in_seconds", "type": "u64" } ] },
+        { "name": "DonateStream", "tag": 48, "fields": [ { "name": "new_treasurer", "type": "pubkey" }, { "name": "new_beneficiary", "type": "pubkey" } ] },
+        { "name": "SetWithdrawHook", "tag": 49, "fields": [ { "name": "hook_program", "type": "option<pubkey>" } ] }
+    ]
+}"#;
+
+ /// Converts an RFC 3339 date (e.g. `2021-08-01T00:00:00Z`) into the unix seconds `start_utc` expects
+ #[cfg(feature = "chrono")]
+ pub fn parse_start_utc(rfc3339: &str) -> Result<u64, StreamError> {
+
+    let date = chrono::DateTime::parse_from_rfc3339(rfc3339)
+        .map_err(|_| StreamError::InvalidArgument)?;
+
+    if date.timestamp() < 0 {
+        return Err(StreamError::InvalidArgument);
+    }
+
+    Ok(date.timestamp() as u64)
+ }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // One instruction of each variant, string fields hold "payroll"
+    fn one_of_each() -> Vec<StreamInstruction> {
+        let key = Pubkey::new_unique();
+        let name = || "payroll".to_string();
+
+        vec![
+            StreamInstruction::CreateStream {
+                beneficiary_address: key, stream_name: name(), rate_amount: 1.0, rate_interval_in_seconds: 60,
+                start_utc: 1, rate_cliff_in_seconds: 2, cliff_vest_amount: 3.0, cliff_vest_percent: 4.0,
+                auto_pause_in_seconds: 5, funding_amount: 6.0, client_ref: [7; 16]
+            },
+            StreamInstruction::AddFunds { contribution_amount: 1.0, funded_on_utc: 2, resume: true },
+            StreamInstruction::RecoverFunds { recover_amount: 1.0 },
+            StreamInstruction::Withdraw { withdrawal_amount: 1.0 },
+            StreamInstruction::PauseStream,
+            StreamInstruction::ResumeStream,
+            StreamInstruction::ProposeUpdate {
+                proposed_by: key, stream_name: name(), treasurer_address: key, beneficiary_address: key,
+                associated_token_address: key, rate_amount: 1.0, rate_interval_in_seconds: 60, rate_cliff_in_seconds: 2,
+                cliff_vest_amount: 3.0, cliff_vest_percent: 4.0, auto_pause_in_seconds: 5, expiration_in_seconds: 6
+            },
+            StreamInstruction::AnswerUpdate { approve: true },
+            StreamInstruction::CloseStream,
+            StreamInstruction::CreateTreasury {
+                treasury_block_height: 1, treasury_base_address: key, funding_increment: 2, max_streams: 3
+            },
+            StreamInstruction::Transfer { amount: 1.0 },
+            StreamInstruction::SetBeneficiaryTokenAccount { token_account: key },
+            StreamInstruction::TransferStreamOwnership { new_treasurer: key },
+            StreamInstruction::ExpireProposal,
+            StreamInstruction::GetVersion,
+            StreamInstruction::CloseTreasury,
+            StreamInstruction::SetAutoPauseThreshold { seconds: 1 },
+            StreamInstruction::RenewStream {
+                beneficiary_address: key, stream_name: name(), rate_amount: 1.0, rate_interval_in_seconds: 60,
+                start_utc: 1, rate_cliff_in_seconds: 2, cliff_vest_amount: 3.0, cliff_vest_percent: 4.0,
+                auto_pause_in_seconds: 5
+            },
+            StreamInstruction::WithdrawMax,
+            StreamInstruction::SetWithdrawHook { hook_program: Some(key) },
+            StreamInstruction::RenameStream { stream_name: name() },
+            StreamInstruction::MultiWithdraw { count: 1 },
+            StreamInstruction::DonateStream { new_treasurer: key, new_beneficiary: key },
+            StreamInstruction::TopUp { amount: 1, min_new_depletion_utc: 2 }
+        ]
+    }
+
+    // Tags `unpack` recognizes, whether or not the body is valid
+    fn known_tags() -> Vec<u8> {
+        (0..=u8::MAX)
+            .filter(|tag| !matches!(
+                StreamInstruction::unpack(&[*tag]),
+                Err(StreamError::UnknownInstructionTag)
+            ))
+            .collect()
+    }
+
+    #[test]
+    fn instruction_schema_parses_and_describes_every_instruction() {
+        let schema: serde_json::Value = serde_json::from_str(instruction_schema()).unwrap();
+        let entries = schema["instructions"].as_array().unwrap();
+        let instructions = one_of_each();
+
+        let mut tags: Vec<u8> = instructions.iter().map(|instruction| instruction.tag()).collect();
+        tags.sort_unstable();
+        assert_eq!(tags, known_tags());
+        assert_eq!(entries.len(), instructions.len());
+
+        for instruction in &instructions {
+            let display = instruction.to_string();
+            let name = display.split('{').next().unwrap();
+            let entry = entries
+                .iter()
+                .find(|entry| entry["tag"].as_u64() == Some(instruction.tag() as u64))
+                .unwrap_or_else(|| panic!("{} is missing", name));
+
+            assert_eq!(entry["name"].as_str(), Some(name));
+
+            // The fields add up to the packed instruction
+            let fields_len: usize = entry["fields"].as_array().unwrap().iter().map(|field| {
+                match field["type"].as_str().unwrap() {
+                    "u8" | "bool" => 1,
+                    "u64" | "f64" => 8,
+                    "bytes16" => 16,
+                    "pubkey" => 32,
+                    "option<pubkey>" => 1 + 32,
+                    "string" => 4 + "payroll".len(),
+                    other => panic!("unknown type {}", other)
+                }
+            }).sum();
+
+            assert_eq!(1 + fields_len, instruction.pack().len(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn truncated_instructions_are_rejected_without_panicking() {
+        for instruction in one_of_each() {
+            let packed = instruction.pack();
+
+            for len in 0..packed.len() {
+                assert!(StreamInstruction::unpack(&packed[..len]).is_err(), "{} {}", instruction, len);
+            }
+        }
+    }
+
+    #[test]
+    fn display_names_the_instruction_and_its_main_fields() {
+        let instructions = one_of_each();
+
+        assert_eq!(
+            instructions[0].to_string(),
+            "CreateStream{name: payroll, rate_amount: 1, interval: 60, funding_amount: 6}"
+        );
+        assert_eq!(StreamInstruction::Withdraw { withdrawal_amount: 2.5 }.to_string(), "Withdraw{withdrawal_amount: 2.5}");
+        assert_eq!(StreamInstruction::PauseStream.to_string(), "PauseStream");
+
+        // Every variant has its own name
+        let names: HashSet<String> = instructions
+            .iter()
+            .map(|instruction| instruction.to_string().split('{').next().unwrap().to_string())
+            .collect();
+
+        assert_eq!(names.len(), instructions.len());
+    }
+
+    #[test]
+    fn packed_instructions_start_with_their_tag() {
+        for instruction in one_of_each() {
+            assert_eq!(instruction.pack()[0], instruction.tag(), "{}", instruction);
+        }
+
+        // Deployed clients already send these tags
+        assert_eq!(StreamInstruction::RenameStream { stream_name: String::new() }.pack()[0], 15);
+        assert_eq!(StreamInstruction::MultiWithdraw { count: 1 }.pack()[0], 16);
+        assert_eq!(StreamInstruction::TopUp { amount: 1, min_new_depletion_utc: 2 }.pack()[0], 17);
+        assert_eq!(StreamInstruction::SetWithdrawHook { hook_program: None }.pack()[0], 49);
+    }
+
+    #[test]
+    fn check_stream_name_bounds_the_length() {
+        assert!(check_stream_name(&"x".repeat(MAX_STREAM_NAME_LEN)).is_ok());
+        assert_eq!(check_stream_name(&"x".repeat(MAX_STREAM_NAME_LEN + 1)), Err(StreamError::BadStringLength));
+    }
+
+    #[test]
+    fn unpack_rejects_stream_names_over_max_len() {
+        let instruction = StreamInstruction::RenameStream { stream_name: "x".repeat(MAX_STREAM_NAME_LEN + 1) };
+
+        assert!(StreamInstruction::unpack(&instruction.pack()).is_err());
+    }
+
+    #[test]
+    fn create_stream_with_funds_passes_the_pool_accounts() {
+        let treasurer_treasury_token = Pubkey::new_unique();
+        let treasury_mint = Pubkey::new_unique();
+        let instruction = create_stream_with_funds(
+            &crate::id(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            treasurer_treasury_token,
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            treasury_mint,
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            spl_token::id(),
+            "payroll".to_string(),
+            1.0,
+            60,
+            0,
+            0,
+            0.0,
+            0.0,
+            0,
+            10.0,
+            [0; 16]
+
+        ).unwrap();
+
+        assert_eq!(instruction.accounts.len(), 15);
+        assert_eq!(instruction.accounts[12].pubkey, treasurer_treasury_token);
+        assert_eq!(instruction.accounts[13].pubkey, treasury_mint);
+        assert_eq!(instruction.accounts[14].pubkey, spl_associated_token_account::id());
+    }
+
+    #[test]
+    fn propose_update_round_trips_its_expiration() {
+        let instruction = StreamInstruction::ProposeUpdate {
+            proposed_by: Pubkey::new_unique(),
+            stream_name: "payroll".to_string(),
+            treasurer_address: Pubkey::new_unique(),
+            beneficiary_address: Pubkey::new_unique(),
+            associated_token_address: Pubkey::default(),
+            rate_amount: 1.0,
+            rate_interval_in_seconds: 60,
+            rate_cliff_in_seconds: 0,
+            cliff_vest_amount: 0.0,
+            cliff_vest_percent: 0.0,
+            auto_pause_in_seconds: 0,
+            expiration_in_seconds: 86_400
+        };
+
+        match StreamInstruction::unpack(&instruction.pack()).unwrap() {
+            StreamInstruction::ProposeUpdate { expiration_in_seconds, .. } => assert_eq!(expiration_in_seconds, 86_400),
+            other => panic!("unpacked {}", other)
+        }
+    }
+
+    #[test]
+    fn create_treasury_is_based_on_the_treasurer() {
+        let treasurer = Pubkey::new_unique();
+        let instruction = create_treasury(&crate::id(), &treasurer, &Pubkey::new_unique(), &Pubkey::new_unique(), &spl_token::id(), 7, 0, 0, false).unwrap();
+
+        match StreamInstruction::unpack(&instruction.data).unwrap() {
+            StreamInstruction::CreateTreasury { treasury_base_address, .. } => assert_eq!(treasury_base_address, treasurer),
+            other => panic!("unpacked {}", other)
+        }
+
+        assert_eq!(instruction.accounts[0].pubkey, treasurer);
+        assert_eq!(instruction.accounts[1].pubkey, find_treasury_address(&treasurer, 7, &crate::id()).0);
+    }
+
+    #[test]
+    fn rate_for_streams_the_funding_over_the_duration() {
+        // 3_600 tokens over an hour, per minute
+        assert_eq!(rate_for(3_600.0, 3_600, 60), 60.0);
+        assert_eq!(rate_for(3_600.0, 3_600, 1), 1.0);
+        assert!((rate_for(100.0, 30 * SECONDS_PER_DAY, SECONDS_PER_DAY) * 30.0 - 100.0).abs() < 1e-9);
+        assert_eq!(rate_for(3_600.0, 0, 60), 0.0);
+    }
+
+    #[test]
+    fn rate_from_annual_pays_per_day_over_a_365_25_days_year() {
+        let (rate_amount, rate_interval_in_seconds) = rate_from_annual(52_560.0);
+
+        assert_eq!(rate_interval_in_seconds, SECONDS_PER_DAY);
+        assert!((rate_amount - 52_560.0 / 365.25).abs() < 1e-9);
+        assert!((rate_amount * 365.25 - 52_560.0).abs() < 1e-6);
+    }
+
+    #[cfg(feature = "chrono")]
+    #[test]
+    fn parse_start_utc_reads_rfc3339_dates() {
+        assert_eq!(parse_start_utc("2021-08-01T00:00:00Z"), Ok(1_627_776_000));
+        assert_eq!(parse_start_utc("2021-08-01T02:00:00+02:00"), Ok(1_627_776_000));
+        assert_eq!(parse_start_utc("1970-01-01T00:00:00Z"), Ok(0));
+        assert_eq!(parse_start_utc("1969-12-31T23:59:59Z"), Err(StreamError::InvalidArgument));
+        assert_eq!(parse_start_utc("2021-08-01"), Err(StreamError::InvalidArgument));
+    }
+
+    #[test]
+    fn parse_pubkey_reads_base58() {
+        let key = Pubkey::new_unique();
+        assert_eq!(parse_pubkey(&key.to_string()), Ok(key));
+        assert_eq!(parse_pubkey("11111111111111111111111111111111"), Ok(Pubkey::default()));
+        assert_eq!(parse_pubkey("not a pubkey"), Err(StreamError::InvalidArgument));
+        assert_eq!(parse_pubkey(""), Err(StreamError::InvalidArgument));
+    }
+
+    #[test]
+    fn contributor_pnl_is_signed_and_saturates() {
+        assert_eq!(contributor_pnl(1_000, 1_250), 250);
+        assert_eq!(contributor_pnl(1_000, 750), -250);
+        assert_eq!(contributor_pnl(1_000, 1_000), 0);
+        assert_eq!(contributor_pnl(0, u64::MAX), i64::MAX);
+        assert_eq!(contributor_pnl(u64::MAX, 0), i64::MIN);
+    }
+
+    #[test]
+    fn cliff_past_the_funded_duration_is_rejected() {
+        // 100 tokens at 1 token per hour last 100 hours
+        assert!(check_cliff_within_duration(100.0, 1.0, 3600, 100 * 3600).is_ok());
+        assert_eq!(check_cliff_within_duration(100.0, 1.0, 3600, 100 * 3600 + 1), Err(StreamError::CliffExceedsDuration));
+    }
+
+    #[test]
+    fn renew_stream_appends_the_signing_beneficiary() {
+        let renew = |current_beneficiary_address| renew_stream(
+            &crate::id(), Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(),
+            Pubkey::new_unique(), Pubkey::new_unique(), "renewed".to_string(), 1.0, 60, 0, 0, 0.0, 0.0, 0,
+            current_beneficiary_address
+        ).unwrap();
+
+        assert_eq!(renew(None).accounts.len(), 8);
+
+        let beneficiary = Pubkey::new_unique();
+        let instruction = renew(Some(beneficiary));
+        assert_eq!(instruction.accounts.len(), 9);
+        assert_eq!(instruction.accounts[8].pubkey, beneficiary);
+        assert!(instruction.accounts[8].is_signer);
+    }
+
+    #[test]
+    fn add_funds_lists_the_accounts_in_the_handler_order() {
+        let keys: Vec<Pubkey> = (0..10).map(|_| Pubkey::new_unique()).collect();
+        let ix = add_funds(
+            &crate::id(), keys[0], keys[1], keys[2], keys[3], keys[4], keys[5], keys[6], keys[7], keys[8], keys[9],
+            spl_token::id(), 1.0, 0, false
+        ).unwrap();
+
+        let expected = [
+            &keys[..],
+            &[crate::id(), spl_token::id(), spl_associated_token_account::id(), solana_program::system_program::id(), solana_program::sysvar::rent::id()]
+        ].concat();
+
+        assert_eq!(ix.accounts.iter().map(|account| account.pubkey).collect::<Vec<_>>(), expected);
+        assert!(ix.accounts[0].is_signer);
+        assert!(ix.accounts[1..].iter().all(|account| !account.is_signer));
+        assert!(!ix.accounts[4].is_writable);
+    }
+
+    #[test]
+    fn withdraw_lists_the_accounts_in_the_handler_order() {
+        let keys: Vec<Pubkey> = (0..8).map(|_| Pubkey::new_unique()).collect();
+        let ix = withdraw(
+            &crate::id(), keys[0], keys[1], keys[2], keys[3], keys[4], keys[5], keys[6], keys[7], spl_token::id(), 1.0, None
+        ).unwrap();
+
+        let expected = [&keys[..], &[crate::id(), spl_token::id()]].concat();
+
+        assert_eq!(ix.accounts.iter().map(|account| account.pubkey).collect::<Vec<_>>(), expected);
+        assert!(ix.accounts[0].is_signer);
+        assert!(ix.accounts[1..].iter().all(|account| !account.is_signer));
+        assert!(ix.accounts[1].is_writable && ix.accounts[4].is_writable && ix.accounts[5].is_writable);
+        assert_eq!(StreamInstruction::unpack(&ix.data).unwrap().to_string(), "Withdraw{withdrawal_amount: 1}");
+    }
+
+    #[test]
+    fn withdraw_builders_append_the_hook_program() {
+        let hook_program = Pubkey::new_unique();
+        let withdraw_max_with = |hook_program| withdraw_max(
+            &crate::id(), Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(),
+            Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(), spl_token::id(),
+            hook_program
+        ).unwrap();
+
+        assert_eq!(withdraw_max_with(None).accounts.len(), 10);
+
+        let instruction = withdraw_max_with(Some(hook_program));
+        assert_eq!(instruction.accounts.len(), 11);
+        assert_eq!(instruction.accounts[10].pubkey, hook_program);
+        assert!(!instruction.accounts[10].is_writable);
+        assert_eq!(instruction.data, StreamInstruction::WithdrawMax.pack());
+    }
+
+    #[test]
+    fn builders_are_only_built_for_this_program() {
+        let key = Pubkey::new_unique();
+        let builds_for = |program_id: &Pubkey| vec![
+            get_version(program_id, &key).map(|_| ()),
+            set_auto_pause_threshold(program_id, &key, &key, &key, 0).map(|_| ()),
+            set_beneficiary_token_account(program_id, &key, &key, &key, &key).map(|_| ()),
+            transfer_stream_ownership(program_id, &key, &key, &key, key).map(|_| ()),
+            close_treasury(program_id, &key, &key, &key, &key).map(|_| ())
+        ];
+
+        for built in builds_for(&crate::id()) {
+            assert_eq!(built, Ok(()));
+        }
+
+        for built in builds_for(&spl_token::id()) {
+            assert_eq!(built, Err(StreamError::IncorrectProgramId));
+        }
+    }
+
+    #[test]
+    fn builders_pass_the_given_token_program() {
+        let key = Pubkey::new_unique;
+        let close_stream_with = |token_program_id| close_stream(
+            &crate::id(), key(), key(), key(), key(), key(), key(), key(), key(), key(), token_program_id
+        );
+        let multi_withdraw_with = |token_program_id| multi_withdraw(
+            &crate::id(), key(), key(), key(), key(), key(), token_program_id, &[(key(), key(), key())]
+        );
+        let create_treasury_with = |token_program_id| create_treasury(
+            &crate::id(), &key(), &key(), &key(), &token_program_id, 7, 0, 0, false
+        );
+
+        for token_program_id in [spl_token::id(), spl_token_2022::id()] {
+            assert_eq!(close_stream_with(token_program_id).unwrap().accounts[10].pubkey, token_program_id);
+            assert_eq!(multi_withdraw_with(token_program_id).unwrap().accounts[6].pubkey, token_program_id);
+            assert_eq!(create_treasury_with(token_program_id).unwrap().accounts[7].pubkey, token_program_id);
+        }
+
+        let unknown = key();
+        assert_eq!(close_stream_with(unknown).unwrap_err(), StreamError::IncorrectProgramId);
+        assert_eq!(multi_withdraw_with(unknown).unwrap_err(), StreamError::IncorrectProgramId);
+        assert_eq!(create_treasury_with(unknown).unwrap_err(), StreamError::IncorrectProgramId);
+    }
+
+    #[test]
+    fn packed_len_is_the_packed_size() {
+        let mut instructions = one_of_each();
+        instructions.push(StreamInstruction::SetWithdrawHook { hook_program: None });
+        instructions.push(StreamInstruction::RenameStream { stream_name: String::new() });
+        instructions.push(StreamInstruction::RenameStream { stream_name: "ñ".repeat(MAX_STREAM_NAME_LEN / 2) });
+
+        for instruction in &instructions {
+            assert_eq!(instruction.pack().len(), instruction.packed_len(), "{}", instruction);
+        }
+    }
+
+    #[test]
+    fn malformed_instructions_report_what_is_malformed() {
+        let unpack = |data: &[u8]| StreamInstruction::unpack(data).err();
+
+        assert_eq!(unpack(&[]), Some(StreamError::InvalidStreamInstruction));
+        assert_eq!(unpack(&[u8::MAX]), Some(StreamError::UnknownInstructionTag));
+        assert_eq!(unpack(&[TAG_TRANSFER_STREAM_OWNERSHIP, 1, 2, 3]), Some(StreamError::TruncatedPubkey));
+        assert_eq!(unpack(&[TAG_WITHDRAW, 0, 0, 0, 0, 0, 0, 0]), Some(StreamError::TruncatedAmount));
+
+        // A name length of 8 with 3 bytes of name, then a length prefix cut short
+        let mut create_stream = vec![TAG_CREATE_STREAM];
+        create_stream.extend_from_slice(Pubkey::new_unique().as_ref());
+        let with_name = |name: &[u8]| [&create_stream[..], name].concat();
+
+        assert_eq!(unpack(&with_name(&[8, 0, 0, 0, b'a', b'b', b'c'])), Some(StreamError::BadStringLength));
+        assert_eq!(unpack(&with_name(&[8, 0])), Some(StreamError::BadStringLength));
+    }
+
+    #[test]
+    fn zero_rate_intervals_are_rejected_on_unpack() {
+        for mut instruction in one_of_each() {
+            match &mut instruction {
+                StreamInstruction::CreateStream { rate_interval_in_seconds, .. } |
+                StreamInstruction::ProposeUpdate { rate_interval_in_seconds, .. } |
+                StreamInstruction::RenewStream { rate_interval_in_seconds, .. } => *rate_interval_in_seconds = 0,
+                _ => continue
+            }
+
+            assert_eq!(StreamInstruction::unpack(&instruction.pack()).err(), Some(StreamError::InvalidRateInterval), "{}", instruction);
+        }
+    }
+
+    #[test]
+    fn unpack_strict_takes_exactly_what_pack_writes() {
+        for instruction in one_of_each() {
+            let mut packed = instruction.pack();
+            assert!(StreamInstruction::unpack_strict(&packed).is_ok(), "{}", instruction);
+
+            packed.push(0);
+            assert_eq!(StreamInstruction::unpack_strict(&packed).err(), Some(StreamError::InvalidStreamInstruction), "{}", instruction);
+        }
+    }
+
+    #[test]
+    fn unpack_strict_rejects_trailing_bytes() {
+        let create_stream = one_of_each().remove(0).pack();
+        let truncated = &create_stream[..create_stream.len() - 1];
+
+        assert_eq!(StreamInstruction::unpack(truncated).err(), Some(StreamError::TruncatedAmount));
+        assert_eq!(StreamInstruction::unpack_strict(truncated).err(), Some(StreamError::TruncatedAmount));
+
+        let mut trailing = create_stream.clone();
+        trailing.push(0);
+
+        assert!(StreamInstruction::unpack(&trailing).is_ok());
+        assert_eq!(StreamInstruction::unpack_strict(&trailing).err(), Some(StreamError::InvalidStreamInstruction));
+        assert_eq!(StreamInstruction::unpack_strict(&[u8::MAX]).err(), Some(StreamError::UnknownInstructionTag));
+    }
+
+    #[test]
+    fn oversized_batches_are_rejected() {
+        let payer = Pubkey::new_unique();
+        let pause = || set_auto_pause_threshold(&crate::id(), &payer, &Pubkey::new_unique(), &Pubkey::new_unique(), 0).unwrap();
+
+        let batch: Vec<Instruction> = (0..MAX_BATCH_SIZE).map(|_| pause()).collect();
+        assert_eq!(check_batch_size(&batch[..4], &payer), Ok(()));
+
+        let batch: Vec<Instruction> = (0..MAX_BATCH_SIZE + 1).map(|_| pause()).collect();
+        assert_eq!(check_batch_size(&batch, &payer), Err(StreamError::BatchTooLarge));
+    }
+
+    #[test]
+    fn bulk_streams_have_to_fit_in_a_transaction() {
+        let params = CreateStreamParams {
+            treasurer_address: Pubkey::new_unique(),
+            beneficiary_mint_address: Pubkey::new_unique(),
+            treasury_address: Pubkey::new_unique(),
+            msp_ops_address: Pubkey::new_unique(),
+            stream_name: "payroll".to_string(),
+            rate_amount: 1.0,
+            rate_interval_in_seconds: 60,
+            start_utc: 0,
+            rate_cliff_in_seconds: 0,
+            cliff_vest_amount: 0.0,
+            cliff_vest_percent: 0.0,
+            auto_pause_in_seconds: 0
+        };
+        let beneficiaries = |count| (0..count).map(|_| (Pubkey::new_unique(), Pubkey::new_unique())).collect::<Vec<_>>();
+
+        assert_eq!(create_streams_bulk(&crate::id(), &params, &beneficiaries(2)).unwrap().len(), 2);
+        assert_eq!(
+            create_streams_bulk(&crate::id(), &params, &beneficiaries(MAX_BATCH_SIZE)).unwrap_err(),
+            StreamError::BatchTooLarge
+        );
+    }
+
+    #[test]
+    fn multi_withdraw_count_is_bounded_on_unpack() {
+        for (count, valid) in [(0, false), (1, true), (MAX_MULTI_WITHDRAW_STREAMS, true), (MAX_MULTI_WITHDRAW_STREAMS + 1, false)].iter() {
+            let unpacked = StreamInstruction::unpack(&[TAG_MULTI_WITHDRAW, *count]);
+            assert_eq!(unpacked.is_ok(), *valid, "{}", count);
+        }
+    }
+
+    #[test]
+    fn funding_has_to_last_one_auto_pause_window() {
+        // 100 tokens at 1 token every 10 seconds last 1_000 seconds
+        assert_eq!(check_rate_for_funding(100.0, 1.0, 10, 1_000), Ok(()));
+        assert_eq!(check_rate_for_funding(100.0, 1.0, 10, 1_001), Err(StreamError::RateTooHighForFunding));
+
+        // Nothing to check without funding, auto pause or interval
+        assert_eq!(check_rate_for_funding(0.0, 1.0, 10, 1_001), Ok(()));
+        assert_eq!(check_rate_for_funding(100.0, 1.0, 10, 0), Ok(()));
+        assert_eq!(check_rate_for_funding(100.0, 1.0, 0, 1_001), Ok(()));
+    }
+
+    #[test]
+    fn streams_created_with_a_client_ref_are_not_signers() {
+        let build = |client_ref: [u8; 16]| create_stream(
+            &crate::id(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            "stream".to_string(),
+            1.0,
+            10,
+            1_000,
+            0,
+            0.0,
+            0.0,
+            0,
+            client_ref
+        ).unwrap();
+
+        assert!(build([0; 16]).accounts[3].is_signer);
+
+        let instruction = build([7; 16]);
+        assert!(!instruction.accounts[3].is_signer);
+        match StreamInstruction::unpack(&instruction.data)
+        {
+            Ok(StreamInstruction::CreateStream { client_ref, .. }) => assert_eq!(client_ref, [7; 16]),
+            _ => panic!("expected a CreateStream instruction")
+        }
+    }
+
+    #[test]
+    fn start_utc_has_to_be_within_the_offset_either_way() {
+        let now_utc = 2_000_000_000;
+
+        assert_eq!(check_start_utc(now_utc, now_utc), Ok(()));
+        assert_eq!(check_start_utc(now_utc + MAX_START_UTC_OFFSET_IN_SECONDS, now_utc), Ok(()));
+        assert_eq!(check_start_utc(now_utc - MAX_START_UTC_OFFSET_IN_SECONDS, now_utc), Ok(()));
+        assert_eq!(
+            check_start_utc(now_utc + MAX_START_UTC_OFFSET_IN_SECONDS + 1, now_utc),
+            Err(StreamError::StartTimeOutOfRange)
+        );
+        assert_eq!(
+            check_start_utc(now_utc - MAX_START_UTC_OFFSET_IN_SECONDS - 1, now_utc),
+            Err(StreamError::StartTimeOutOfRange)
+        );
+        assert_eq!(check_start_utc(u64::MAX, now_utc), Err(StreamError::StartTimeOutOfRange));
+    }
+
+    #[test]
+    fn accounts_are_not_reused_across_roles() {
+        let (first, second) = (Pubkey::new_unique(), Pubkey::new_unique());
+
+        assert_eq!(check_unique_accounts(&[AccountMeta::new(first, true), AccountMeta::new(second, false)]), Ok(()));
+        assert_eq!(
+            check_unique_accounts(&[AccountMeta::new(first, true), AccountMeta::new_readonly(first, false)]),
+            Err(StreamError::DuplicateAccount)
+        );
+
+        // Unset optional accounts
+        assert_eq!(
+            check_unique_accounts(&[AccountMeta::new(Pubkey::default(), false), AccountMeta::new(Pubkey::default(), false)]),
+            Ok(())
+        );
+
+        let beneficiary = Pubkey::new_unique();
+        let withdrawal = |beneficiary_token_address: Pubkey| withdraw(
+            &crate::id(),
+            beneficiary,
+            beneficiary_token_address,
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            spl_token::id(),
+            1.0,
+            None
+        );
+
+        assert!(withdrawal(Pubkey::new_unique()).is_ok());
+        assert_eq!(withdrawal(beneficiary).err(), Some(StreamError::DuplicateAccount));
+    }
+}
\ No newline at end of file
@@ -4,6 +4,11 @@ pub mod instruction;
 pub mod processor;
 pub mod state;
 pub mod constants;
+pub mod pda;
+pub mod amount;
+
+#[cfg(feature = "events")]
+pub mod events;
 
 #[cfg(not(feature = "no-entrypoint"))]
 pub mod entrypoint;
@@ -11,7 +16,6 @@ pub use solana_program;
 
 use solana_program::{
     declare_id,
-    entrypoint::ProgramResult,
     pubkey::Pubkey
 };
 
@@ -19,9 +23,20 @@ use crate::error::StreamError;
 
 declare_id!("H6wJxgkcc93yeUFnsZHgor3Q3pSWgGpEysfqKrwLtMko");
 
-pub fn check_program_account(program_id: &Pubkey) -> ProgramResult {
+/// `[major, minor, patch]` returned by the `GetVersion` instruction
+pub const PROGRAM_VERSION: [u8; 3] = [1, 1, 0];
+
+pub fn check_program_account(program_id: &Pubkey) -> Result<(), StreamError> {
     if program_id != &id() {
-        return Err(StreamError::IncorrectProgramId.into());
+        return Err(StreamError::IncorrectProgramId);
+    }
+    Ok(())
+}
+
+/// Fails unless `token_program_id` is the SPL Token or the Token-2022 program
+pub fn check_token_program_account(token_program_id: &Pubkey) -> Result<(), StreamError> {
+    if token_program_id != &spl_token::id() && token_program_id != &spl_token_2022::id() {
+        return Err(StreamError::IncorrectProgramId);
     }
     Ok(())
 }
\ No newline at end of file
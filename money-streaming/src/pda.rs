@@ -0,0 +1,97 @@
+// Program derived addresses. The treasury seeds include the treasury block height (the
+// `CreateTreasury` nonce) and only streams created with a `client_ref` are at a PDA
+
+use solana_program::pubkey::Pubkey;
+
+/// Finds the treasury (pool) account address and its bump seed
+pub fn find_treasury_address(
+    treasury_base_address: &Pubkey,
+    treasury_block_height: u64,
+    program_id: &Pubkey
+
+) -> (Pubkey, u8) {
+
+    Pubkey::find_program_address(
+        &[
+            treasury_base_address.as_ref(),
+            &treasury_block_height.to_le_bytes()
+        ], 
+        program_id
+    )
+}
+
+/// Finds the address of a stream created with a `client_ref` idempotency key and its bump seed
+pub fn find_stream_address(
+    treasury_address: &Pubkey,
+    client_ref: &[u8; 16],
+    program_id: &Pubkey
+
+) -> (Pubkey, u8) {
+
+    Pubkey::find_program_address(
+        &[
+            treasury_address.as_ref(),
+            client_ref
+        ], 
+        program_id
+    )
+}
+
+/// Finds the treasury pool mint address and its bump seed
+pub fn find_treasury_mint_address(
+    treasury_base_address: &Pubkey,
+    treasury_address: &Pubkey,
+    treasury_block_height: u64,
+    program_id: &Pubkey
+
+) -> (Pubkey, u8) {
+
+    Pubkey::find_program_address(
+        &[
+            treasury_base_address.as_ref(),
+            treasury_address.as_ref(),
+            &treasury_block_height.to_le_bytes()
+        ], 
+        program_id
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn treasury_address_is_deterministic_and_reproduced_by_its_bump() {
+        let treasurer = Pubkey::new_unique();
+        let (treasury_address, bump_seed) = find_treasury_address(&treasurer, 42, &crate::id());
+
+        assert_eq!(find_treasury_address(&treasurer, 42, &crate::id()), (treasury_address, bump_seed));
+        assert_ne!(find_treasury_address(&treasurer, 43, &crate::id()).0, treasury_address);
+
+        let seeds: &[&[u8]] = &[treasurer.as_ref(), &42u64.to_le_bytes(), &[bump_seed]];
+        assert_eq!(Pubkey::create_program_address(seeds, &crate::id()).unwrap(), treasury_address);
+    }
+
+    #[test]
+    fn stream_address_is_deterministic_and_reproduced_by_its_bump() {
+        let treasury = Pubkey::new_unique();
+        let client_ref = [7u8; 16];
+        let (stream_address, bump_seed) = find_stream_address(&treasury, &client_ref, &crate::id());
+
+        assert_eq!(find_stream_address(&treasury, &client_ref, &crate::id()), (stream_address, bump_seed));
+        assert_ne!(find_stream_address(&treasury, &[8u8; 16], &crate::id()).0, stream_address);
+
+        let seeds: &[&[u8]] = &[treasury.as_ref(), &client_ref, &[bump_seed]];
+        assert_eq!(Pubkey::create_program_address(seeds, &crate::id()).unwrap(), stream_address);
+    }
+
+    #[test]
+    fn treasury_mint_address_is_reproduced_by_its_bump() {
+        let treasurer = Pubkey::new_unique();
+        let (treasury_address, _) = find_treasury_address(&treasurer, 42, &crate::id());
+        let (mint_address, bump_seed) = find_treasury_mint_address(&treasurer, &treasury_address, 42, &crate::id());
+
+        let seeds: &[&[u8]] = &[treasurer.as_ref(), treasury_address.as_ref(), &42u64.to_le_bytes(), &[bump_seed]];
+        assert_eq!(Pubkey::create_program_address(seeds, &crate::id()).unwrap(), mint_address);
+    }
+}
\ No newline at end of file
@@ -6,18 +6,37 @@ use num_traits;
 use solana_program::{
     msg,
     system_instruction,
-    program::{ invoke, invoke_signed },
+    program::{ invoke, invoke_signed, set_return_data },
     pubkey::Pubkey,
     entrypoint::ProgramResult,
+    program_error::ProgramError,
+    program_option::COption,
     account_info::{ next_account_info, AccountInfo },
     program_pack::{ IsInitialized, Pack },
     sysvar::{ clock::Clock, rent::Rent, Sysvar } 
 };
 
+use spl_token_2022::extension::{
+    BaseStateWithExtensions,
+    ExtensionType,
+    StateWithExtensions,
+    transfer_fee::{ TransferFeeConfig, instruction::transfer_checked_with_fee }
+};
+
 use crate::{
+    PROGRAM_VERSION,
     error::StreamError,
-    instruction::{ StreamInstruction },
-    state::{ Stream, StreamTerms, Treasury },
+    instruction::{ 
+        StreamInstruction, 
+        withdraw_hook, 
+        check_rate_for_funding, 
+        check_cliff_within_duration, 
+        check_start_utc,
+        check_stream_name
+    },
+    amount::to_base_amount,
+    pda::{ find_treasury_address, find_treasury_mint_address, find_stream_address },
+    state::{ Stream, StreamTerms, Treasury, PauseReason, CURRENT_VERSION, STREAM_TERMS_VERSION },
     constants::{ 
         CREATE_STREAM_FLAT_FEE,
         ADD_FUNDS_PERCENT_FEE,
@@ -26,10 +45,15 @@ use crate::{
         CLOSE_STREAM_FLAT_FEE,
         CLOSE_STREAM_PERCENT_FEE, 
         LAMPORTS_PER_SOL, 
-        TREASURY_MINT_DECIMALS 
+        TREASURY_MINT_DECIMALS,
+        PROPOSAL_EXPIRATION_IN_SECONDS,
+        MAX_MULTI_WITHDRAW_STREAMS
     }
 };
 
+#[cfg(feature = "events")]
+use crate::events::StreamEvent;
+
 pub struct Processor {}
 
 impl Processor {
@@ -43,6 +67,10 @@ impl Processor {
 
         let instruction = StreamInstruction::unpack(instruction_data)?;
 
+        // Logs the decoded instruction with its arguments
+        #[cfg(feature = "verbose-logs")]
+        msg!("Decoded instruction: {}", instruction);
+
         match instruction {
 
             StreamInstruction::CreateStream {
@@ -54,7 +82,9 @@ impl Processor {
                 rate_cliff_in_seconds,
                 cliff_vest_amount,
                 cliff_vest_percent,
-                auto_pause_in_seconds
+                auto_pause_in_seconds,
+                funding_amount,
+                client_ref
 
             } => {
 
@@ -71,7 +101,9 @@ impl Processor {
                     rate_cliff_in_seconds,
                     cliff_vest_amount,
                     cliff_vest_percent,
-                    auto_pause_in_seconds
+                    auto_pause_in_seconds,
+                    funding_amount,
+                    client_ref
                 )
             },
 
@@ -108,7 +140,7 @@ impl Processor {
                 Self::process_withdraw(
                     accounts, 
                     program_id, 
-                    withdrawal_amount
+                    Some(withdrawal_amount)
                 )
             },
 
@@ -141,7 +173,8 @@ impl Processor {
                 rate_cliff_in_seconds,
                 cliff_vest_amount,
                 cliff_vest_percent,
-                auto_pause_in_seconds
+                auto_pause_in_seconds,
+                expiration_in_seconds
 
             } => {
 
@@ -160,7 +193,8 @@ impl Processor {
                     rate_cliff_in_seconds,
                     cliff_vest_amount,
                     cliff_vest_percent,
-                    auto_pause_in_seconds
+                    auto_pause_in_seconds,
+                    expiration_in_seconds
                 )                
             },
 
@@ -185,7 +219,9 @@ impl Processor {
 
             StreamInstruction::CreateTreasury { 
                 treasury_block_height,
-                treasury_base_address
+                treasury_base_address,
+                funding_increment,
+                max_streams
 
             } => {
                 msg!("Instruction: CreateTreasury");
@@ -194,7 +230,9 @@ impl Processor {
                     accounts, 
                     program_id,
                     treasury_block_height,
-                    treasury_base_address
+                    treasury_base_address,
+                    funding_increment,
+                    max_streams
                 )
             },
 
@@ -207,6 +245,153 @@ impl Processor {
                     amount
                 )
             },
+
+            StreamInstruction::SetBeneficiaryTokenAccount { token_account } => {
+                msg!("Instruction: SetBeneficiaryTokenAccount");
+
+                Self::process_set_beneficiary_token_account(
+                    accounts, 
+                    program_id,
+                    token_account
+                )
+            },
+
+            StreamInstruction::TransferStreamOwnership { new_treasurer } => {
+                msg!("Instruction: TransferStreamOwnership");
+
+                Self::process_transfer_stream_ownership(
+                    accounts, 
+                    program_id,
+                    new_treasurer
+                )
+            },
+
+            StreamInstruction::ExpireProposal => {
+                msg!("Instruction: ExpireProposal");
+
+                Self::process_expire_proposal(
+                    accounts, 
+                    program_id
+                )
+            },
+
+            StreamInstruction::CloseTreasury => {
+                msg!("Instruction: CloseTreasury");
+
+                Self::process_close_treasury(
+                    accounts, 
+                    program_id
+                )
+            },
+
+            StreamInstruction::SetAutoPauseThreshold { seconds } => {
+                msg!("Instruction: SetAutoPauseThreshold");
+
+                Self::process_set_auto_pause_threshold(
+                    accounts, 
+                    program_id,
+                    seconds
+                )
+            },
+
+            StreamInstruction::RenewStream {
+                beneficiary_address,
+                stream_name,
+                rate_amount,
+                rate_interval_in_seconds,
+                start_utc,
+                rate_cliff_in_seconds,
+                cliff_vest_amount,
+                cliff_vest_percent,
+                auto_pause_in_seconds
+
+            } => {
+
+                msg!("Instruction: RenewStream");
+
+                Self::process_renew_stream(
+                    accounts, 
+                    program_id,
+                    beneficiary_address,
+                    stream_name,
+                    rate_amount,
+                    rate_interval_in_seconds,
+                    start_utc,
+                    rate_cliff_in_seconds,
+                    cliff_vest_amount,
+                    cliff_vest_percent,
+                    auto_pause_in_seconds
+                )
+            },
+
+            StreamInstruction::WithdrawMax => {
+                msg!("Instruction: WithdrawMax");
+                
+                Self::process_withdraw(
+                    accounts, 
+                    program_id, 
+                    None
+                )
+            },
+
+            StreamInstruction::SetWithdrawHook { hook_program } => {
+                msg!("Instruction: SetWithdrawHook");
+
+                Self::process_set_withdraw_hook(
+                    accounts, 
+                    program_id,
+                    hook_program
+                )
+            },
+
+            StreamInstruction::RenameStream { stream_name } => {
+                msg!("Instruction: RenameStream");
+
+                Self::process_rename_stream(
+                    accounts, 
+                    program_id,
+                    stream_name
+                )
+            },
+
+            StreamInstruction::MultiWithdraw { count } => {
+                msg!("Instruction: MultiWithdraw");
+
+                Self::process_multi_withdraw(
+                    accounts, 
+                    program_id,
+                    count
+                )
+            },
+
+            StreamInstruction::DonateStream { new_treasurer, new_beneficiary } => {
+                msg!("Instruction: DonateStream");
+
+                Self::process_donate_stream(
+                    accounts, 
+                    program_id,
+                    new_treasurer,
+                    new_beneficiary
+                )
+            },
+
+            StreamInstruction::TopUp { amount, min_new_depletion_utc } => {
+                msg!("Instruction: TopUp");
+
+                Self::process_top_up(
+                    accounts, 
+                    program_id,
+                    amount,
+                    min_new_depletion_utc
+                )
+            },
+
+            StreamInstruction::GetVersion => {
+                msg!("Instruction: GetVersion");
+                set_return_data(&PROGRAM_VERSION);
+
+                Ok(())
+            },
         }
     }
 
@@ -221,10 +406,14 @@ impl Processor {
         rate_cliff_in_seconds: u64,
         cliff_vest_amount: f64,
         cliff_vest_percent: f64,
-        auto_pause_in_seconds: u64
+        auto_pause_in_seconds: u64,
+        funding_amount: f64,
+        client_ref: [u8; 16]
         
     ) -> ProgramResult {
 
+        require_accounts(accounts, 8)?;
+
         let account_info_iter = &mut accounts.iter();
         let treasurer_account_info = next_account_info(account_info_iter)?;
         let treasury_account_info = next_account_info(account_info_iter)?;
@@ -242,12 +431,24 @@ impl Processor {
             return Err(StreamError::MissingInstructionSignature.into());
         }
 
-        if treasury_account_info.owner != program_id
+        check_stream_name(&stream_name)?;
+
+        require_program_owned(treasury_account_info, program_id)?;
+        require_treasury_layout(treasury_account_info)?;
+
+        let treasury = Treasury::unpack_any_len(&treasury_account_info.data.borrow())?;
+
+        if treasury.treasury_base_address.ne(treasurer_account_info.key)
         {
-            return Err(StreamError::InstructionNotAuthorized.into());
+            return Err(StreamError::InstructionNotAuthorized.into()); // Only the treasurer can stream from its treasury
+        }
+
+        if treasury.max_streams != 0 && treasury.streams_count >= treasury.max_streams
+        {
+            return Err(StreamError::TooManyStreams.into());
         }
 
-        let stream_balance = rent.minimum_balance(Stream::LEN);
+        let stream_balance = Stream::rent_exempt_lamports(rent);
         let create_stream_ix = system_instruction::create_account(
             treasurer_account_info.key,
             stream_account_info.key,
@@ -256,17 +457,54 @@ impl Processor {
             msp_account_info.key
         );
 
-        invoke(&create_stream_ix, &[
-            treasurer_account_info.clone(),
-            stream_account_info.clone(),
-            msp_account_info.clone(),
-            system_account_info.clone()
-        ]);
+        if client_ref == [0; 16]
+        {
+            invoke(&create_stream_ix, &[
+                treasurer_account_info.clone(),
+                stream_account_info.clone(),
+                msp_account_info.clone(),
+                system_account_info.clone()
+            ]);
+        }
+        else
+        {
+            let (stream_address, stream_bump_seed) = find_stream_address(
+                treasury_account_info.key,
+                &client_ref,
+                program_id
+            );
+
+            if stream_address.ne(stream_account_info.key)
+            {
+                return Err(StreamError::InvalidStreamData.into());
+            }
+
+            // A retry with the same key finds the stream already created
+            if stream_account_info.lamports() > 0 || !stream_account_info.data_is_empty()
+            {
+                return Err(StreamError::StreamAlreadyInitialized.into());
+            }
+
+            let stream_signer_seed: &[&[_]] = &[
+                treasury_account_info.key.as_ref(),
+                &client_ref,
+                &[stream_bump_seed]
+            ];
+
+            invoke_signed(&create_stream_ix, &[
+                treasurer_account_info.clone(),
+                stream_account_info.clone(),
+                msp_account_info.clone(),
+                system_account_info.clone()
+            ],
+            &[stream_signer_seed])?;
+        }
 
         msg!("Stream account created with address: {:?}", (*stream_account_info.key).to_string());
         let mut stream = Stream::unpack_from_slice(&stream_account_info.data.borrow())?;
 
         // Updating stream data
+        stream.version = CURRENT_VERSION;
         stream.stream_name = stream_name;
         stream.treasurer_address = *treasurer_account_info.key;
         stream.rate_amount = rate_amount;
@@ -284,18 +522,61 @@ impl Processor {
         stream.total_withdrawals = 0.0;
         stream.escrow_vested_amount_snap = 0.0;
         stream.escrow_vested_amount_snap_block_height = clock.slot as u64;
-        stream.escrow_vested_amount_snap_block_time = clock.unix_timestamp as u64;
+        // Nothing has vested yet, dating the snapshot before the cliff end keeps the cliff amount
+        // to be released even when the cliff is already over
+        stream.escrow_vested_amount_snap_block_time = cmp::min(
+            clock.unix_timestamp as u64,
+            stream.cliff_end_utc().saturating_sub(1)
+        );
         stream.stream_resumed_block_height = 0;
         stream.stream_resumed_block_time = 0;
+        stream.client_ref = client_ref;
 
         if auto_pause_in_seconds != 0 
         {
             stream.auto_pause_in_seconds = auto_pause_in_seconds;
         }
 
+        check_cliff_before_depletion(&stream, funding_amount)?;
+        check_new_stream_terms(&stream, funding_amount, clock.unix_timestamp as u64)?;
+
+        if funding_amount > 0.0
+        {
+            Self::fund_new_stream(
+                account_info_iter,
+                program_id,
+                treasurer_account_info,
+                treasury_account_info,
+                beneficiary_mint_account_info,
+                msp_ops_account_info,
+                system_account_info,
+                rent_account_info,
+                &mut stream,
+                funding_amount,
+                &clock
+            )?;
+        }
+
         stream.initialized = true;                
+        stream.last_activity_utc = clock.unix_timestamp as u64;
         Stream::pack_into_slice(&stream, &mut stream_account_info.data.borrow_mut());
 
+        #[cfg(feature = "events")]
+        StreamEvent::StreamCreated {
+            stream_address: *stream_account_info.key,
+            treasurer_address: *treasurer_account_info.key,
+            beneficiary_address: stream.beneficiary_address,
+            total_deposits: stream.total_deposits
+
+        }.emit()?;
+
+        let mut treasury = Treasury::unpack_any_len(&treasury_account_info.data.borrow())?;
+        treasury.streams_count = treasury.streams_count
+            .checked_add(1)
+            .ok_or(StreamError::Overflow)?;
+
+        treasury.pack_any_len(&mut treasury_account_info.data.borrow_mut())?;
+
         // Debit Fees from treasurer
         let fees_lamports = CREATE_STREAM_FLAT_FEE * (LAMPORTS_PER_SOL as f64);
         let fees_transfer_ix = system_instruction::transfer(
@@ -327,6 +608,8 @@ impl Processor {
 
     ) -> ProgramResult {
 
+        require_accounts(accounts, 15)?;
+
         let account_info_iter = &mut accounts.iter();
         let contributor_account_info = next_account_info(account_info_iter)?;
         let contributor_token_account_info = next_account_info(account_info_iter)?;
@@ -350,35 +633,33 @@ impl Processor {
             return Err(StreamError::MissingInstructionSignature.into());
         }
 
-        if treasury_account_info.owner != program_id || stream_account_info.owner != program_id
-        {
-            return Err(StreamError::InstructionNotAuthorized.into());
-        }
+        require_any_token_program(token_program_account_info)?;
+
+        require_program_owned(treasury_account_info, program_id)?;
+        require_treasury_layout(treasury_account_info)?;
+        require_program_owned(stream_account_info, program_id)?;
+        require_account_layout(stream_account_info, Stream::LEN)?;
 
         // Check is the stream needs to be paused because of lacks of funds
         let mut stream = Stream::unpack_from_slice(&stream_account_info.data.borrow())?;
         let current_block_height = clock.slot as u64;
         let current_block_time = clock.unix_timestamp as u64;
-        let is_running = (stream.stream_resumed_block_time >= stream.escrow_vested_amount_snap_block_time) as u64;
-        let rate = stream.rate_amount / (stream.rate_interval_in_seconds as f64) * (is_running as f64);
-        let marker_block_time = cmp::max(stream.stream_resumed_block_time, stream.escrow_vested_amount_snap_block_time);
-        let elapsed_time = (current_block_time - marker_block_time) as f64;
-        let mut escrow_vested_amount = stream.escrow_vested_amount_snap + rate * elapsed_time;
+        let escrow_vested_amount = stream.vested_amount_from_snapshot(current_block_time);
         let no_funds = (escrow_vested_amount >= stream.total_deposits - stream.total_withdrawals) as u64;
 
-        // Pause if no funds and it is running before
-        if no_funds == 1
+        // Pause if no funds and it is running before. A stream never funded has nothing to
+        // snapshot (and its cliff amount still has to be released)
+        if no_funds == 1 && stream.total_deposits > 0.0
         {
-            escrow_vested_amount = stream.total_deposits - stream.total_withdrawals;
-            stream.escrow_vested_amount_snap = escrow_vested_amount;
-            stream.escrow_vested_amount_snap_block_height = current_block_height;
-            stream.escrow_vested_amount_snap_block_time = current_block_time;
+            let escrow_vested_amount = stream.total_deposits - stream.total_withdrawals;
+            pause_stream(&mut stream, escrow_vested_amount, PauseReason::AutoLowFunds, current_block_height, current_block_time);
         }
 
         // Create treasury associated token account if doesn't exist
-        let treasury_token_address = spl_associated_token_account::get_associated_token_address(
+        let treasury_token_address = spl_associated_token_account::get_associated_token_address_with_program_id(
             treasury_account_info.key,
-            beneficiary_mint_account_info.key
+            beneficiary_mint_account_info.key,
+            token_program_account_info.key
         );
 
         if treasury_token_address != *treasury_token_account_info.key 
@@ -388,10 +669,11 @@ impl Processor {
 
         if (*treasury_token_account_info.owner).ne(token_program_account_info.key)
         {
-            let create_treasury_associated_token_ix = spl_associated_token_account::create_associated_token_account(
+            let create_treasury_associated_token_ix = spl_associated_token_account::instruction::create_associated_token_account(
                 contributor_account_info.key,
                 treasury_account_info.key,
-                beneficiary_mint_account_info.key
+                beneficiary_mint_account_info.key,
+                token_program_account_info.key
             );
 
             invoke(&create_treasury_associated_token_ix, &[
@@ -413,13 +695,19 @@ impl Processor {
 
         let fee = ADD_FUNDS_PERCENT_FEE * contribution_amount / 100f64;
         let amount = contribution_amount - fee;
-        let treasury = Treasury::unpack_from_slice(&treasury_account_info.data.borrow())?;
+        let treasury = Treasury::unpack_any_len(&treasury_account_info.data.borrow())?;
 
-        let (treasury_pool_address, treasury_pool_bump_seed) = Pubkey::find_program_address(
-            &[
-                treasury.treasury_base_address.as_ref(),
-                &treasury.treasury_block_height.to_le_bytes()
-            ], 
+        if treasury.funding_increment != 0
+        {
+            let beneficiary_mint = spl_token::state::Mint::unpack_from_slice(&beneficiary_mint_account_info.data.borrow())?;
+            let beneficiary_pow = num_traits::pow(10f64, beneficiary_mint.decimals.into());
+
+            require_funding_increment(&treasury, contribution_amount, beneficiary_pow)?;
+        }
+
+        let (treasury_pool_address, treasury_pool_bump_seed) = find_treasury_address(
+            &treasury.treasury_base_address,
+            treasury.treasury_block_height,
             msp_account_info.key
         );
 
@@ -431,101 +719,50 @@ impl Processor {
         if (*contributor_treasury_token_account_info.key).ne(&Pubkey::default()) &&
            (*treasury_mint_account_info.key).ne(&Pubkey::default())
         {
-            if (*contributor_treasury_token_account_info.owner).ne(token_program_account_info.key)
-            {
-                // Create contributor treasury associated token account
-                let contributor_treasury_token_address = spl_associated_token_account::get_associated_token_address(
-                    contributor_account_info.key,
-                    treasury_mint_account_info.key
-                );
-
-                if contributor_treasury_token_address != *contributor_treasury_token_account_info.key 
-                {
-                    return Err(StreamError::InvalidContributorTreasuryToken.into());
-                }
-
-                // Create the contributor treasury token account if there is a treasury pool and the account does not exists
-                let create_contributor_treasury_atoken_ix = spl_associated_token_account::create_associated_token_account(
-                    contributor_account_info.key,
-                    contributor_account_info.key,
-                    treasury_mint_account_info.key
-                );
-
-                invoke(&create_contributor_treasury_atoken_ix, &[
-                    associated_token_program_account_info.clone(),
-                    contributor_account_info.clone(),
-                    contributor_treasury_token_account_info.clone(),
-                    treasury_mint_account_info.clone(),
-                    system_account_info.clone(),
-                    token_program_account_info.clone(),
-                    rent_account_info.clone()
-                ]);
-
-                msg!(
-                    "Contributor treasury associated token account created at: {:?} address", 
-                    (*contributor_treasury_token_account_info.key).to_string()
-                );
-            }
-            
-            // Mint just if there is a treasury pool
-            let treasury_mint = spl_token::state::Mint::unpack_from_slice(&treasury_mint_account_info.data.borrow())?;
-            let treasury_mint_signer_seed: &[&[_]] = &[
-                treasury.treasury_base_address.as_ref(),
-                &treasury.treasury_block_height.to_le_bytes(),
-                &[treasury_pool_bump_seed]
-            ];
- 
-            let treasury_pow = num_traits::pow(10f64, treasury_mint.decimals.into());    
-            let mint_to_ix = spl_token::instruction::mint_to(
-                token_program_account_info.key,
-                treasury_mint_account_info.key,
-                contributor_treasury_token_account_info.key,
-                treasury_account_info.key,
-                &[],
-                (amount * treasury_pow) as u64
-            )?;
-
-            invoke_signed(&mint_to_ix,
-                &[
-                    token_program_account_info.clone(),
-                    treasury_mint_account_info.clone(),
-                    contributor_treasury_token_account_info.clone(),
-                    treasury_account_info.clone()
-                ],
-                &[treasury_mint_signer_seed]
+            let beneficiary_mint = spl_token::state::Mint::unpack_from_slice(&beneficiary_mint_account_info.data.borrow())?;
+            let treasury_balance = StateWithExtensions::<spl_token_2022::state::Account>::unpack(
+                &treasury_token_account_info.data.borrow()
+            )?.base.amount;
+
+            Self::mint_pool_tokens(
+                contributor_account_info,
+                contributor_treasury_token_account_info,
+                treasury_account_info,
+                treasury_mint_account_info,
+                token_program_account_info,
+                associated_token_program_account_info,
+                system_account_info,
+                rent_account_info,
+                &treasury,
+                &stream,
+                treasury_pool_bump_seed,
+                amount,
+                treasury_balance as f64 / num_traits::pow(10f64, beneficiary_mint.decimals.into())
             )?;
-
-            msg!("Minting {:?} treasury pool tokens to: {:?}", 
-                amount, 
-                (*contributor_treasury_token_account_info.key).to_string()
-            );
         }
 
         // Transfer tokens from contributor to treasury pool
         let beneficiary_mint = spl_token::state::Mint::unpack_from_slice(&beneficiary_mint_account_info.data.borrow())?;
         let beneficiary_pow = num_traits::pow(10f64, beneficiary_mint.decimals.into());
-        let transfer_ix = spl_token::instruction::transfer(
-            token_program_account_info.key,
-            contributor_token_account_info.key,
-            treasury_token_account_info.key,
-            contributor_account_info.key,
-            &[],
-            (amount * beneficiary_pow) as u64
+        let received_base_amount = transfer_tokens(
+            token_program_account_info,
+            contributor_token_account_info,
+            beneficiary_mint_account_info,
+            treasury_token_account_info,
+            contributor_account_info,
+            (amount * beneficiary_pow) as u64,
+            &[]
         )?;
 
-        invoke(&transfer_ix, &[
-            contributor_account_info.clone(),
-            treasury_token_account_info.clone(),
-            contributor_token_account_info.clone(),
-            token_program_account_info.clone()
-        ]);
+        // Token-2022 mints may charge a transfer fee, only what reached the treasury is deposited
+        let received_amount = received_base_amount as f64 / beneficiary_pow;
 
         msg!("Transfer {:?} tokens to: {:?}",
-            amount, 
-            (*contributor_token_account_info.key).to_string()
+            received_amount, 
+            (*treasury_token_account_info.key).to_string()
         );
 
-        stream.total_deposits += amount;
+        stream.total_deposits = checked_add_amount(stream.total_deposits, received_amount, beneficiary_pow)?;
 
         if stream.funded_on_utc == 0 // First time the stream is being funded
         {
@@ -536,17 +773,27 @@ impl Processor {
         // and it is going to be manually resumed again        
         if no_funds == 1 || resume == true
         {
-            stream.stream_resumed_block_height = current_block_height;
-            stream.stream_resumed_block_time = current_block_time;
+            resume_stream(&mut stream, current_block_height, current_block_time);
         }
 
+        stream.last_activity_utc = clock.unix_timestamp as u64;
         // Save
         Stream::pack_into_slice(&stream, &mut stream_account_info.data.borrow_mut());
 
+        #[cfg(feature = "events")]
+        StreamEvent::FundsAdded {
+            stream_address: *stream_account_info.key,
+            contributor_address: *contributor_account_info.key,
+            amount: received_amount,
+            total_deposits: stream.total_deposits
+
+        }.emit()?;
+
         // Create the Money Streaming Program operations token account if not exists
-        let msp_ops_token_address = spl_associated_token_account::get_associated_token_address(
+        let msp_ops_token_address = spl_associated_token_account::get_associated_token_address_with_program_id(
             msp_ops_account_info.key,
-            beneficiary_mint_account_info.key
+            beneficiary_mint_account_info.key,
+            token_program_account_info.key
         );
 
         if msp_ops_token_address != *msp_ops_token_account_info.key 
@@ -556,10 +803,11 @@ impl Processor {
 
         if *msp_ops_token_account_info.owner != *token_program_account_info.key
         {
-            let create_msp_associated_token_ix = spl_associated_token_account::create_associated_token_account(
+            let create_msp_associated_token_ix = spl_associated_token_account::instruction::create_associated_token_account(
                 contributor_account_info.key,
                 msp_ops_account_info.key,
-                beneficiary_mint_account_info.key
+                beneficiary_mint_account_info.key,
+                token_program_account_info.key
             );
 
             invoke(&create_msp_associated_token_ix, &[
@@ -580,22 +828,16 @@ impl Processor {
         }
 
         // Pay fees
-        let fees_ix = spl_token::instruction::transfer(
-            token_program_account_info.key,
-            contributor_token_account_info.key,
-            msp_ops_token_account_info.key,
-            contributor_account_info.key,
-            &[],
-            (fee * beneficiary_pow) as u64
+        transfer_tokens(
+            token_program_account_info,
+            contributor_token_account_info,
+            beneficiary_mint_account_info,
+            msp_ops_token_account_info,
+            contributor_account_info,
+            (fee * beneficiary_pow) as u64,
+            &[]
         )?;
 
-        invoke(&fees_ix, &[
-            contributor_account_info.clone(),
-            contributor_token_account_info.clone(),
-            msp_ops_token_account_info.clone(),
-            token_program_account_info.clone()
-        ]);
-
         msg!("Transfer {:?} tokens of fee to: {:?}",
             fee, 
             (*msp_ops_token_account_info.key).to_string()
@@ -611,6 +853,8 @@ impl Processor {
 
     ) -> ProgramResult {
 
+        require_accounts(accounts, 12)?;
+
         let account_info_iter = &mut accounts.iter();
         let contributor_account_info = next_account_info(account_info_iter)?;
         let contributor_token_account_info = next_account_info(account_info_iter)?;
@@ -631,15 +875,25 @@ impl Processor {
             return Err(StreamError::MissingInstructionSignature.into());
         }      
 
-        if treasury_account_info.owner != program_id || stream_account_info.owner != program_id
+        require_any_token_program(token_program_account_info)?;
+
+        require_program_owned(treasury_account_info, program_id)?;
+        require_treasury_layout(treasury_account_info)?;
+        require_program_owned(stream_account_info, program_id)?;
+        require_account_layout(stream_account_info, Stream::LEN)?;
+
+        let mut treasury = Treasury::unpack_any_len(&treasury_account_info.data.borrow())?;
+
+        if treasury.treasury_mint_address.ne(treasury_mint_account_info.key)
         {
-            return Err(StreamError::InstructionNotAuthorized.into());
+            return Err(StreamError::InvalidTreasuryPool.into());
         }
 
         // Get contributor treasury associated token account
-        let contributor_treasury_token_address = spl_associated_token_account::get_associated_token_address(
+        let contributor_treasury_token_address = spl_associated_token_account::get_associated_token_address_with_program_id(
             contributor_account_info.key,
-            treasury_mint_account_info.key
+            treasury_mint_account_info.key,
+            token_program_account_info.key
         );
 
         if contributor_treasury_token_address.ne(contributor_treasury_token_account_info.key) 
@@ -648,13 +902,22 @@ impl Processor {
         }
 
         let fee = WITHDRAW_PERCENT_FEE * recover_amount / 100f64;
-        let treasury_mint = spl_token::state::Mint::unpack_from_slice(&treasury_mint_account_info.data.borrow())?;
+        let contributor_treasury_token = StateWithExtensions::<spl_token_2022::state::Account>::unpack(
+            &contributor_treasury_token_account_info.data.borrow()
+        )?.base;
+        let treasury_mint = StateWithExtensions::<spl_token_2022::state::Mint>::unpack(
+            &treasury_mint_account_info.data.borrow()
+        )?.base;
+
+        require_pool_mint_authority(&treasury_mint, treasury_account_info.key)?;
+
         let treasury_mint_pow = num_traits::pow(10f64, treasury_mint.decimals.into());
         let burn_amount = recover_amount * treasury_mint_pow;
-        let recover_amount_percent = recover_amount / (treasury_mint.supply as f64) * 100f64; // The percent that represents the `recover_amount` in the pool
+        let pool_supply = treasury_mint.supply as f64 / treasury_mint_pow;
+        let recover_amount_percent = recover_amount / pool_supply * 100f64; // The percent that represents the `recover_amount` in the pool
 
         // Burn treasury tokens from the contributor treasury token account       
-        let burn_ix = spl_token::instruction::burn(
+        let burn_ix = spl_token_2022::instruction::burn(
             token_program_account_info.key,
             contributor_treasury_token_account_info.key,
             treasury_mint_account_info.key,
@@ -676,21 +939,28 @@ impl Processor {
         );
         
         let mut stream = Stream::unpack_from_slice(&stream_account_info.data.borrow())?; 
-        let current_block_time = clock.unix_timestamp as u64;
-        let is_running = (stream.stream_resumed_block_time > stream.escrow_vested_amount_snap_block_time) as u64;
-        let rate = stream.rate_amount / (stream.rate_interval_in_seconds as f64) * (is_running as f64);
-        let marker_block_time = cmp::max(stream.stream_resumed_block_time, stream.escrow_vested_amount_snap_block_time);
-        let elapsed_time = (current_block_time - marker_block_time) as f64;
-        let mut escrow_vested_amount = stream.escrow_vested_amount_snap + rate * elapsed_time;
-        
-        if escrow_vested_amount > stream.total_deposits - stream.total_withdrawals
+
+        if stream.treasury_address.ne(treasury_account_info.key)
         {
-            escrow_vested_amount = stream.total_deposits - stream.total_withdrawals;
+            return Err(StreamError::InvalidTreasuryAccount.into());
         }
 
+        let current_block_time = clock.unix_timestamp as u64;
+        let escrow_vested_amount = stream.vested_amount_from_snapshot(current_block_time);
+
         let escrow_unvested_amount = stream.total_deposits - stream.total_withdrawals - escrow_vested_amount;
         let amount = recover_amount_percent * escrow_unvested_amount / 100f64; // The amount calculated by the percent of the pool that the contributor owns
         let transfer_amount = amount - fee;
+        let recoverable_amount = stream.recoverable_amount(
+            contributor_treasury_token.amount as f64 / treasury_mint_pow,
+            pool_supply,
+            current_block_time
+        );
+
+        if amount > recoverable_amount
+        {
+            return Err(StreamError::RecoverExceedsUnvested.into());
+        }
 
         if transfer_amount > escrow_unvested_amount
         {
@@ -698,14 +968,13 @@ impl Processor {
         }
 
         // Transfer tokens to contributor        
-        let contributor_mint = spl_token::state::Mint::unpack_from_slice(&contributor_mint_account_info.data.borrow())?;
-        let contributor_mint_pow = num_traits::pow(10f64, contributor_mint.decimals.into());
-        let mut treasury = Treasury::unpack_from_slice(&treasury_account_info.data.borrow())?;
-        let (treasury_pool_address, treasury_pool_bump_seed) = Pubkey::find_program_address(
-            &[
-                treasury.treasury_base_address.as_ref(),
-                &treasury.treasury_block_height.to_le_bytes()
-            ], 
+        let contributor_mint_decimals = StateWithExtensions::<spl_token_2022::state::Mint>::unpack(
+            &contributor_mint_account_info.data.borrow()
+        )?.base.decimals;
+        let contributor_mint_pow = num_traits::pow(10f64, contributor_mint_decimals.into());
+        let (treasury_pool_address, treasury_pool_bump_seed) = find_treasury_address(
+            &treasury.treasury_base_address,
+            treasury.treasury_block_height,
             msp_account_info.key
         );
 
@@ -721,25 +990,15 @@ impl Processor {
             &[treasury_pool_bump_seed]
         ];
 
-        let contributor_transfer_ix = spl_token::instruction::transfer(
-            token_program_account_info.key,
-            treasury_token_account_info.key,
-            contributor_token_account_info.key,
-            treasury_account_info.key,
-            &[],
-            (transfer_amount * contributor_mint_pow) as u64
-        )?;
-
-        invoke_signed(&contributor_transfer_ix, 
-            &[
-                treasury_account_info.clone(),
-                treasury_token_account_info.clone(),
-                contributor_token_account_info.clone(),
-                token_program_account_info.clone(),
-                msp_account_info.clone()
-            ],
+        transfer_tokens(
+            token_program_account_info,
+            treasury_token_account_info,
+            contributor_mint_account_info,
+            contributor_token_account_info,
+            treasury_account_info,
+            (transfer_amount * contributor_mint_pow) as u64,
             &[treasury_signer_seed]
-        );
+        )?;
 
         msg!("Transfer {:?} tokens to: {:?}",
             transfer_amount, 
@@ -747,7 +1006,8 @@ impl Processor {
         );
 
         // Update the stream
-        stream.total_deposits -= transfer_amount;
+        stream.total_deposits = checked_sub_amount(stream.total_deposits, transfer_amount, contributor_mint_pow)?;
+        stream.last_activity_utc = clock.unix_timestamp as u64;
         // Save
         Stream::pack_into_slice(&stream, &mut stream_account_info.data.borrow_mut());
 
@@ -758,9 +1018,12 @@ impl Processor {
             treasury.treasury_block_height = 0;
             treasury.treasury_mint_address = Pubkey::default();
             treasury.treasury_base_address = Pubkey::default();
+            treasury.funding_increment = 0;
+            treasury.streams_count = 0;
+            treasury.max_streams = 0;
             treasury.initialized = false;
 
-            Treasury::pack_into_slice(&treasury, &mut treasury_account_info.data.borrow_mut());
+            treasury.pack_any_len(&mut treasury_account_info.data.borrow_mut())?;
 
             // Close the treasury
             let msp_ops_lamports = msp_ops_account_info.lamports();
@@ -775,22 +1038,16 @@ impl Processor {
         }
 
         // Pay fees
-        let fees_ix = spl_token::instruction::transfer(
-            token_program_account_info.key,
-            contributor_token_account_info.key,
-            msp_ops_token_account_info.key,
-            contributor_account_info.key,
-            &[],
-            fee as u64
+        transfer_tokens(
+            token_program_account_info,
+            contributor_token_account_info,
+            contributor_mint_account_info,
+            msp_ops_token_account_info,
+            contributor_account_info,
+            (fee * contributor_mint_pow) as u64,
+            &[]
         )?;
 
-        invoke(&fees_ix, &[
-            contributor_account_info.clone(),
-            contributor_token_account_info.clone(),
-            msp_ops_token_account_info.clone(),
-            token_program_account_info.clone()
-        ]);
-
         msg!("Transfer {:?} tokens of fee to: {:?}",
             fee, 
             (*msp_ops_token_account_info.key).to_string()
@@ -802,10 +1059,12 @@ impl Processor {
     fn process_withdraw(
         accounts: &[AccountInfo],
         program_id: &Pubkey,
-        withdrawal_amount: f64
+        withdrawal_amount: Option<f64> // None withdraws everything withdrawable
 
     ) -> ProgramResult {
 
+        require_accounts(accounts, 10)?;
+
         let account_info_iter = &mut accounts.iter();
         let beneficiary_account_info = next_account_info(account_info_iter)?;
         let beneficiary_token_account_info = next_account_info(account_info_iter)?;
@@ -824,43 +1083,58 @@ impl Processor {
             return Err(StreamError::MissingInstructionSignature.into());
         }
 
-        if stream_account_info.owner != program_id || treasury_account_info.owner != program_id
-        {
-            return Err(StreamError::InstructionNotAuthorized.into());
-        }
+        require_any_token_program(token_program_account_info)?;
+
+        require_program_owned(stream_account_info, program_id)?;
+        require_account_layout(stream_account_info, Stream::LEN)?;
+        require_program_owned(treasury_account_info, program_id)?;
+        require_treasury_layout(treasury_account_info)?;
 
         let mut stream = Stream::unpack_from_slice(&stream_account_info.data.borrow())?;
-        let _current_block_height = clock.slot as u64;
-        let current_block_time = clock.unix_timestamp as u64;
-        let is_running = (stream.stream_resumed_block_time >= stream.escrow_vested_amount_snap_block_time) as u64;
-        let rate = stream.rate_amount / (stream.rate_interval_in_seconds as f64) * (is_running as f64);
-        let marker_block_time = cmp::max(stream.stream_resumed_block_time, stream.escrow_vested_amount_snap_block_time);
-        let elapsed_time = (current_block_time - marker_block_time) as f64;
-        let mut escrow_vested_amount = stream.escrow_vested_amount_snap + rate * elapsed_time;
-        
-        if escrow_vested_amount >= (stream.total_deposits - stream.total_withdrawals)
+
+        if stream.treasury_address.ne(treasury_account_info.key)
+        {
+            return Err(StreamError::InvalidTreasuryAccount.into());
+        }
+
+        if stream.beneficiary_token_address.ne(&Pubkey::default()) &&
+           stream.beneficiary_token_address.ne(beneficiary_token_account_info.key)
         {
-            escrow_vested_amount = stream.total_deposits - stream.total_withdrawals;
+            return Err(StreamError::InvalidBeneficiaryTokenAccount.into());
         }
 
-        if withdrawal_amount > escrow_vested_amount
+        // The destination token account has to belong to the beneficiary of the stream
+        let beneficiary_token_owner = StateWithExtensions::<spl_token_2022::state::Account>::unpack(
+            &beneficiary_token_account_info.data.borrow()
+        )?.base.owner;
+
+        if beneficiary_token_owner.ne(&stream.beneficiary_address)
         {
-            return Err(StreamError::NotAllowedWithdrawalAmount.into());
+            return Err(StreamError::InvalidBeneficiaryTokenAccount.into());
         }
 
+        let _current_block_height = clock.slot as u64;
+        let current_block_time = clock.unix_timestamp as u64;
+        let beneficiary_mint = spl_token::state::Mint::unpack_from_slice(&beneficiary_mint_account_info.data.borrow())?;
+        let beneficiary_mint_pow = num_traits::pow(10f64, beneficiary_mint.decimals.into());
+        let withdrawal_amount = match withdrawal_amount
+        {
+            Some(amount) => amount,
+            None => stream.withdrawable_amount(current_block_time, beneficiary_mint.decimals)? as f64 / beneficiary_mint_pow
+        };
+        let escrow_vested_amount = stream.vested_amount_from_snapshot(current_block_time);
+
+        stream.check_withdrawal(withdrawal_amount, current_block_time, beneficiary_mint.decimals)?;
+
         let _escrow_unvested_amount = stream.total_deposits - stream.total_withdrawals - escrow_vested_amount;
         let fee = WITHDRAW_PERCENT_FEE * withdrawal_amount / 100f64;
         let transfer_amount = withdrawal_amount - fee;
-        let beneficiary_mint = spl_token::state::Mint::unpack_from_slice(&beneficiary_mint_account_info.data.borrow())?;
-        let beneficiary_mint_pow = num_traits::pow(10f64, beneficiary_mint.decimals.into());
 
         // Withdraw
-        let treasury = Treasury::unpack_from_slice(&treasury_account_info.data.borrow())?;
-        let (treasury_pool_address, treasury_pool_bump_seed) = Pubkey::find_program_address(
-            &[
-                treasury.treasury_base_address.as_ref(),
-                &treasury.treasury_block_height.to_le_bytes()
-            ], 
+        let treasury = Treasury::unpack_any_len(&treasury_account_info.data.borrow())?;
+        let (treasury_pool_address, treasury_pool_bump_seed) = find_treasury_address(
+            &treasury.treasury_base_address,
+            treasury.treasury_block_height,
             msp_account_info.key
         );
 
@@ -876,61 +1150,93 @@ impl Processor {
             &[treasury_pool_bump_seed]
         ];
 
-        let transfer_ix = spl_token::instruction::transfer(
-            token_program_account_info.key,
-            treasury_token_account_info.key,
-            beneficiary_token_account_info.key,
-            treasury_account_info.key,
-            &[],
-            (transfer_amount * beneficiary_mint_pow) as u64
-        )?;
-
-        invoke_signed(&transfer_ix, 
-            &[
-                treasury_account_info.clone(),
-                treasury_token_account_info.clone(),
-                beneficiary_token_account_info.clone(),
-                token_program_account_info.clone(),
-                msp_account_info.clone()
-            ],
+        let received_base_amount = transfer_tokens(
+            token_program_account_info,
+            treasury_token_account_info,
+            beneficiary_mint_account_info,
+            beneficiary_token_account_info,
+            treasury_account_info,
+            (transfer_amount * beneficiary_mint_pow) as u64,
             &[treasury_signer_seed]
-        );
+        )?;
 
         msg!("Transfer {:?} tokens to: {:?}",
-            transfer_amount, 
+            received_base_amount as f64 / beneficiary_mint_pow, 
             (*beneficiary_token_account_info.key).to_string()
         );
 
-        // Update stream account data
-        stream.total_withdrawals += withdrawal_amount;
-        stream.escrow_vested_amount_snap = escrow_vested_amount - withdrawal_amount;
-        stream.stream_resumed_block_height = clock.slot as u64;
-        stream.stream_resumed_block_time = clock.unix_timestamp as u64; 
+        // Update stream account data, a paused stream stays paused
+        let is_streaming = stream.is_streaming();
+
+        stream.total_withdrawals = checked_add_amount(stream.total_withdrawals, withdrawal_amount, beneficiary_mint_pow)?;
+        // Withdrawals are checked on base units, the rounding can take the snapshot just below 0
+        stream.escrow_vested_amount_snap = (escrow_vested_amount - withdrawal_amount).max(0.0);
+        stream.escrow_vested_amount_snap_block_height = clock.slot as u64;
+        stream.escrow_vested_amount_snap_block_time = clock.unix_timestamp as u64;
+
+        if is_streaming
+        {
+            stream.stream_resumed_block_height = clock.slot as u64;
+            stream.stream_resumed_block_time = clock.unix_timestamp as u64; 
+        }
+
+        stream.last_activity_utc = clock.unix_timestamp as u64;
         // Save
         Stream::pack_into_slice(&stream, &mut stream_account_info.data.borrow_mut());
 
+        #[cfg(feature = "events")]
+        StreamEvent::Withdrawn {
+            stream_address: *stream_account_info.key,
+            beneficiary_address: *beneficiary_account_info.key,
+            amount: withdrawal_amount,
+            total_withdrawals: stream.total_withdrawals
+
+        }.emit()?;
+
         // Pay fees
-        let fees_ix = spl_token::instruction::transfer(
-            token_program_account_info.key,
-            beneficiary_token_account_info.key,
-            msp_ops_token_account_info.key,
-            beneficiary_account_info.key,
-            &[],
-            (fee * beneficiary_mint_pow) as u64
+        transfer_tokens(
+            token_program_account_info,
+            beneficiary_token_account_info,
+            beneficiary_mint_account_info,
+            msp_ops_token_account_info,
+            beneficiary_account_info,
+            (fee * beneficiary_mint_pow) as u64,
+            &[]
         )?;
 
-        invoke(&fees_ix, &[
-            beneficiary_account_info.clone(),
-            beneficiary_token_account_info.clone(),
-            msp_ops_token_account_info.clone(),
-            token_program_account_info.clone()
-        ]);
-
         msg!("Transfer {:?} tokens of fee to: {:?}",
             fee, 
             (*msp_ops_token_account_info.key).to_string()
         );
-        
+
+        // Called last, with the stream already saved, so the hook only sees the final state
+        if let Some(hook_program) = stream.withdraw_hook_program
+        {
+            let hook_program_account_info = next_account_info(account_info_iter)?;
+
+            if hook_program_account_info.key.ne(&hook_program) || !hook_program_account_info.executable
+            {
+                return Err(StreamError::InvalidWithdrawHook.into());
+            }
+
+            let hook_ix = withdraw_hook(
+                &hook_program,
+                stream_account_info.key,
+                beneficiary_account_info.key,
+                beneficiary_token_account_info.key,
+                received_base_amount
+            );
+
+            invoke(&hook_ix, &[
+                stream_account_info.clone(),
+                beneficiary_account_info.clone(),
+                beneficiary_token_account_info.clone(),
+                hook_program_account_info.clone()
+            ])?;
+
+            msg!("Withdraw hook invoked: {:?}", hook_program.to_string());
+        }
+
         Ok(())
     }
 
@@ -940,6 +1246,8 @@ impl Processor {
 
     ) -> ProgramResult {
 
+        require_accounts(accounts, 4)?;
+
         let account_info_iter = &mut accounts.iter();
         let initializer_account_info = next_account_info(account_info_iter)?;
         let stream_account_info = next_account_info(account_info_iter)?;
@@ -952,31 +1260,28 @@ impl Processor {
             return Err(StreamError::MissingInstructionSignature.into());
         }
 
+        require_program_owned(stream_account_info, program_id)?;
+        require_account_layout(stream_account_info, Stream::LEN)?;
+
         let mut stream = Stream::unpack_from_slice(&stream_account_info.data.borrow())?;
 
-        if stream_account_info.owner != program_id ||
-        (
-            stream.treasurer_address.ne(initializer_account_info.key) && 
-            stream.beneficiary_address.ne(initializer_account_info.key)
-        )
+        if stream.treasurer_address.ne(initializer_account_info.key) && 
+           stream.beneficiary_address.ne(initializer_account_info.key)
         {
             return Err(StreamError::InstructionNotAuthorized.into());
         }
 
-        let current_block_height = clock.slot as u64;
-        let current_block_time = clock.unix_timestamp as u64;
-        let rate = stream.rate_amount / (stream.rate_interval_in_seconds as f64);
-        let elapsed_time = current_block_time - stream.stream_resumed_block_time;
-        let mut escrow_vested_amount = stream.escrow_vested_amount_snap + rate * (elapsed_time as f64);
-        
-        if escrow_vested_amount > stream.total_deposits - stream.total_withdrawals 
+        if !stream.is_streaming()
         {
-            escrow_vested_amount = stream.total_deposits - stream.total_withdrawals;
+            return Err(StreamError::StreamAlreadyPaused.into());
         }
 
-        stream.escrow_vested_amount_snap = escrow_vested_amount;
-        stream.escrow_vested_amount_snap_block_height = current_block_height;
-        stream.escrow_vested_amount_snap_block_time = current_block_time;
+        let current_block_height = clock.slot as u64;
+        let current_block_time = clock.unix_timestamp as u64;
+        let escrow_vested_amount = stream.vested_amount_from_snapshot(current_block_time);
+
+        pause_stream(&mut stream, escrow_vested_amount, PauseReason::Manual, current_block_height, current_block_time);
+        stream.last_activity_utc = clock.unix_timestamp as u64;
         // Save
         Stream::pack_into_slice(&stream, &mut stream_account_info.data.borrow_mut());
         msg!("Pausing the stream");
@@ -1009,6 +1314,8 @@ impl Processor {
 
     ) -> ProgramResult {
 
+        require_accounts(accounts, 4)?;
+
         let account_info_iter = &mut accounts.iter();
         let initializer_account_info = next_account_info(account_info_iter)?;
         let stream_account_info = next_account_info(account_info_iter)?;
@@ -1021,20 +1328,25 @@ impl Processor {
             return Err(StreamError::MissingInstructionSignature.into());
         }
 
+        require_program_owned(stream_account_info, program_id)?;
+        require_account_layout(stream_account_info, Stream::LEN)?;
+
         let mut stream = Stream::unpack_from_slice(&stream_account_info.data.borrow())?;
 
-        if stream_account_info.owner != program_id || 
-        (
-            stream.treasurer_address.ne(initializer_account_info.key) && 
-            stream.beneficiary_address.ne(initializer_account_info.key)
-        )
+        if stream.treasurer_address.ne(initializer_account_info.key) && 
+           stream.beneficiary_address.ne(initializer_account_info.key)
         {
             return Err(StreamError::InstructionNotAuthorized.into());
         }
 
+        if stream.is_streaming()
+        {
+            return Err(StreamError::StreamAlreadyRunning.into());
+        }
+
         // Resuming the stream and updating data
-        stream.stream_resumed_block_height = clock.slot as u64;
-        stream.stream_resumed_block_time = clock.unix_timestamp as u64;
+        resume_stream(&mut stream, clock.slot as u64, clock.unix_timestamp as u64);
+        stream.last_activity_utc = clock.unix_timestamp as u64;
         // Save
         Stream::pack_into_slice(&stream, &mut stream_account_info.data.borrow_mut());
         msg!("Resuming the stream");
@@ -1074,12 +1386,15 @@ impl Processor {
         rate_cliff_in_seconds: u64,
         cliff_vest_amount: f64,
         cliff_vest_percent: f64,
-        auto_pause_in_seconds: u64
+        auto_pause_in_seconds: u64,
+        expiration_in_seconds: u64
 
     ) -> ProgramResult {
 
         let _treasurer_account_info: &AccountInfo;
         let _beneficiary_account_info: &AccountInfo;
+        require_accounts(accounts, 6)?;
+
         let account_info_iter = &mut accounts.iter();
         let initializer_account_info = next_account_info(account_info_iter)?;
         let stream_terms_account_info = next_account_info(account_info_iter)?;
@@ -1087,16 +1402,19 @@ impl Processor {
         let stream_account_info = next_account_info(account_info_iter)?;
         let msp_ops_account_info = next_account_info(account_info_iter)?;
         let system_account_info = next_account_info(account_info_iter)?;
+        let clock = Clock::get()?;
 
         if !initializer_account_info.is_signer 
         {
             return Err(StreamError::MissingInstructionSignature.into());
         }    
 
-        if stream_terms_account_info.owner != program_id || stream_account_info.owner != program_id
-        {
-            return Err(StreamError::InstructionNotAuthorized.into());
-        }
+        check_stream_name(&stream_name)?;
+
+        require_program_owned(stream_terms_account_info, program_id)?;
+        require_account_layout(stream_terms_account_info, StreamTerms::LEN)?;
+        require_program_owned(stream_account_info, program_id)?;
+        require_account_layout(stream_account_info, Stream::LEN)?;
 
         let stream = Stream::unpack_from_slice(&stream_account_info.data.borrow())?;
 
@@ -1108,11 +1426,25 @@ impl Processor {
 
         let mut stream_terms = StreamTerms::unpack_from_slice(&stream_terms_account_info.data.borrow())?;
 
+        // A pending proposal is only cleared by answering it (or expiring it)
         if stream_terms.is_initialized() 
         {
-            return Err(StreamError::StreamAlreadyInitialized.into());
+            return Err(StreamError::UpdateAlreadyPending.into());
         }
 
+        // Unset terms keep the stream values, the resulting cliff has to fit in the funded duration
+        let new_rate_amount = if rate_amount != 0.0 { rate_amount } else { stream.rate_amount };
+        let new_rate_interval_in_seconds = if rate_interval_in_seconds != 0 { rate_interval_in_seconds } else { stream.rate_interval_in_seconds };
+        let new_rate_cliff_in_seconds = if rate_cliff_in_seconds != 0 { rate_cliff_in_seconds } else { stream.rate_cliff_in_seconds };
+
+        check_cliff_within_duration(
+            stream.total_deposits,
+            new_rate_amount,
+            new_rate_interval_in_seconds,
+            new_rate_cliff_in_seconds
+        )?;
+
+        stream_terms.version = STREAM_TERMS_VERSION;
         stream_terms.proposed_by = *initializer_account_info.key;
         stream_terms.stream_id = *stream_account_info.key;
         stream_terms.stream_name = stream_name;
@@ -1124,6 +1456,11 @@ impl Processor {
         stream_terms.rate_cliff_in_seconds = rate_cliff_in_seconds;
         stream_terms.cliff_vest_amount = cliff_vest_amount;
         stream_terms.cliff_vest_percent = cliff_vest_percent;
+        stream_terms.proposed_at_utc = clock.unix_timestamp as u64;
+        stream_terms.expiration_in_seconds = match expiration_in_seconds {
+            0 => PROPOSAL_EXPIRATION_IN_SECONDS,
+            _ => expiration_in_seconds
+        };
 
         if auto_pause_in_seconds != 0 
         {
@@ -1169,6 +1506,8 @@ impl Processor {
     ) -> ProgramResult {
 
         let treasurer_account_info: &AccountInfo;
+        require_accounts(accounts, 6)?;
+
         let account_info_iter = &mut accounts.iter();
         let initializer_account_info = next_account_info(account_info_iter)?;
         let stream_terms_account_info = next_account_info(account_info_iter)?;
@@ -1176,16 +1515,17 @@ impl Processor {
         let stream_account_info = next_account_info(account_info_iter)?;
         let msp_ops_account_info = next_account_info(account_info_iter)?;
         let system_account_info = next_account_info(account_info_iter)?;
+        let clock = Clock::get()?;
 
         if !initializer_account_info.is_signer 
         {
             return Err(StreamError::MissingInstructionSignature.into());
         }
 
-        if stream_terms_account_info.owner != program_id || stream_account_info.owner != program_id
-        {
-            return Err(StreamError::InstructionNotAuthorized.into()); // The stream terms' account should be owned by the streaming program
-        }
+        require_program_owned(stream_terms_account_info, program_id)?; // The stream terms' account should be owned by the streaming program
+        require_account_layout(stream_terms_account_info, StreamTerms::LEN)?;
+        require_program_owned(stream_account_info, program_id)?;
+        require_account_layout(stream_account_info, Stream::LEN)?;
         
         let mut stream_terms = StreamTerms::unpack_from_slice(&stream_terms_account_info.data.borrow())?;
 
@@ -1214,82 +1554,63 @@ impl Processor {
             return Err(StreamError::InstructionNotAuthorized.into());
         }
 
-        if approve == false // Rejected: Close stream terms account 
-        {
-            let treasurer_lamports = treasurer_account_info.lamports();
-            let stream_terms_lamports = stream_terms_account_info.lamports();
+        // The other party has to be the beneficiary
+        let beneficiary_account_info = if treasurer_account_info.key.eq(initializer_account_info.key) {
+            counterparty_account_info
+        } else {
+            initializer_account_info
+        };
 
-            **stream_terms_account_info.lamports.borrow_mut() = 0;
-            **treasurer_account_info.lamports.borrow_mut() = treasurer_lamports
-                .checked_add(stream_terms_lamports)
-                .ok_or(StreamError::Overflow)?;
-            
-            stream_terms = StreamTerms::default();
-        } 
-        else // Approved: Update stream data and close stream terms account
+        if stream.beneficiary_address.ne(beneficiary_account_info.key)
         {
-            if stream_terms.stream_name.ne(&stream.stream_name) 
-            {
-                stream.stream_name = stream.stream_name
-            }
-
-            if stream_terms.treasurer_address.ne(&Pubkey::default()) && 
-                stream_terms.treasurer_address.ne(&stream.treasurer_address) 
-            {
-                stream.treasurer_address = stream_terms.treasurer_address;
-            }
-
-            if stream_terms.beneficiary_address.ne(&Pubkey::default()) && 
-                stream_terms.beneficiary_address.ne(&stream.beneficiary_address) 
-            {        
-                stream.beneficiary_address = stream_terms.beneficiary_address;
-            }
-
-            if stream_terms.associated_token_address.ne(&Pubkey::default()) && 
-                stream_terms.associated_token_address.ne(&stream.beneficiary_associated_token) 
-            {       
-                stream.beneficiary_associated_token = stream_terms.associated_token_address;
-            }
-
-            if stream_terms.rate_amount != 0.0 && stream_terms.rate_amount != stream.rate_amount 
-            {       
-                stream.rate_amount = stream_terms.rate_amount;
-            }
-
-            if stream_terms.rate_interval_in_seconds != 0 && 
-               stream.rate_interval_in_seconds != stream_terms.rate_interval_in_seconds 
-            {
-                stream.rate_interval_in_seconds = stream_terms.rate_interval_in_seconds;
-            }
+            return Err(StreamError::InstructionNotAuthorized.into());
+        }
 
-            if stream_terms.rate_cliff_in_seconds != 0 && 
-                stream_terms.rate_cliff_in_seconds != stream.rate_cliff_in_seconds 
-            {
-                stream.rate_cliff_in_seconds = stream_terms.rate_cliff_in_seconds;
-            }
+        if approve == true // Approved: Update stream data
+        {
+            // What vested so far vested at the old rate, snapshot it before the terms change
+            let current_block_time = clock.unix_timestamp as u64;
+            let is_streaming = stream.is_streaming();
 
-            if stream_terms.cliff_vest_amount != 0.0 && 
-                stream_terms.cliff_vest_amount != stream.cliff_vest_amount 
-            {
-                stream.cliff_vest_amount = stream_terms.cliff_vest_amount;
-            }
+            stream.escrow_vested_amount_snap = stream.vested_amount_from_snapshot(current_block_time);
+            stream.escrow_vested_amount_snap_block_height = clock.slot as u64;
+            stream.escrow_vested_amount_snap_block_time = current_block_time;
 
-            if stream_terms.cliff_vest_percent != 100 as f64 && 
-                stream_terms.cliff_vest_percent != stream.cliff_vest_percent 
+            if is_streaming
             {
-                stream.cliff_vest_percent = stream_terms.cliff_vest_percent;
+                stream.stream_resumed_block_height = clock.slot as u64;
+                stream.stream_resumed_block_time = current_block_time;
             }
 
-            if stream_terms.auto_pause_in_seconds != 0 && 
-                stream_terms.auto_pause_in_seconds != stream.auto_pause_in_seconds 
-            {
-                stream.auto_pause_in_seconds = stream_terms.auto_pause_in_seconds;
-            }
+            let mut updated_stream = stream.clone();
+            apply_stream_terms(&mut updated_stream, &stream_terms);
+            check_updated_stream_terms(&updated_stream)?;
 
+            stream = updated_stream;
+            stream.last_activity_utc = clock.unix_timestamp as u64;
             // Save stream
             Stream::pack_into_slice(&stream, &mut stream_account_info.data.borrow_mut());
         }
 
+        // Either way the proposal is answered: close stream terms account, the rent goes back to
+        // the proposer (or to the treasurer if the proposer is no longer a party of the stream)
+        let rent_recipient_account_info = if stream_terms.proposed_by.eq(counterparty_account_info.key) {
+            counterparty_account_info
+        } else if stream_terms.proposed_by.eq(initializer_account_info.key) {
+            initializer_account_info
+        } else {
+            treasurer_account_info
+        };
+
+        let rent_recipient_lamports = rent_recipient_account_info.lamports();
+        let stream_terms_lamports = stream_terms_account_info.lamports();
+
+        **stream_terms_account_info.lamports.borrow_mut() = 0;
+        **rent_recipient_account_info.lamports.borrow_mut() = rent_recipient_lamports
+            .checked_add(stream_terms_lamports)
+            .ok_or(StreamError::Overflow)?;
+
+        stream_terms = StreamTerms::default();
         // Save stream terms
         StreamTerms::pack_into_slice(&stream_terms, &mut stream_terms_account_info.data.borrow_mut());
 
@@ -1317,10 +1638,12 @@ impl Processor {
 
     fn process_close_stream(
         accounts: &[AccountInfo],
-        _program_id: &Pubkey
+        program_id: &Pubkey
 
     ) -> ProgramResult {
 
+        require_accounts(accounts, 12)?;
+
         let account_info_iter = &mut accounts.iter();
         let initializer_account_info = next_account_info(account_info_iter)?;
         let treasurer_token_account_info = next_account_info(account_info_iter)?;
@@ -1341,6 +1664,13 @@ impl Processor {
             return Err(StreamError::MissingInstructionSignature.into());
         }
 
+        require_any_token_program(token_program_account_info)?;
+
+        require_program_owned(stream_account_info, program_id)?;
+        require_account_layout(stream_account_info, Stream::LEN)?;
+        require_program_owned(treasury_account_info, program_id)?;
+        require_treasury_layout(treasury_account_info)?;
+
         let mut stream = Stream::unpack_from_slice(&stream_account_info.data.borrow())?;
 
         if stream.treasurer_address.ne(initializer_account_info.key) &&
@@ -1348,19 +1678,42 @@ impl Processor {
         {
             return Err(StreamError::InstructionNotAuthorized.into()); // Just the treasurer or the beneficiary can close a stream
         }
+
+        if stream.treasury_address.ne(treasury_account_info.key)
+        {
+            return Err(StreamError::InvalidTreasuryAccount.into());
+        }
+
+        if stream.beneficiary_token_address.ne(&Pubkey::default()) &&
+           stream.beneficiary_token_address.ne(beneficiary_token_account_info.key)
+        {
+            return Err(StreamError::InvalidBeneficiaryTokenAccount.into());
+        }
+
+        // Each party gets its share in its own token account, whoever closes the stream
+        let beneficiary_token = StateWithExtensions::<spl_token_2022::state::Account>::unpack(
+            &beneficiary_token_account_info.data.borrow()
+        )?.base;
+
+        if beneficiary_token.owner.ne(&stream.beneficiary_address) ||
+           beneficiary_token.mint.ne(beneficiary_mint_account_info.key)
+        {
+            return Err(StreamError::InvalidBeneficiaryTokenAccount.into());
+        }
+
+        let treasurer_token = StateWithExtensions::<spl_token_2022::state::Account>::unpack(
+            &treasurer_token_account_info.data.borrow()
+        )?.base;
+
+        if treasurer_token.owner.ne(&stream.treasurer_address) ||
+           treasurer_token.mint.ne(beneficiary_mint_account_info.key)
+        {
+            return Err(StreamError::InvalidTreasurerTokenAccount.into());
+        }
         
         let current_block_height = clock.slot as u64;
         let current_block_time = clock.unix_timestamp as u64;
-        let is_running = (stream.stream_resumed_block_time >= stream.escrow_vested_amount_snap_block_time) as u64;
-        let rate = stream.rate_amount / (stream.rate_interval_in_seconds as f64) * (is_running as f64);
-        let marker_block_time = cmp::max(stream.stream_resumed_block_time, stream.escrow_vested_amount_snap_block_time);
-        let elapsed_time = (current_block_time - marker_block_time) as f64;
-        let mut escrow_vested_amount = stream.escrow_vested_amount_snap + rate * elapsed_time;
-        
-        if escrow_vested_amount > stream.total_deposits - stream.total_withdrawals 
-        {
-            escrow_vested_amount = stream.total_deposits - stream.total_withdrawals;
-        }
+        let escrow_vested_amount = stream.vested_amount_from_snapshot(current_block_time);
 
         // Pausing the stream
         stream.escrow_vested_amount_snap = escrow_vested_amount;
@@ -1368,20 +1721,20 @@ impl Processor {
         stream.escrow_vested_amount_snap_block_time = current_block_time;
         msg!("Pausing the stream");
 
-        let mint = spl_token::state::Mint::unpack_from_slice(&beneficiary_mint_account_info.data.borrow())?;
-        let mint_pow = num_traits::pow(10f64, mint.decimals.into());
+        let mint_decimals = StateWithExtensions::<spl_token_2022::state::Mint>::unpack(
+            &beneficiary_mint_account_info.data.borrow()
+        )?.base.decimals;
+        let mint_pow = num_traits::pow(10f64, mint_decimals.into());
         
         if escrow_vested_amount > 0.0 
         {
             // Crediting escrow vested amount to the beneficiary
             let beneficiary_fee = CLOSE_STREAM_PERCENT_FEE * escrow_vested_amount / 100f64;
             let transfer_amount = escrow_vested_amount - beneficiary_fee;            
-            let treasury = Treasury::unpack_from_slice(&treasury_account_info.data.borrow())?;
-            let (treasury_pool_address, treasury_pool_bump_seed) = Pubkey::find_program_address(
-                &[
-                    treasury.treasury_base_address.as_ref(),
-                    &treasury.treasury_block_height.to_le_bytes()
-                ], 
+            let treasury = Treasury::unpack_any_len(&treasury_account_info.data.borrow())?;
+            let (treasury_pool_address, treasury_pool_bump_seed) = find_treasury_address(
+                &treasury.treasury_base_address,
+                treasury.treasury_block_height,
                 msp_account_info.key
             );
 
@@ -1397,25 +1750,15 @@ impl Processor {
                 &[treasury_pool_bump_seed]
             ];
 
-            let transfer_ix = spl_token::instruction::transfer(
-                token_program_account_info.key,
-                treasury_token_account_info.key,
-                beneficiary_token_account_info.key,
-                treasury_account_info.key,
-                &[],
-                (transfer_amount * mint_pow) as u64
-            )?;
-
-            invoke_signed(&transfer_ix, 
-                &[
-                    treasury_account_info.clone(),
-                    treasury_token_account_info.clone(),
-                    beneficiary_token_account_info.clone(),
-                    token_program_account_info.clone(),
-                    msp_account_info.clone()
-                ],
+            transfer_tokens(
+                token_program_account_info,
+                treasury_token_account_info,
+                beneficiary_mint_account_info,
+                beneficiary_token_account_info,
+                treasury_account_info,
+                (transfer_amount * mint_pow) as u64,
                 &[treasury_signer_seed]
-            );
+            )?;
 
             msg!("Transfer {:?} tokens to: {:?}",
                 transfer_amount, 
@@ -1423,25 +1766,15 @@ impl Processor {
             );
 
             // Pay fee by the beneficiary
-            let beneficiary_fee_ix = spl_token::instruction::transfer(
-                token_program_account_info.key,
-                treasury_token_account_info.key,
-                msp_ops_token_account_info.key,
-                treasury_account_info.key,
-                &[],
-                (beneficiary_fee * mint_pow) as u64
-            )?;
-
-            invoke_signed(&beneficiary_fee_ix, 
-                &[
-                    treasury_account_info.clone(),
-                    treasury_token_account_info.clone(),
-                    msp_ops_token_account_info.clone(),
-                    token_program_account_info.clone(),
-                    msp_account_info.clone()
-                ],
+            transfer_tokens(
+                token_program_account_info,
+                treasury_token_account_info,
+                beneficiary_mint_account_info,
+                msp_ops_token_account_info,
+                treasury_account_info,
+                (beneficiary_fee * mint_pow) as u64,
                 &[treasury_signer_seed]
-            );
+            )?;
 
             msg!("Transfer {:?} tokens of fee to: {:?}",
                 beneficiary_fee, 
@@ -1453,14 +1786,12 @@ impl Processor {
 
         if escrow_unvested_amount > 0.0
         {
-            // Crediting escrow unvested amount to the contributor
-            let transfer_amount = escrow_unvested_amount - CLOSE_STREAM_FLAT_FEE;            
-            let treasury = Treasury::unpack_from_slice(&treasury_account_info.data.borrow())?;
-            let (treasury_pool_address, treasury_pool_bump_seed) = Pubkey::find_program_address(
-                &[
-                    treasury.treasury_base_address.as_ref(),
-                    &treasury.treasury_block_height.to_le_bytes()
-                ], 
+            // Crediting escrow unvested amount to the treasurer, the flat fee is paid in lamports below
+            let transfer_amount = escrow_unvested_amount;
+            let treasury = Treasury::unpack_any_len(&treasury_account_info.data.borrow())?;
+            let (treasury_pool_address, treasury_pool_bump_seed) = find_treasury_address(
+                &treasury.treasury_base_address,
+                treasury.treasury_block_height,
                 msp_account_info.key
             );
 
@@ -1476,25 +1807,15 @@ impl Processor {
                 &[treasury_pool_bump_seed]
             ];
 
-            let transfer_ix = spl_token::instruction::transfer(
-                token_program_account_info.key,
-                treasury_token_account_info.key,
-                treasurer_token_account_info.key,
-                treasury_account_info.key,
-                &[],
-                (transfer_amount * mint_pow) as u64
-            )?;
-
-            invoke_signed(&transfer_ix, 
-                &[
-                    treasury_account_info.clone(),
-                    treasury_token_account_info.clone(),
-                    treasurer_token_account_info.clone(),
-                    token_program_account_info.clone(),
-                    msp_account_info.clone()
-                ],
+            transfer_tokens(
+                token_program_account_info,
+                treasury_token_account_info,
+                beneficiary_mint_account_info,
+                treasurer_token_account_info,
+                treasury_account_info,
+                (transfer_amount * mint_pow) as u64,
                 &[treasury_signer_seed]
-            );
+            )?;
 
             msg!("Transfer {:?} tokens to: {:?}",
                 transfer_amount, 
@@ -1521,10 +1842,17 @@ impl Processor {
         stream.stream_resumed_block_height = 0;
         stream.stream_resumed_block_time = 0;
         stream.auto_pause_in_seconds = 0;
+        stream.beneficiary_token_address = Pubkey::default();
+        stream.last_activity_utc = 0;
         stream.initialized = false;
         // Save
         Stream::pack_into_slice(&stream, &mut stream_account_info.data.borrow_mut());
 
+        let mut treasury = Treasury::unpack_any_len(&treasury_account_info.data.borrow())?;
+        treasury.streams_count = treasury.streams_count.saturating_sub(1);
+
+        treasury.pack_any_len(&mut treasury_account_info.data.borrow_mut())?;
+
         // Debit fees from the initializer of the instruction
         let fee_lamports = CLOSE_STREAM_FLAT_FEE * (LAMPORTS_PER_SOL as f64);
         let fee_transfer_ix = system_instruction::transfer(
@@ -1562,10 +1890,14 @@ impl Processor {
         accounts: &[AccountInfo], 
         _program_id: &Pubkey,
         treasury_block_height: u64,
-        treasury_base_address: Pubkey
+        treasury_base_address: Pubkey,
+        funding_increment: u64,
+        max_streams: u64
 
     ) -> ProgramResult {
         
+        require_accounts(accounts, 11)?;
+
         let account_info_iter = &mut accounts.iter();
         let treasurer_account_info = next_account_info(account_info_iter)?;
         let treasury_account_info = next_account_info(account_info_iter)?;
@@ -1585,12 +1917,18 @@ impl Processor {
             return Err(StreamError::MissingInstructionSignature.into());
         }
 
+        // The treasury is owned by the signer of its creation, CloseTreasury pays its rent to them
+        if treasury_base_address.ne(treasurer_account_info.key)
+        {
+            return Err(StreamError::InstructionNotAuthorized.into());
+        }
+
+        require_any_token_program(token_program_account_info)?;
+
         // Create treasury account
-        let (treasury_pool_address, treasury_pool_bump_seed) = Pubkey::find_program_address(
-            &[
-                treasury_base_address.as_ref(),
-                &treasury_block_height.to_le_bytes(),
-            ], 
+        let (treasury_pool_address, treasury_pool_bump_seed) = find_treasury_address(
+            &treasury_base_address,
+            treasury_block_height,
             msp_account_info.key
         );
 
@@ -1630,9 +1968,10 @@ impl Processor {
         );
 
         // Create treasury associated token account
-        let treasury_token_address = spl_associated_token_account::get_associated_token_address(
+        let treasury_token_address = spl_associated_token_account::get_associated_token_address_with_program_id(
             treasury_account_info.key,
-            treasury_token_mint_account_info.key
+            treasury_token_mint_account_info.key,
+            token_program_account_info.key
         );
 
         if treasury_token_address.ne(treasury_token_account_info.key) 
@@ -1642,10 +1981,11 @@ impl Processor {
 
         if (*treasury_token_account_info.owner).ne(token_program_account_info.key)
         {
-            let create_treasury_associated_token_ix = spl_associated_token_account::create_associated_token_account(
+            let create_treasury_associated_token_ix = spl_associated_token_account::instruction::create_associated_token_account(
                 treasurer_account_info.key,
                 treasury_account_info.key,
-                treasury_token_mint_account_info.key
+                treasury_token_mint_account_info.key,
+                token_program_account_info.key
             );
 
             invoke(&create_treasury_associated_token_ix, &[
@@ -1667,13 +2007,12 @@ impl Processor {
 
         if (*treasury_mint_account_info.key).ne(&Pubkey::default())
         {
-            // Create treasury mint
-            let (treasury_mint_address, treasury_mint_bump_seed) = Pubkey::find_program_address(
-                &[
-                    treasury_base_address.as_ref(),
-                    treasury_pool_address.as_ref(),
-                    &treasury_block_height.to_le_bytes()
-                ], 
+            // Create treasury mint, owned by the token program of the treasury tokens. A Token-2022 mint
+            // without extensions has the SPL Token mint size
+            let (treasury_mint_address, treasury_mint_bump_seed) = find_treasury_mint_address(
+                &treasury_base_address,
+                &treasury_pool_address,
+                treasury_block_height,
                 msp_account_info.key
             );
 
@@ -1714,7 +2053,7 @@ impl Processor {
             );
 
             // Initialize treasury mint
-            let init_mint_ix = spl_token::instruction::initialize_mint(
+            let init_mint_ix = spl_token_2022::instruction::initialize_mint(
                 token_program_account_info.key,
                 treasury_mint_account_info.key,
                 treasury_account_info.key, // msp_account_info.key,
@@ -1733,14 +2072,16 @@ impl Processor {
         }
 
         // Update treasury data
-        let mut treasury = Treasury::unpack_from_slice(&treasury_account_info.data.borrow())?;
+        let mut treasury = Treasury::unpack_any_len(&treasury_account_info.data.borrow())?;
 
         treasury.treasury_block_height = treasury_block_height;
         treasury.treasury_mint_address = *treasury_mint_account_info.key;
         treasury.treasury_base_address = treasury_base_address;
+        treasury.funding_increment = funding_increment;
+        treasury.max_streams = max_streams;
         treasury.initialized = true;
         // Save
-        Treasury::pack_into_slice(&treasury, &mut treasury_account_info.data.borrow_mut());
+        treasury.pack_any_len(&mut treasury_account_info.data.borrow_mut())?;
 
         // Debit Fees from treasurer
         let fees_lamports = CREATE_STREAM_FLAT_FEE * (LAMPORTS_PER_SOL as f64);
@@ -1771,6 +2112,8 @@ impl Processor {
         
     ) -> ProgramResult {
 
+        require_accounts(accounts, 7)?;
+
         let account_info_iter = &mut accounts.iter();
         let source_account_info = next_account_info(account_info_iter)?;
         let source_token_account_info = next_account_info(account_info_iter)?;
@@ -1785,49 +2128,46 @@ impl Processor {
             return Err(StreamError::MissingInstructionSignature.into());
         }
 
+        require_any_token_program(token_program_account_info)?;
+
         let mint = spl_token::state::Mint::unpack_from_slice(&mint_account_info.data.borrow())?;
         let pow = num_traits::pow(10f64, mint.decimals.into());
+        let source_token = spl_token::state::Account::unpack_from_slice(&source_token_account_info.data.borrow())?;
+
+        if source_token.amount < (amount * pow) as u64
+        {
+            return Err(StreamError::InsufficientBalance.into());
+        }
+
         let fee = ADD_FUNDS_PERCENT_FEE * amount / 100f64;
         let transfer_amount = amount - fee;
         // Transfer
-        let transfer_ix = spl_token::instruction::transfer(
-            token_program_account_info.key,
-            source_token_account_info.key,
-            destination_token_account_info.key,
-            source_account_info.key,
-            &[],
-            (transfer_amount * pow) as u64
+        transfer_tokens(
+            token_program_account_info,
+            source_token_account_info,
+            mint_account_info,
+            destination_token_account_info,
+            source_account_info,
+            (transfer_amount * pow) as u64,
+            &[]
         )?;
 
-        invoke(&transfer_ix, &[
-            source_account_info.clone(),
-            source_token_account_info.clone(),
-            destination_token_account_info.clone(),
-            token_program_account_info.clone()
-        ]);
-
         msg!("Transfer {:?} tokens to: {:?}",
             amount, 
             (*destination_token_account_info.key).to_string()
         );
 
         // Pay fees
-        let fees_ix = spl_token::instruction::transfer(
-            token_program_account_info.key,
-            source_token_account_info.key,
-            msp_ops_token_account_info.key,
-            source_account_info.key,
-            &[],
-            (fee * pow) as u64
+        transfer_tokens(
+            token_program_account_info,
+            source_token_account_info,
+            mint_account_info,
+            msp_ops_token_account_info,
+            source_account_info,
+            (fee * pow) as u64,
+            &[]
         )?;
 
-        invoke(&fees_ix, &[
-            source_account_info.clone(),
-            source_token_account_info.clone(),
-            msp_ops_token_account_info.clone(),
-            token_program_account_info.clone()
-        ]);
-
         msg!("Transfer {:?} tokens of fee to: {:?}",
             fee, 
             (*msp_ops_token_account_info.key).to_string()
@@ -1835,4 +2175,2590 @@ impl Processor {
 
         Ok(())
     }
+    fn process_set_beneficiary_token_account(
+        accounts: &[AccountInfo],
+        program_id: &Pubkey,
+        token_account: Pubkey
+
+    ) -> ProgramResult {
+
+        require_accounts(accounts, 4)?;
+
+        let account_info_iter = &mut accounts.iter();
+        let beneficiary_account_info = next_account_info(account_info_iter)?;
+        let beneficiary_token_account_info = next_account_info(account_info_iter)?;
+        let stream_account_info = next_account_info(account_info_iter)?;
+        let _msp_ops_account_info = next_account_info(account_info_iter)?;
+        let clock = Clock::get()?;
+
+        if !beneficiary_account_info.is_signer 
+        {
+            return Err(StreamError::MissingInstructionSignature.into());
+        }
+
+        require_program_owned(stream_account_info, program_id)?;
+        require_account_layout(stream_account_info, Stream::LEN)?;
+
+        let mut stream = Stream::unpack_from_slice(&stream_account_info.data.borrow())?;
+
+        if stream.beneficiary_address.ne(beneficiary_account_info.key)
+        {
+            return Err(StreamError::InstructionNotAuthorized.into()); // Only the beneficiary can choose where to receive the funds
+        }
+
+        if token_account.ne(beneficiary_token_account_info.key) || 
+           ((*beneficiary_token_account_info.owner).ne(&spl_token::id()) &&
+            (*beneficiary_token_account_info.owner).ne(&spl_token_2022::id()))
+        {
+            return Err(StreamError::InvalidBeneficiaryTokenAccount.into());
+        }
+
+        let beneficiary_token = StateWithExtensions::<spl_token_2022::state::Account>::unpack(
+            &beneficiary_token_account_info.data.borrow()
+        )?.base;
+
+        if beneficiary_token.owner.ne(&stream.beneficiary_address) ||
+           beneficiary_token.mint.ne(&stream.beneficiary_associated_token)
+        {
+            return Err(StreamError::InvalidBeneficiaryTokenAccount.into());
+        }
+
+        stream.beneficiary_token_address = token_account;
+        stream.last_activity_utc = clock.unix_timestamp as u64;
+        // Save
+        Stream::pack_into_slice(&stream, &mut stream_account_info.data.borrow_mut());
+
+        msg!("Beneficiary token account set to: {:?}", token_account.to_string());
+
+        Ok(())
+    }
+
+    fn process_transfer_stream_ownership(
+        accounts: &[AccountInfo],
+        program_id: &Pubkey,
+        new_treasurer: Pubkey
+
+    ) -> ProgramResult {
+
+        require_accounts(accounts, 3)?;
+
+        let account_info_iter = &mut accounts.iter();
+        let treasurer_account_info = next_account_info(account_info_iter)?;
+        let stream_account_info = next_account_info(account_info_iter)?;
+        let _msp_ops_account_info = next_account_info(account_info_iter)?;
+        let clock = Clock::get()?;
+
+        if !treasurer_account_info.is_signer 
+        {
+            return Err(StreamError::MissingInstructionSignature.into());
+        }
+
+        require_program_owned(stream_account_info, program_id)?;
+        require_account_layout(stream_account_info, Stream::LEN)?;
+
+        let mut stream = Stream::unpack_from_slice(&stream_account_info.data.borrow())?;
+
+        if stream.treasurer_address.ne(treasurer_account_info.key)
+        {
+            return Err(StreamError::InstructionNotAuthorized.into()); // Only the current treasurer can hand off the stream
+        }
+
+        if new_treasurer.eq(&Pubkey::default())
+        {
+            return Err(StreamError::InvalidArgument.into());
+        }
+
+        stream.treasurer_address = new_treasurer;
+        stream.last_activity_utc = clock.unix_timestamp as u64;
+        // Save
+        Stream::pack_into_slice(&stream, &mut stream_account_info.data.borrow_mut());
+
+        msg!("Stream ownership transferred to: {:?}", new_treasurer.to_string());
+
+        Ok(())
+    }
+
+    fn fund_new_stream<'a, 'b: 'a>(
+        account_info_iter: &mut std::slice::Iter<'a, AccountInfo<'b>>,
+        program_id: &Pubkey,
+        treasurer_account_info: &AccountInfo<'b>,
+        treasury_account_info: &AccountInfo<'b>,
+        beneficiary_mint_account_info: &AccountInfo<'b>,
+        msp_ops_account_info: &AccountInfo<'b>,
+        system_account_info: &AccountInfo<'b>,
+        rent_account_info: &AccountInfo<'b>,
+        stream: &mut Stream,
+        funding_amount: f64,
+        clock: &Clock
+
+    ) -> ProgramResult {
+
+        let treasurer_token_account_info = next_account_info(account_info_iter)?;
+        let treasury_token_account_info = next_account_info(account_info_iter)?;
+        let msp_ops_token_account_info = next_account_info(account_info_iter)?;
+        let token_program_account_info = next_account_info(account_info_iter)?;
+        let treasurer_treasury_token_account_info = next_account_info(account_info_iter)?;
+        let treasury_mint_account_info = next_account_info(account_info_iter)?;
+        let associated_token_program_account_info = next_account_info(account_info_iter)?;
+
+        require_any_token_program(token_program_account_info)?;
+
+        let treasury = Treasury::unpack_any_len(&treasury_account_info.data.borrow())?;
+        let (treasury_pool_address, treasury_pool_bump_seed) = find_treasury_address(
+            &treasury.treasury_base_address,
+            treasury.treasury_block_height,
+            program_id
+        );
+
+        if treasury_pool_address != *treasury_account_info.key 
+        {
+            return Err(StreamError::InvalidTreasuryPool.into());
+        }
+
+        let treasury_token_address = spl_associated_token_account::get_associated_token_address_with_program_id(
+            treasury_account_info.key,
+            beneficiary_mint_account_info.key,
+            token_program_account_info.key
+        );
+
+        if treasury_token_address != *treasury_token_account_info.key 
+        {
+            return Err(StreamError::InvalidTreasuryAccount.into());
+        }
+
+        let msp_ops_token_address = spl_associated_token_account::get_associated_token_address_with_program_id(
+            msp_ops_account_info.key,
+            beneficiary_mint_account_info.key,
+            token_program_account_info.key
+        );
+
+        if msp_ops_token_address != *msp_ops_token_account_info.key 
+        {
+            return Err(StreamError::InvalidMspOpsToken.into());
+        }
+
+        let fee = ADD_FUNDS_PERCENT_FEE * funding_amount / 100f64;
+        let amount = funding_amount - fee;
+        let beneficiary_mint = spl_token::state::Mint::unpack_from_slice(&beneficiary_mint_account_info.data.borrow())?;
+        let beneficiary_pow = num_traits::pow(10f64, beneficiary_mint.decimals.into());
+        let treasurer_token_amount = StateWithExtensions::<spl_token_2022::state::Account>::unpack(
+            &treasurer_token_account_info.data.borrow()
+        )?.base.amount;
+
+        if treasurer_token_amount < (funding_amount * beneficiary_pow) as u64
+        {
+            return Err(StreamError::InsufficientBalance.into());
+        }
+
+        require_funding_increment(&treasury, funding_amount, beneficiary_pow)?;
+
+        let treasury_balance_before = StateWithExtensions::<spl_token_2022::state::Account>::unpack(
+            &treasury_token_account_info.data.borrow()
+        )?.base.amount;
+
+        // The treasurer gets the pool tokens of the funding as any other contributor does
+        if (*treasurer_treasury_token_account_info.key).ne(&Pubkey::default()) &&
+           (*treasury_mint_account_info.key).ne(&Pubkey::default())
+        {
+            Self::mint_pool_tokens(
+                treasurer_account_info,
+                treasurer_treasury_token_account_info,
+                treasury_account_info,
+                treasury_mint_account_info,
+                token_program_account_info,
+                associated_token_program_account_info,
+                system_account_info,
+                rent_account_info,
+                &treasury,
+                stream,
+                treasury_pool_bump_seed,
+                amount,
+                treasury_balance_before as f64 / beneficiary_pow
+            )?;
+        }
+
+        // Transfer tokens from treasurer to treasury
+        let received_base_amount = transfer_tokens(
+            token_program_account_info,
+            treasurer_token_account_info,
+            beneficiary_mint_account_info,
+            treasury_token_account_info,
+            treasurer_account_info,
+            (amount * beneficiary_pow) as u64,
+            &[]
+        )?;
+
+        // The deposits are only credited for tokens that actually reached the treasury
+        let treasury_balance_after = StateWithExtensions::<spl_token_2022::state::Account>::unpack(
+            &treasury_token_account_info.data.borrow()
+        )?.base.amount;
+
+        if treasury_balance_after.checked_sub(treasury_balance_before) != Some(received_base_amount)
+        {
+            return Err(StreamError::FundingNotReceived.into());
+        }
+
+        let received_amount = received_base_amount as f64 / beneficiary_pow;
+
+        msg!("Transfer {:?} tokens to: {:?}",
+            received_amount, 
+            (*treasury_token_account_info.key).to_string()
+        );
+
+        // Pay fees
+        transfer_tokens(
+            token_program_account_info,
+            treasurer_token_account_info,
+            beneficiary_mint_account_info,
+            msp_ops_token_account_info,
+            treasurer_account_info,
+            (fee * beneficiary_pow) as u64,
+            &[]
+        )?;
+
+        msg!("Transfer {:?} tokens of fee to: {:?}",
+            fee, 
+            (*msp_ops_token_account_info.key).to_string()
+        );
+
+        stream.total_deposits = received_amount;
+        stream.funded_on_utc = clock.unix_timestamp as u64;
+        resume_stream(stream, clock.slot as u64, clock.unix_timestamp as u64);
+
+        Ok(())
+    }
+
+    // Mints the contributor the treasury pool tokens for `amount` contributed to a treasury holding
+    // `treasury_balance` (UI amounts), creating their pool token account if it does not exist yet
+    fn mint_pool_tokens<'a>(
+        contributor_account_info: &AccountInfo<'a>,
+        contributor_treasury_token_account_info: &AccountInfo<'a>,
+        treasury_account_info: &AccountInfo<'a>,
+        treasury_mint_account_info: &AccountInfo<'a>,
+        token_program_account_info: &AccountInfo<'a>,
+        associated_token_program_account_info: &AccountInfo<'a>,
+        system_account_info: &AccountInfo<'a>,
+        rent_account_info: &AccountInfo<'a>,
+        treasury: &Treasury,
+        stream: &Stream,
+        treasury_pool_bump_seed: u8,
+        amount: f64,
+        treasury_balance: f64
+
+    ) -> ProgramResult {
+
+        if treasury.treasury_mint_address.ne(treasury_mint_account_info.key)
+        {
+            return Err(StreamError::InvalidTreasuryPool.into());
+        }
+
+        if (*contributor_treasury_token_account_info.owner).ne(token_program_account_info.key)
+        {
+            // Create contributor treasury associated token account
+            let contributor_treasury_token_address = spl_associated_token_account::get_associated_token_address_with_program_id(
+                contributor_account_info.key,
+                treasury_mint_account_info.key,
+                token_program_account_info.key
+            );
+
+            if contributor_treasury_token_address != *contributor_treasury_token_account_info.key 
+            {
+                return Err(StreamError::InvalidContributorTreasuryToken.into());
+            }
+
+            // Create the contributor treasury token account if there is a treasury pool and the account does not exists
+            let create_contributor_treasury_atoken_ix = spl_associated_token_account::instruction::create_associated_token_account(
+                contributor_account_info.key,
+                contributor_account_info.key,
+                treasury_mint_account_info.key,
+                token_program_account_info.key
+            );
+
+            invoke(&create_contributor_treasury_atoken_ix, &[
+                associated_token_program_account_info.clone(),
+                contributor_account_info.clone(),
+                contributor_treasury_token_account_info.clone(),
+                treasury_mint_account_info.clone(),
+                system_account_info.clone(),
+                token_program_account_info.clone(),
+                rent_account_info.clone()
+            ]);
+
+            msg!(
+                "Contributor treasury associated token account created at: {:?} address", 
+                (*contributor_treasury_token_account_info.key).to_string()
+            );
+        }
+        
+        // Mint just if there is a treasury pool
+        let treasury_mint = StateWithExtensions::<spl_token_2022::state::Mint>::unpack(
+            &treasury_mint_account_info.data.borrow()
+        )?.base;
+
+        require_pool_mint_authority(&treasury_mint, treasury_account_info.key)?;
+
+        let treasury_mint_signer_seed: &[&[_]] = &[
+            treasury.treasury_base_address.as_ref(),
+            &treasury.treasury_block_height.to_le_bytes(),
+            &[treasury_pool_bump_seed]
+        ];
+ 
+        let treasury_pow = num_traits::pow(10f64, treasury_mint.decimals.into());    
+        let pool_tokens = stream.pool_tokens_for_contribution(
+            (amount * treasury_pow) as u64,
+            treasury_mint.supply,
+            (treasury_balance * treasury_pow) as u64
+        )?;
+        let mint_to_ix = spl_token_2022::instruction::mint_to(
+            token_program_account_info.key,
+            treasury_mint_account_info.key,
+            contributor_treasury_token_account_info.key,
+            treasury_account_info.key,
+            &[],
+            pool_tokens
+        )?;
+
+        invoke_signed(&mint_to_ix,
+            &[
+                token_program_account_info.clone(),
+                treasury_mint_account_info.clone(),
+                contributor_treasury_token_account_info.clone(),
+                treasury_account_info.clone()
+            ],
+            &[treasury_mint_signer_seed]
+        )?;
+
+        msg!("Minting {:?} treasury pool tokens to: {:?}", 
+            amount, 
+            (*contributor_treasury_token_account_info.key).to_string()
+        );
+
+        Ok(())
+    }
+
+    fn process_expire_proposal(
+        accounts: &[AccountInfo], 
+        program_id: &Pubkey
+
+    ) -> ProgramResult {
+
+        require_accounts(accounts, 3)?;
+
+        let account_info_iter = &mut accounts.iter();
+        let stream_terms_account_info = next_account_info(account_info_iter)?;
+        let proposer_account_info = next_account_info(account_info_iter)?;
+        let _msp_ops_account_info = next_account_info(account_info_iter)?;
+        let clock = Clock::get()?;
+
+        require_program_owned(stream_terms_account_info, program_id)?;
+        require_account_layout(stream_terms_account_info, StreamTerms::LEN)?;
+
+        let stream_terms = StreamTerms::unpack_from_slice(&stream_terms_account_info.data.borrow())?;
+
+        if !stream_terms.is_initialized()
+        {
+            return Err(StreamError::InvalidStreamData.into());
+        }
+
+        if stream_terms.proposed_by.ne(proposer_account_info.key)
+        {
+            return Err(StreamError::InstructionNotAuthorized.into()); // The rent goes back to the proposer only
+        }
+
+        if !stream_terms.is_expired(clock.unix_timestamp as u64)
+        {
+            return Err(StreamError::ProposalNotExpired.into());
+        }
+
+        // Close stream terms account
+        let proposer_lamports = proposer_account_info.lamports();
+        let stream_terms_lamports = stream_terms_account_info.lamports();
+
+        **stream_terms_account_info.lamports.borrow_mut() = 0;
+        **proposer_account_info.lamports.borrow_mut() = proposer_lamports
+            .checked_add(stream_terms_lamports)
+            .ok_or(StreamError::Overflow)?;
+
+        StreamTerms::pack_into_slice(&StreamTerms::default(), &mut stream_terms_account_info.data.borrow_mut());
+
+        Ok(())
+    }
+
+    fn process_close_treasury(
+        accounts: &[AccountInfo], 
+        program_id: &Pubkey
+
+    ) -> ProgramResult {
+
+        require_accounts(accounts, 4)?;
+
+        let account_info_iter = &mut accounts.iter();
+        let treasurer_account_info = next_account_info(account_info_iter)?;
+        let treasury_account_info = next_account_info(account_info_iter)?;
+        let treasury_token_account_info = next_account_info(account_info_iter)?;
+        let _msp_ops_account_info = next_account_info(account_info_iter)?;
+
+        if !treasurer_account_info.is_signer
+        {
+            return Err(StreamError::MissingInstructionSignature.into());
+        }
+
+        require_program_owned(treasury_account_info, program_id)?;
+        require_treasury_layout(treasury_account_info)?;
+
+        let treasury = Treasury::unpack_any_len(&treasury_account_info.data.borrow())?;
+
+        if treasury.treasury_base_address.ne(treasurer_account_info.key)
+        {
+            return Err(StreamError::InstructionNotAuthorized.into()); // Only the treasurer can close the treasury
+        }
+
+        // Legacy treasuries do not count their streams, there is no telling whether they are empty
+        if treasury_account_info.data_len() == Treasury::LEGACY_LEN
+        {
+            msg!("Error: Treasuries of the legacy layout can not be closed");
+            return Err(StreamError::AccountLayoutMismatch.into());
+        }
+
+        if treasury.streams_count != 0
+        {
+            return Err(StreamError::TreasuryNotEmpty.into());
+        }
+
+        if (*treasury_token_account_info.owner).ne(&spl_token::id()) &&
+           (*treasury_token_account_info.owner).ne(&spl_token_2022::id())
+        {
+            return Err(StreamError::InvalidTreasuryToken.into());
+        }
+
+        let treasury_token = StateWithExtensions::<spl_token_2022::state::Account>::unpack(
+            &treasury_token_account_info.data.borrow()
+        )?.base;
+
+        if treasury_token.owner.ne(treasury_account_info.key)
+        {
+            return Err(StreamError::InvalidTreasuryToken.into());
+        }
+
+        if treasury_token.amount != 0
+        {
+            return Err(StreamError::TreasuryNotEmpty.into());
+        }
+
+        Treasury::default().pack_any_len(&mut treasury_account_info.data.borrow_mut())?;
+
+        // Close the treasury
+        let treasurer_lamports = treasurer_account_info.lamports();
+        let treasury_lamports = treasury_account_info.lamports();
+
+        **treasury_account_info.lamports.borrow_mut() = 0;
+        **treasurer_account_info.lamports.borrow_mut() = treasurer_lamports
+            .checked_add(treasury_lamports)
+            .ok_or(StreamError::Overflow)?;
+
+        msg!("Closing the treasury");
+
+        Ok(())
+    }
+
+    fn process_set_auto_pause_threshold(
+        accounts: &[AccountInfo],
+        program_id: &Pubkey,
+        seconds: u64
+
+    ) -> ProgramResult {
+
+        require_accounts(accounts, 3)?;
+
+        let account_info_iter = &mut accounts.iter();
+        let treasurer_account_info = next_account_info(account_info_iter)?;
+        let stream_account_info = next_account_info(account_info_iter)?;
+        let _msp_ops_account_info = next_account_info(account_info_iter)?;
+        let clock = Clock::get()?;
+
+        if !treasurer_account_info.is_signer 
+        {
+            return Err(StreamError::MissingInstructionSignature.into());
+        }
+
+        require_program_owned(stream_account_info, program_id)?;
+        require_account_layout(stream_account_info, Stream::LEN)?;
+
+        let mut stream = Stream::unpack_from_slice(&stream_account_info.data.borrow())?;
+
+        if stream.treasurer_address.ne(treasurer_account_info.key)
+        {
+            return Err(StreamError::InstructionNotAuthorized.into()); // Only the treasurer can tune the auto-pause
+        }
+
+        let current_block_time = clock.unix_timestamp as u64;
+
+        // Zero disables the auto-pause, otherwise the funds left to stream have to cover one window
+        check_rate_for_funding(
+            stream.locked_amount(current_block_time),
+            stream.rate_amount,
+            stream.rate_interval_in_seconds,
+            seconds
+        )?;
+
+        if resumes_with_auto_pause_threshold(&stream, seconds, current_block_time)
+        {
+            resume_stream(&mut stream, clock.slot as u64, current_block_time);
+            msg!("Resuming the auto-paused stream");
+        }
+
+        stream.auto_pause_in_seconds = seconds;
+        stream.last_activity_utc = clock.unix_timestamp as u64;
+        // Save
+        Stream::pack_into_slice(&stream, &mut stream_account_info.data.borrow_mut());
+
+        Ok(())
+    }
+
+    fn process_renew_stream(
+        accounts: &[AccountInfo],
+        program_id: &Pubkey,
+        beneficiary_address: Pubkey,
+        stream_name: String,
+        rate_amount: f64,
+        rate_interval_in_seconds: u64,
+        start_utc: u64,
+        rate_cliff_in_seconds: u64,
+        cliff_vest_amount: f64,
+        cliff_vest_percent: f64,
+        auto_pause_in_seconds: u64
+        
+    ) -> ProgramResult {
+
+        require_accounts(accounts, 8)?;
+
+        let account_info_iter = &mut accounts.iter();
+        let treasurer_account_info = next_account_info(account_info_iter)?;
+        let stream_account_info = next_account_info(account_info_iter)?;
+        let new_stream_account_info = next_account_info(account_info_iter)?;
+        let treasury_account_info = next_account_info(account_info_iter)?;
+        let msp_ops_account_info = next_account_info(account_info_iter)?;
+        let msp_account_info = next_account_info(account_info_iter)?;
+        let system_account_info = next_account_info(account_info_iter)?;
+        let rent_account_info = next_account_info(account_info_iter)?;
+        let rent = &Rent::from_account_info(rent_account_info)?;
+        let clock = Clock::get()?;
+
+        if !treasurer_account_info.is_signer 
+        {
+            return Err(StreamError::MissingInstructionSignature.into());
+        }
+
+        check_stream_name(&stream_name)?;
+
+        require_program_owned(stream_account_info, program_id)?;
+        require_account_layout(stream_account_info, Stream::LEN)?;
+        require_program_owned(treasury_account_info, program_id)?;
+        require_treasury_layout(treasury_account_info)?;
+
+        let mut stream = Stream::unpack_from_slice(&stream_account_info.data.borrow())?;
+
+        if stream.treasurer_address.ne(treasurer_account_info.key)
+        {
+            return Err(StreamError::InstructionNotAuthorized.into()); // Only the treasurer can renew a stream
+        }
+
+        if stream.treasury_address.ne(treasury_account_info.key)
+        {
+            return Err(StreamError::InvalidTreasuryAccount.into());
+        }
+
+        let current_block_time = clock.unix_timestamp as u64;
+        let escrow_vested_amount = stream.withdrawable_ui_amount(current_block_time);
+        let remaining_amount = stream.total_deposits - stream.total_withdrawals;
+
+        // The vested amount not withdrawn yet still belongs to the current beneficiary
+        // and the unvested amount needs a rate to ever vest
+        if (escrow_vested_amount > 0.0 && beneficiary_address.ne(&stream.beneficiary_address)) ||
+           (remaining_amount > escrow_vested_amount && rate_amount <= 0.0)
+        {
+            return Err(StreamError::RenewalStrandsFunds.into());
+        }
+
+        // The vested amount is locked again under the new terms, only its owner can agree to that
+        if escrow_vested_amount > 0.0
+        {
+            let beneficiary_account_info = next_account_info(account_info_iter)?;
+
+            if beneficiary_account_info.key.ne(&stream.beneficiary_address)
+            {
+                return Err(StreamError::InstructionNotAuthorized.into());
+            }
+
+            if !beneficiary_account_info.is_signer
+            {
+                return Err(StreamError::MissingInstructionSignature.into());
+            }
+        }
+
+        let new_stream_balance = Stream::rent_exempt_lamports(rent);
+        let create_stream_ix = system_instruction::create_account(
+            treasurer_account_info.key,
+            new_stream_account_info.key,
+            new_stream_balance,
+            u64::from_le_bytes(Stream::LEN.to_le_bytes()),
+            msp_account_info.key
+        );
+
+        invoke(&create_stream_ix, &[
+            treasurer_account_info.clone(),
+            new_stream_account_info.clone(),
+            msp_account_info.clone(),
+            system_account_info.clone()
+        ])?;
+
+        msg!("Stream account created with address: {:?}", (*new_stream_account_info.key).to_string());
+        let mut new_stream = Stream::unpack_from_slice(&new_stream_account_info.data.borrow())?;
+
+        new_stream.version = CURRENT_VERSION;
+        new_stream.stream_name = stream_name;
+        new_stream.treasurer_address = *treasurer_account_info.key;
+        new_stream.rate_amount = rate_amount;
+        new_stream.rate_interval_in_seconds = rate_interval_in_seconds;
+        new_stream.funded_on_utc = current_block_time;
+        new_stream.start_utc = start_utc;
+        new_stream.rate_cliff_in_seconds = rate_cliff_in_seconds;
+        new_stream.cliff_vest_amount = cliff_vest_amount;
+        new_stream.cliff_vest_percent = cliff_vest_percent;
+        new_stream.beneficiary_address = beneficiary_address;
+        new_stream.beneficiary_associated_token = stream.beneficiary_associated_token;
+        new_stream.beneficiary_token_address = stream.beneficiary_token_address;
+        new_stream.treasury_address = stream.treasury_address;
+        new_stream.total_deposits = remaining_amount;
+        new_stream.total_withdrawals = 0.0;
+        // Carry the vested amount over as already vested escrow
+        new_stream.escrow_vested_amount_snap = escrow_vested_amount;
+        new_stream.escrow_vested_amount_snap_block_height = clock.slot as u64;
+        // The carried amount does not include the new cliff, date the snapshot before its end
+        new_stream.escrow_vested_amount_snap_block_time = cmp::min(
+            current_block_time,
+            new_stream.cliff_end_utc().saturating_sub(1)
+        );
+        new_stream.stream_resumed_block_height = clock.slot as u64;
+        new_stream.stream_resumed_block_time = current_block_time;
+        new_stream.auto_pause_in_seconds = auto_pause_in_seconds;
+
+        // Only the unvested part streams under the new terms
+        check_new_stream_terms(&new_stream, remaining_amount - escrow_vested_amount, current_block_time)?;
+
+        new_stream.last_activity_utc = current_block_time;
+        new_stream.initialized = true;
+        // Save
+        Stream::pack_into_slice(&new_stream, &mut new_stream_account_info.data.borrow_mut());
+
+        msg!("Moved {:?} tokens to stream: {:?}", 
+            remaining_amount, 
+            (*new_stream_account_info.key).to_string()
+        );
+
+        // Clean the renewed stream, the treasury keeps the same amount of streams
+        stream = Stream::default();
+        Stream::pack_into_slice(&stream, &mut stream_account_info.data.borrow_mut());
+
+        // Debit Fees from treasurer
+        let fees_lamports = CREATE_STREAM_FLAT_FEE * (LAMPORTS_PER_SOL as f64);
+        let fees_transfer_ix = system_instruction::transfer(
+            treasurer_account_info.key,
+            msp_ops_account_info.key,
+            fees_lamports as u64
+        );
+
+        invoke(&fees_transfer_ix, &[
+            treasurer_account_info.clone(),
+            msp_ops_account_info.clone(),
+            system_account_info.clone()
+        ])?;
+
+        msg!("Transfer {:?} lamports of fee to: {:?}", 
+            fees_lamports, 
+            (*msp_ops_account_info.key).to_string()
+        );
+
+        // Close the renewed stream account
+        let treasurer_lamports = treasurer_account_info.lamports();
+        let stream_lamports = stream_account_info.lamports();
+
+        **stream_account_info.lamports.borrow_mut() = 0;
+        **treasurer_account_info.lamports.borrow_mut() = treasurer_lamports
+            .checked_add(stream_lamports)
+            .ok_or(StreamError::Overflow)?;
+
+        msg!("Closing the renewed stream");
+
+        Ok(())
+    }
+
+    fn process_set_withdraw_hook(
+        accounts: &[AccountInfo],
+        program_id: &Pubkey,
+        hook_program: Option<Pubkey>
+
+    ) -> ProgramResult {
+
+        require_accounts(accounts, 3)?;
+
+        let account_info_iter = &mut accounts.iter();
+        let beneficiary_account_info = next_account_info(account_info_iter)?;
+        let stream_account_info = next_account_info(account_info_iter)?;
+        let _msp_ops_account_info = next_account_info(account_info_iter)?;
+        let clock = Clock::get()?;
+
+        if !beneficiary_account_info.is_signer 
+        {
+            return Err(StreamError::MissingInstructionSignature.into());
+        }
+
+        require_program_owned(stream_account_info, program_id)?;
+        require_account_layout(stream_account_info, Stream::LEN)?;
+
+        let mut stream = Stream::unpack_from_slice(&stream_account_info.data.borrow())?;
+
+        if stream.beneficiary_address.ne(beneficiary_account_info.key)
+        {
+            return Err(StreamError::InstructionNotAuthorized.into());
+        }
+
+        if let Some(hook_program) = hook_program
+        {
+            let hook_program_account_info = next_account_info(account_info_iter)?;
+
+            if hook_program_account_info.key.ne(&hook_program) || !hook_program_account_info.executable
+            {
+                return Err(StreamError::InvalidWithdrawHook.into());
+            }
+        }
+
+        stream.withdraw_hook_program = hook_program;
+        stream.last_activity_utc = clock.unix_timestamp as u64;
+        // Save
+        Stream::pack_into_slice(&stream, &mut stream_account_info.data.borrow_mut());
+
+        Ok(())
+    }
+
+    fn process_rename_stream(
+        accounts: &[AccountInfo],
+        program_id: &Pubkey,
+        stream_name: String
+
+    ) -> ProgramResult {
+
+        require_accounts(accounts, 3)?;
+
+        let account_info_iter = &mut accounts.iter();
+        let treasurer_account_info = next_account_info(account_info_iter)?;
+        let stream_account_info = next_account_info(account_info_iter)?;
+        let _msp_ops_account_info = next_account_info(account_info_iter)?;
+        let clock = Clock::get()?;
+
+        if !treasurer_account_info.is_signer 
+        {
+            return Err(StreamError::MissingInstructionSignature.into());
+        }
+
+        check_stream_name(&stream_name)?;
+
+        require_program_owned(stream_account_info, program_id)?;
+        require_account_layout(stream_account_info, Stream::LEN)?;
+
+        let mut stream = Stream::unpack_from_slice(&stream_account_info.data.borrow())?;
+
+        if stream.treasurer_address.ne(treasurer_account_info.key)
+        {
+            return Err(StreamError::InstructionNotAuthorized.into());
+        }
+
+        stream.stream_name = stream_name;
+        stream.last_activity_utc = clock.unix_timestamp as u64;
+        // Save
+        Stream::pack_into_slice(&stream, &mut stream_account_info.data.borrow_mut());
+
+        Ok(())
+    }
+
+    fn process_multi_withdraw(
+        accounts: &[AccountInfo],
+        program_id: &Pubkey,
+        count: u8
+
+    ) -> ProgramResult {
+
+        if count == 0 || count > MAX_MULTI_WITHDRAW_STREAMS
+        {
+            return Err(StreamError::BatchTooLarge.into());
+        }
+
+        require_accounts(accounts, 7 + 3 * count as usize)?;
+
+        let (shared_accounts, stream_accounts) = accounts.split_at(7);
+        let beneficiary_account_info = &shared_accounts[0];
+
+        for stream_group in stream_accounts.chunks_exact(3).take(count as usize)
+        {
+            let stream_account_info = &stream_group[2];
+
+            require_program_owned(stream_account_info, program_id)?;
+            require_account_layout(stream_account_info, Stream::LEN)?;
+
+            let stream = Stream::unpack_from_slice(&stream_account_info.data.borrow())?;
+
+            if stream.beneficiary_address.ne(beneficiary_account_info.key)
+            {
+                return Err(StreamError::InstructionNotAuthorized.into());
+            }
+
+            // There is no room for the hook program accounts, a hooked stream has to use Withdraw
+            if stream.withdraw_hook_program.is_some()
+            {
+                return Err(StreamError::InvalidWithdrawHook.into());
+            }
+
+            // Same accounts (and order) as a Withdraw instruction
+            let withdraw_accounts = [
+                shared_accounts[0].clone(),
+                shared_accounts[1].clone(),
+                shared_accounts[2].clone(),
+                stream_group[0].clone(),
+                stream_group[1].clone(),
+                stream_group[2].clone(),
+                shared_accounts[3].clone(),
+                shared_accounts[4].clone(),
+                shared_accounts[5].clone(),
+                shared_accounts[6].clone()
+            ];
+
+            msg!("Withdrawing from stream: {:?}", stream_account_info.key.to_string());
+
+            Self::process_withdraw(&withdraw_accounts, program_id, None)?;
+        }
+
+        Ok(())
+    }
+
+    fn process_donate_stream(
+        accounts: &[AccountInfo],
+        program_id: &Pubkey,
+        new_treasurer: Pubkey,
+        new_beneficiary: Pubkey
+
+    ) -> ProgramResult {
+
+        require_accounts(accounts, 4)?;
+
+        let account_info_iter = &mut accounts.iter();
+        let treasurer_account_info = next_account_info(account_info_iter)?;
+        let beneficiary_account_info = next_account_info(account_info_iter)?;
+        let stream_account_info = next_account_info(account_info_iter)?;
+        let _msp_ops_account_info = next_account_info(account_info_iter)?;
+        let clock = Clock::get()?;
+
+        if !treasurer_account_info.is_signer || !beneficiary_account_info.is_signer
+        {
+            return Err(StreamError::MissingInstructionSignature.into());
+        }
+
+        require_program_owned(stream_account_info, program_id)?;
+        require_account_layout(stream_account_info, Stream::LEN)?;
+
+        let mut stream = Stream::unpack_from_slice(&stream_account_info.data.borrow())?;
+
+        if stream.treasurer_address.ne(treasurer_account_info.key) || 
+           stream.beneficiary_address.ne(beneficiary_account_info.key)
+        {
+            return Err(StreamError::InstructionNotAuthorized.into());
+        }
+
+        // Both roles have to go to real and distinct accounts
+        if new_treasurer.eq(&Pubkey::default()) || 
+           new_beneficiary.eq(&Pubkey::default()) ||
+           new_treasurer.eq(&new_beneficiary)
+        {
+            return Err(StreamError::InvalidArgument.into());
+        }
+
+        stream.treasurer_address = new_treasurer;
+        stream.beneficiary_address = new_beneficiary;
+        stream.beneficiary_token_address = Pubkey::default();
+        stream.withdraw_hook_program = None;
+        stream.last_activity_utc = clock.unix_timestamp as u64;
+        // Save
+        Stream::pack_into_slice(&stream, &mut stream_account_info.data.borrow_mut());
+
+        msg!(
+            "Stream donated to treasurer: {:?} and beneficiary: {:?}", 
+            new_treasurer.to_string(),
+            new_beneficiary.to_string()
+        );
+
+        Ok(())
+    }
+
+    fn process_top_up(
+        accounts: &[AccountInfo],
+        program_id: &Pubkey,
+        amount: u64,
+        min_new_depletion_utc: u64
+
+    ) -> ProgramResult {
+
+        require_accounts(accounts, 15)?;
+
+        let beneficiary_mint_account_info = &accounts[3];
+        let stream_account_info = &accounts[7];
+        let clock = Clock::get()?;
+
+        let beneficiary_mint = spl_token::state::Mint::unpack_from_slice(&beneficiary_mint_account_info.data.borrow())?;
+        let beneficiary_pow = num_traits::pow(10f64, beneficiary_mint.decimals.into());
+
+        Self::process_add_funds(
+            accounts, 
+            program_id, 
+            amount as f64 / beneficiary_pow,
+            clock.unix_timestamp as u64,
+            true
+        )?;
+
+        let mut stream = Stream::unpack_from_slice(&stream_account_info.data.borrow())?;
+        let new_depletion_utc = stream.estimated_depletion_utc(clock.unix_timestamp as u64);
+
+        if new_depletion_utc < min_new_depletion_utc
+        {
+            return Err(StreamError::DepletionTooSoon.into());
+        }
+
+        stream.treasury_estimated_depletion_utc = new_depletion_utc;
+        Stream::pack_into_slice(&stream, &mut stream_account_info.data.borrow_mut());
+
+        Ok(())
+    }
+}
+
+fn require_program_owned(
+    account: &AccountInfo,
+    program_id: &Pubkey
+
+) -> Result<(), StreamError> {
+
+    if account.owner != program_id
+    {
+        return Err(StreamError::IncorrectProgramId);
+    }
+
+    Ok(())
+}
+
+fn require_account_layout(
+    account: &AccountInfo,
+    len: usize
+
+) -> Result<(), StreamError> {
+
+    if account.data_len() != len
+    {
+        msg!("Error: Account {:?} data does not match the layout expected by the instruction", account.key.to_string());
+        return Err(StreamError::AccountLayoutMismatch);
+    }
+
+    Ok(())
+}
+
+// Treasuries created before the funding increment, the streams count and `max_streams` were added
+// keep the legacy layout
+fn require_treasury_layout(account: &AccountInfo) -> Result<(), StreamError> {
+
+    if account.data_len() != Treasury::LEN && account.data_len() != Treasury::LEGACY_LEN
+    {
+        msg!("Error: Account {:?} data does not match the treasury layout", account.key.to_string());
+        return Err(StreamError::AccountLayoutMismatch);
+    }
+
+    Ok(())
+}
+
+fn require_any_token_program(account: &AccountInfo) -> Result<(), StreamError> {
+
+    if account.key != &spl_token::id() && account.key != &spl_token_2022::id()
+    {
+        return Err(StreamError::IncorrectProgramId);
+    }
+
+    Ok(())
+}
+
+// Decimals of the mint and the fee it charges to transfer `amount` (None if it charges no fee).
+// Fails for Token-2022 extensions the program can not account for
+fn mint_transfer_fee(
+    mint_account_info: &AccountInfo,
+    amount: u64,
+    epoch: u64
+
+) -> Result<(u8, Option<u64>), ProgramError> {
+
+    let mint_data = mint_account_info.data.borrow();
+
+    if (*mint_account_info.owner).eq(&spl_token::id())
+    {
+        let mint = spl_token::state::Mint::unpack_from_slice(&mint_data)?;
+        return Ok((mint.decimals, None));
+    }
+
+    if (*mint_account_info.owner).ne(&spl_token_2022::id())
+    {
+        return Err(StreamError::IncorrectProgramId.into());
+    }
+
+    let mint = StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&mint_data)?;
+    let mut transfer_fee = None;
+
+    for extension_type in mint.get_extension_types()?
+    {
+        match extension_type
+        {
+            ExtensionType::TransferFeeConfig => {
+                let transfer_fee_config = mint.get_extension::<TransferFeeConfig>()?;
+                let fee = transfer_fee_config
+                    .calculate_epoch_fee(epoch, amount)
+                    .ok_or(StreamError::Overflow)?;
+
+                transfer_fee = Some(fee);
+            },
+            ExtensionType::MintCloseAuthority => {},
+            _ => {
+                msg!("Error: Mint extension {:?} is not supported", extension_type);
+                return Err(StreamError::UnsupportedMintExtension.into());
+            }
+        }
+    }
+
+    Ok((mint.base.decimals, transfer_fee))
+}
+
+// Transfers `amount` base units with the checked token instructions so Token-2022 mints are
+// supported too. Returns the amount that reached the destination once the transfer fee is withheld
+fn transfer_tokens<'a>(
+    token_program_account_info: &AccountInfo<'a>,
+    source_account_info: &AccountInfo<'a>,
+    mint_account_info: &AccountInfo<'a>,
+    destination_account_info: &AccountInfo<'a>,
+    authority_account_info: &AccountInfo<'a>,
+    amount: u64,
+    signer_seeds: &[&[&[u8]]]
+
+) -> Result<u64, ProgramError> {
+
+    let clock = Clock::get()?;
+    let (decimals, transfer_fee) = mint_transfer_fee(mint_account_info, amount, clock.epoch)?;
+    let transfer_ix = match transfer_fee
+    {
+        Some(fee) => transfer_checked_with_fee(
+            token_program_account_info.key,
+            source_account_info.key,
+            mint_account_info.key,
+            destination_account_info.key,
+            authority_account_info.key,
+            &[],
+            amount,
+            decimals,
+            fee
+        )?,
+        None => spl_token_2022::instruction::transfer_checked(
+            token_program_account_info.key,
+            source_account_info.key,
+            mint_account_info.key,
+            destination_account_info.key,
+            authority_account_info.key,
+            &[],
+            amount,
+            decimals
+        )?
+    };
+
+    invoke_signed(&transfer_ix, 
+        &[
+            source_account_info.clone(),
+            mint_account_info.clone(),
+            destination_account_info.clone(),
+            authority_account_info.clone(),
+            token_program_account_info.clone()
+        ],
+        signer_seeds
+    )?;
+
+    Ok(amount - transfer_fee.unwrap_or(0))
+}
+
+// Stream totals are kept in UI units, the updates are checked on their token base units
+fn checked_add_amount(total: f64, amount: f64, pow: f64) -> Result<f64, StreamError> {
+
+    let total = to_base_amount(total, pow)?
+        .checked_add(to_base_amount(amount, pow)?)
+        .ok_or(StreamError::AmountOverflow)?;
+
+    Ok(total as f64 / pow)
+}
+
+fn checked_sub_amount(total: f64, amount: f64, pow: f64) -> Result<f64, StreamError> {
+
+    let total = to_base_amount(total, pow)?
+        .checked_sub(to_base_amount(amount, pow)?)
+        .ok_or(StreamError::AmountOverflow)?;
+
+    Ok(total as f64 / pow)
+}
+
+// Funding has to be a whole multiple of the treasury `funding_increment` (base units, 0 for any amount).
+// The amount is rounded to base units, truncating would turn e.g. 0.29 into 28 hundredths
+fn require_funding_increment(
+    treasury: &Treasury,
+    funding_amount: f64,
+    pow: f64
+
+) -> Result<(), StreamError> {
+
+    if treasury.funding_increment != 0 && to_base_amount(funding_amount, pow)? % treasury.funding_increment != 0
+    {
+        return Err(StreamError::InvalidFundingIncrement);
+    }
+
+    Ok(())
+}
+
+// Copies the terms an approved proposal changes into `stream`, unset terms keep the stream values
+fn apply_stream_terms(stream: &mut Stream, stream_terms: &StreamTerms) {
+
+    if !stream_terms.stream_name.is_empty() && stream_terms.stream_name.ne(&stream.stream_name) 
+    {
+        stream.stream_name = stream_terms.stream_name.clone();
+    }
+
+    if stream_terms.treasurer_address.ne(&Pubkey::default()) && 
+        stream_terms.treasurer_address.ne(&stream.treasurer_address) 
+    {
+        stream.treasurer_address = stream_terms.treasurer_address;
+    }
+
+    if stream_terms.beneficiary_address.ne(&Pubkey::default()) && 
+        stream_terms.beneficiary_address.ne(&stream.beneficiary_address) 
+    {        
+        stream.beneficiary_address = stream_terms.beneficiary_address;
+    }
+
+    if stream_terms.associated_token_address.ne(&Pubkey::default()) && 
+        stream_terms.associated_token_address.ne(&stream.beneficiary_associated_token) 
+    {       
+        stream.beneficiary_associated_token = stream_terms.associated_token_address;
+    }
+
+    if stream_terms.rate_amount != 0.0 && stream_terms.rate_amount != stream.rate_amount 
+    {       
+        stream.rate_amount = stream_terms.rate_amount;
+    }
+
+    if stream_terms.rate_interval_in_seconds != 0 && 
+       stream.rate_interval_in_seconds != stream_terms.rate_interval_in_seconds 
+    {
+        stream.rate_interval_in_seconds = stream_terms.rate_interval_in_seconds;
+    }
+
+    if stream_terms.rate_cliff_in_seconds != 0 && 
+        stream_terms.rate_cliff_in_seconds != stream.rate_cliff_in_seconds 
+    {
+        stream.rate_cliff_in_seconds = stream_terms.rate_cliff_in_seconds;
+    }
+
+    if stream_terms.cliff_vest_amount != 0.0 && 
+        stream_terms.cliff_vest_amount != stream.cliff_vest_amount 
+    {
+        stream.cliff_vest_amount = stream_terms.cliff_vest_amount;
+    }
+
+    if stream_terms.cliff_vest_percent != 100 as f64 && 
+        stream_terms.cliff_vest_percent != stream.cliff_vest_percent 
+    {
+        stream.cliff_vest_percent = stream_terms.cliff_vest_percent;
+    }
+
+    if stream_terms.auto_pause_in_seconds != 0 && 
+        stream_terms.auto_pause_in_seconds != stream.auto_pause_in_seconds 
+    {
+        stream.auto_pause_in_seconds = stream_terms.auto_pause_in_seconds;
+    }
+}
+
+// Validates the terms an approved proposal leaves on a stream already snapshotted at the old rate,
+// the unvested balance being what the new terms have to stream
+fn check_updated_stream_terms(stream: &Stream) -> Result<(), StreamError> {
+
+    if !(0.0..=100.0).contains(&stream.cliff_vest_percent)
+    {
+        return Err(StreamError::InvalidCliffPercent);
+    }
+
+    check_cliff_within_duration(
+        stream.total_deposits - stream.total_withdrawals - stream.escrow_vested_amount_snap,
+        stream.rate_amount,
+        stream.rate_interval_in_seconds,
+        stream.rate_cliff_in_seconds
+    )?;
+
+    if !stream.is_payable()
+    {
+        return Err(StreamError::StreamCannotVest);
+    }
+
+    Ok(())
+}
+
+// Snapshots what vested until the pause and records why the stream was paused
+fn pause_stream(
+    stream: &mut Stream,
+    escrow_vested_amount: f64,
+    reason: PauseReason,
+    block_height: u64,
+    block_time: u64
+
+) {
+
+    stream.escrow_vested_amount_snap = escrow_vested_amount;
+    stream.escrow_vested_amount_snap_block_height = block_height;
+    stream.escrow_vested_amount_snap_block_time = block_time;
+    stream.pause_reason = reason as u8;
+}
+
+fn resume_stream(
+    stream: &mut Stream,
+    block_height: u64,
+    block_time: u64
+
+) {
+
+    stream.stream_resumed_block_height = block_height;
+    stream.stream_resumed_block_time = block_time;
+    stream.pause_reason = PauseReason::NotPaused as u8;
+}
+
+// Only a stream paused for low funds is resumed by a new auto-pause threshold, when it has funds
+// left and would not be auto-paused again under that threshold at `now_utc`. A manually paused
+// stream stays paused
+fn resumes_with_auto_pause_threshold(
+    stream: &Stream,
+    seconds: u64,
+    now_utc: u64
+
+) -> bool {
+
+    if stream.pause_reason != PauseReason::AutoLowFunds as u8 || stream.is_streaming()
+    {
+        return false;
+    }
+
+    let mut resumed = stream.clone();
+    resumed.auto_pause_in_seconds = seconds;
+    resume_stream(&mut resumed, 0, now_utc);
+
+    resumed.locked_amount(now_utc) > 0.0 && !resumed.should_auto_pause(now_utc)
+}
+
+// The cliff of a new stream has to end before its funding is estimated to run out, streaming
+// `funding_amount` at the stream rate from `start_utc`. Nothing to estimate without funding or rate
+fn check_cliff_before_depletion(stream: &Stream, funding_amount: f64) -> Result<(), StreamError> {
+
+    let rate = stream.rate_per_second();
+
+    if funding_amount <= 0.0 || rate <= 0.0
+    {
+        return Ok(());
+    }
+
+    let estimated_depletion_utc = stream.start_utc.saturating_add((funding_amount / rate) as u64);
+
+    if stream.cliff_end_utc() >= estimated_depletion_utc
+    {
+        return Err(StreamError::CliffAfterDepletion);
+    }
+
+    Ok(())
+}
+
+// Validates the terms of a stream being created or renewed, `funding_amount` being what it starts streaming
+fn check_new_stream_terms(
+    stream: &Stream,
+    funding_amount: f64,
+    now_utc: u64
+
+) -> Result<(), StreamError> {
+
+    if !(0.0..=100.0).contains(&stream.cliff_vest_percent)
+    {
+        return Err(StreamError::InvalidCliffPercent);
+    }
+
+    check_rate_for_funding(
+        funding_amount,
+        stream.rate_amount,
+        stream.rate_interval_in_seconds,
+        stream.auto_pause_in_seconds
+    )?;
+
+    check_cliff_within_duration(
+        funding_amount,
+        stream.rate_amount,
+        stream.rate_interval_in_seconds,
+        stream.rate_cliff_in_seconds
+    )?;
+
+    check_start_utc(stream.start_utc, now_utc)?;
+
+    if !stream.is_payable()
+    {
+        return Err(StreamError::StreamCannotVest);
+    }
+
+    Ok(())
+}
+
+fn require_accounts(
+    accounts: &[AccountInfo],
+    count: usize
+
+) -> ProgramResult {
+
+    if accounts.len() < count
+    {
+        msg!("Error: The instruction expects {:?} accounts, got {:?}", count, accounts.len());
+        return Err(ProgramError::NotEnoughAccountKeys);
+    }
+
+    Ok(())
+}
+
+// Only the treasury pool (a program PDA) can mint pool tokens, otherwise shares could be minted at will
+fn require_pool_mint_authority(
+    treasury_mint: &spl_token_2022::state::Mint,
+    treasury_address: &Pubkey
+
+) -> Result<(), StreamError> {
+
+    if treasury_mint.mint_authority != COption::Some(*treasury_address)
+    {
+        return Err(StreamError::InvalidPoolMintAuthority);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{ cell::{ Cell, RefCell }, sync::Once };
+    use solana_program::{
+        entrypoint::SUCCESS,
+        program_stubs::{ set_syscall_stubs, SyscallStubs },
+        system_program
+    };
+    use spl_token_2022::extension::{ non_transferable::NonTransferable, StateWithExtensionsMut };
+    use crate::constants::MAX_START_UTC_OFFSET_IN_SECONDS;
+
+    thread_local! {
+        static NOW_UTC: Cell<i64> = Cell::new(0);
+        static RETURN_DATA: RefCell<Vec<u8>> = RefCell::new(Vec::new());
+        static LOGS: RefCell<Vec<String>> = RefCell::new(Vec::new());
+    }
+
+    // The handlers read the clock through a syscall, the tests serve the time `set_clock` sets on
+    // their thread and keep the return data and the logs there. Cross-program invocations are left
+    // to the default stubs, which do nothing
+    struct TestSyscalls;
+
+    impl SyscallStubs for TestSyscalls {
+        fn sol_get_clock_sysvar(&self, var_addr: *mut u8) -> u64 {
+            let clock = Clock { unix_timestamp: NOW_UTC.with(|now| now.get()), ..Clock::default() };
+
+            unsafe { *(var_addr as *mut Clock) = clock; }
+            SUCCESS
+        }
+
+        fn sol_log(&self, message: &str) {
+            LOGS.with(|logs| logs.borrow_mut().push(message.to_string()));
+        }
+
+        fn sol_set_return_data(&self, data: &[u8]) {
+            RETURN_DATA.with(|return_data| *return_data.borrow_mut() = data.to_vec());
+        }
+    }
+
+    fn set_clock(now_utc: i64) {
+        static STUBS: Once = Once::new();
+
+        STUBS.call_once(|| { set_syscall_stubs(Box::new(TestSyscalls)); });
+        NOW_UTC.with(|now| now.set(now_utc));
+    }
+
+    // An account as the runtime hands it to a handler
+    struct TestAccount {
+        key: Pubkey,
+        owner: Pubkey,
+        lamports: u64,
+        data: Vec<u8>,
+        is_signer: bool
+    }
+
+    impl TestAccount {
+        fn new(owner: &Pubkey, data: Vec<u8>) -> Self {
+            TestAccount { key: Pubkey::new_unique(), owner: *owner, lamports: LAMPORTS_PER_SOL, data, is_signer: false }
+        }
+
+        fn signer(key: &Pubkey) -> Self {
+            TestAccount { key: *key, is_signer: true, ..TestAccount::new(&system_program::id(), Vec::new()) }
+        }
+
+        fn program(key: &Pubkey) -> Self {
+            TestAccount { key: *key, ..TestAccount::new(&solana_program::bpf_loader::id(), Vec::new()) }
+        }
+
+        fn stream(program_id: &Pubkey, stream: &Stream) -> Self {
+            let mut data = vec![0u8; Stream::LEN];
+            stream.pack_into_slice(&mut data);
+
+            TestAccount::new(program_id, data)
+        }
+
+        fn treasury(program_id: &Pubkey, treasury: &Treasury) -> Self {
+            let mut data = vec![0u8; Treasury::LEN];
+            treasury.pack_into_slice(&mut data);
+
+            TestAccount::new(program_id, data)
+        }
+
+        fn rent() -> Self {
+            let rent = Rent::default();
+            let mut data = rent.lamports_per_byte_year.to_le_bytes().to_vec();
+            data.extend_from_slice(&rent.exemption_threshold.to_le_bytes());
+            data.push(rent.burn_percent);
+
+            TestAccount { key: solana_program::sysvar::rent::id(), ..TestAccount::new(&solana_program::sysvar::id(), data) }
+        }
+
+        fn mint(decimals: u8) -> Self {
+            let mint = spl_token::state::Mint { decimals, is_initialized: true, ..spl_token::state::Mint::default() };
+            let mut data = vec![0u8; spl_token::state::Mint::LEN];
+            mint.pack_into_slice(&mut data);
+
+            TestAccount::new(&spl_token::id(), data)
+        }
+
+        // A Token-2022 mint with the `extension_types` initialized, the transfer fee is 1% capped at 50
+        fn token_2022_mint(decimals: u8, extension_types: &[ExtensionType]) -> Self {
+            let mut data = vec![0u8; ExtensionType::get_account_len::<spl_token_2022::state::Mint>(extension_types)];
+            let mut mint = StateWithExtensionsMut::<spl_token_2022::state::Mint>::unpack_uninitialized(&mut data).unwrap();
+
+            for extension_type in extension_types
+            {
+                match extension_type
+                {
+                    ExtensionType::TransferFeeConfig => {
+                        let transfer_fee_config = mint.init_extension::<TransferFeeConfig>(true).unwrap();
+                        transfer_fee_config.newer_transfer_fee.transfer_fee_basis_points = 100.into();
+                        transfer_fee_config.newer_transfer_fee.maximum_fee = 50.into();
+                    },
+                    ExtensionType::NonTransferable => { mint.init_extension::<NonTransferable>(true).unwrap(); },
+                    _ => unreachable!()
+                }
+            }
+
+            mint.base.decimals = decimals;
+            mint.base.is_initialized = true;
+            mint.pack_base();
+            mint.init_account_type().unwrap();
+
+            TestAccount::new(&spl_token_2022::id(), data)
+        }
+
+        fn token_account(mint: &Pubkey, owner: &Pubkey, amount: u64) -> Self {
+            let account = spl_token::state::Account {
+                mint: *mint,
+                owner: *owner,
+                amount,
+                state: spl_token::state::AccountState::Initialized,
+                ..spl_token::state::Account::default()
+            };
+            let mut data = vec![0u8; spl_token::state::Account::LEN];
+            account.pack_into_slice(&mut data);
+
+            TestAccount::new(&spl_token::id(), data)
+        }
+
+        fn info(&mut self) -> AccountInfo<'_> {
+            AccountInfo::new(&self.key, self.is_signer, true, &mut self.lamports, &mut self.data, &self.owner, false, 0)
+        }
+
+        fn unpack_stream(&self) -> Stream {
+            Stream::unpack_from_slice(&self.data).unwrap()
+        }
+    }
+
+    fn infos(accounts: &mut [TestAccount]) -> Vec<AccountInfo<'_>> {
+        accounts.iter_mut().map(TestAccount::info).collect()
+    }
+
+    #[test]
+    fn funding_increment_is_checked_on_rounded_base_units() {
+        let mut treasury = Treasury::default();
+        treasury.funding_increment = 29;
+
+        // 0.29 * 100 is 28.999999999999996 in f64
+        assert_eq!(require_funding_increment(&treasury, 0.29, 100.0), Ok(()));
+        assert_eq!(require_funding_increment(&treasury, 0.30, 100.0), Err(StreamError::InvalidFundingIncrement));
+
+        treasury.funding_increment = 0;
+        assert_eq!(require_funding_increment(&treasury, 0.30, 100.0), Ok(()));
+    }
+
+    fn new_stream_terms() -> Stream {
+        let mut stream = Stream::default();
+        stream.start_utc = 1_000;
+        stream.rate_amount = 1.0;
+        stream.rate_interval_in_seconds = 10;
+        stream.rate_cliff_in_seconds = 100;
+        stream
+    }
+
+    #[test]
+    fn new_stream_terms_are_checked() {
+        let stream = new_stream_terms();
+        assert_eq!(check_new_stream_terms(&stream, 100.0, 1_000), Ok(()));
+
+        // 100 tokens at 0.1 per second last 1000 seconds
+        let mut cliff = new_stream_terms();
+        cliff.rate_cliff_in_seconds = 1_001;
+        assert_eq!(check_new_stream_terms(&cliff, 100.0, 1_000), Err(StreamError::CliffExceedsDuration));
+
+        let mut auto_pause = new_stream_terms();
+        auto_pause.auto_pause_in_seconds = 1_001;
+        assert_eq!(check_new_stream_terms(&auto_pause, 100.0, 1_000), Err(StreamError::RateTooHighForFunding));
+
+        assert_eq!(
+            check_new_stream_terms(&stream, 100.0, 1_000 + MAX_START_UTC_OFFSET_IN_SECONDS + 1),
+            Err(StreamError::StartTimeOutOfRange)
+        );
+
+        let mut dead = new_stream_terms();
+        dead.rate_amount = 0.0;
+        assert_eq!(check_new_stream_terms(&dead, 100.0, 1_000), Err(StreamError::StreamCannotVest));
+        dead.cliff_vest_amount = 5.0;
+        assert_eq!(check_new_stream_terms(&dead, 100.0, 1_000), Ok(()));
+    }
+
+    #[test]
+    fn cliff_has_to_end_before_the_estimated_depletion() {
+        // 100 tokens at 0.1 per second from 1_000 run out at 2_000
+        let mut stream = new_stream_terms();
+        stream.rate_cliff_in_seconds = 999;
+        assert_eq!(check_cliff_before_depletion(&stream, 100.0), Ok(()));
+
+        stream.rate_cliff_in_seconds = 1_000;
+        assert_eq!(check_cliff_before_depletion(&stream, 100.0), Err(StreamError::CliffAfterDepletion));
+
+        // Unfunded, or without a rate, there is no depletion to estimate
+        assert_eq!(check_cliff_before_depletion(&stream, 0.0), Ok(()));
+        stream.rate_amount = 0.0;
+        assert_eq!(check_cliff_before_depletion(&stream, 100.0), Ok(()));
+    }
+
+    #[test]
+    fn create_stream_rejects_a_short_funded_long_cliff_stream() {
+        set_clock(1_000);
+        let program_id = crate::id();
+        let treasurer = Pubkey::new_unique();
+        let mut treasury = Treasury::default();
+        treasury.initialized = true;
+        treasury.treasury_base_address = treasurer;
+
+        let mut accounts = vec![
+            TestAccount::signer(&treasurer),
+            TestAccount::treasury(&program_id, &treasury),
+            TestAccount::new(&spl_token::id(), Vec::new()),
+            TestAccount::new(&program_id, vec![0u8; Stream::LEN]),
+            TestAccount::new(&system_program::id(), Vec::new()),
+            TestAccount::program(&program_id),
+            TestAccount::program(&system_program::id()),
+            TestAccount::rent()
+        ];
+
+        // 10 tokens at 1 per 10 seconds last 100 seconds, the cliff a day
+        let result = Processor::process_create_stream(
+            &infos(&mut accounts), &program_id, Pubkey::new_unique(), "payroll".to_string(),
+            1.0, 10, 1_000, 86_400, 0.0, 0.0, 0, 10.0, [0; 16]
+        );
+
+        assert_eq!(result, Err(StreamError::CliffAfterDepletion.into()));
+    }
+
+    fn auto_paused_stream() -> Stream {
+        let mut stream = new_stream_terms();
+        stream.total_deposits = 100.0;
+        stream.auto_pause_in_seconds = 500;
+        stream.stream_resumed_block_time = 1_000;
+        // Paused at 1_500 with 45 vested
+        stream.escrow_vested_amount_snap = 45.0;
+        stream.escrow_vested_amount_snap_block_time = 1_500;
+        stream.pause_reason = PauseReason::AutoLowFunds as u8;
+        stream
+    }
+
+    #[test]
+    fn auto_paused_streams_resume_only_if_the_new_threshold_keeps_them_running() {
+        // 55 locked at 0.1 per second last 550 seconds
+        let stream = auto_paused_stream();
+        assert!(resumes_with_auto_pause_threshold(&stream, 500, 1_600));
+        assert!(resumes_with_auto_pause_threshold(&stream, 550, 1_600));
+        assert!(resumes_with_auto_pause_threshold(&stream, 0, 1_600));
+        // It would be auto-paused again right away
+        assert!(!resumes_with_auto_pause_threshold(&stream, 551, 1_600));
+        assert!(!resumes_with_auto_pause_threshold(&stream, 1_000, 1_600));
+
+        // Nothing left to stream
+        let mut depleted = auto_paused_stream();
+        depleted.escrow_vested_amount_snap = 100.0;
+        assert!(!resumes_with_auto_pause_threshold(&depleted, 0, 1_600));
+
+        // Paused by the treasurer after the previous threshold
+        let mut manual = auto_paused_stream();
+        manual.pause_reason = PauseReason::Manual as u8;
+        assert!(!resumes_with_auto_pause_threshold(&manual, 100, 1_600));
+
+        let mut streaming = auto_paused_stream();
+        streaming.stream_resumed_block_time = 1_500;
+        assert!(!resumes_with_auto_pause_threshold(&streaming, 100, 1_600));
+    }
+
+    #[test]
+    fn beneficiary_token_account_is_set_only_to_a_beneficiary_account_of_the_stream_mint() {
+        set_clock(1_500);
+        let program_id = crate::id();
+        let beneficiary = Pubkey::new_unique();
+        let mint = Pubkey::new_unique();
+        let mut stream = new_stream_terms();
+        stream.initialized = true;
+        stream.beneficiary_address = beneficiary;
+        stream.beneficiary_associated_token = mint;
+
+        let set_token_account = |token_account: TestAccount| {
+            let token_address = token_account.key;
+            let mut accounts = vec![
+                TestAccount::signer(&beneficiary),
+                token_account,
+                TestAccount::stream(&program_id, &stream),
+                TestAccount::new(&system_program::id(), Vec::new())
+            ];
+            let result = Processor::process_set_beneficiary_token_account(&infos(&mut accounts), &program_id, token_address);
+
+            (result, accounts[2].unpack_stream())
+        };
+
+        let token_account = TestAccount::token_account(&mint, &beneficiary, 0);
+        let token_address = token_account.key;
+        let (result, updated) = set_token_account(token_account);
+        assert_eq!(result, Ok(()));
+        assert_eq!(updated.beneficiary_token_address, token_address);
+        assert_eq!(updated.last_activity_utc, 1_500);
+
+        for token_account in vec![
+            TestAccount::token_account(&mint, &Pubkey::new_unique(), 0),
+            TestAccount::token_account(&Pubkey::new_unique(), &beneficiary, 0)
+        ] {
+            let (result, updated) = set_token_account(token_account);
+            assert_eq!(result, Err(StreamError::InvalidBeneficiaryTokenAccount.into()));
+            assert_eq!(updated.beneficiary_token_address, Pubkey::default());
+        }
+    }
+
+    #[test]
+    fn every_pause_path_records_its_reason() {
+        let mut stream = new_stream_terms();
+        stream.total_deposits = 100.0;
+        stream.stream_resumed_block_time = 1_000;
+        stream.escrow_vested_amount_snap_block_time = 1_000;
+
+        pause_stream(&mut stream, 45.0, PauseReason::Manual, 10, 1_450);
+        assert!(!stream.is_streaming());
+        assert_eq!(stream.pause_reason, PauseReason::Manual as u8);
+        assert_eq!(stream.escrow_vested_amount_snap, 45.0);
+
+        resume_stream(&mut stream, 20, 1_500);
+        assert!(stream.is_streaming());
+        assert_eq!(stream.pause_reason, PauseReason::NotPaused as u8);
+
+        pause_stream(&mut stream, 100.0, PauseReason::AutoLowFunds, 30, 2_000);
+        assert!(!stream.is_streaming());
+        assert_eq!(stream.pause_reason, PauseReason::AutoLowFunds as u8);
+        assert_eq!(stream.escrow_vested_amount_snap_block_height, 30);
+    }
+
+    #[test]
+    fn approved_terms_change_only_the_terms_they_set() {
+        let mut stream = new_stream_terms();
+        stream.stream_name = "payroll".to_string();
+        stream.cliff_vest_percent = 10.0;
+
+        let mut stream_terms = StreamTerms::default();
+        stream_terms.stream_name = "bonus".to_string();
+        stream_terms.beneficiary_address = Pubkey::new_unique();
+        stream_terms.rate_amount = 2.0;
+        stream_terms.rate_cliff_in_seconds = 50;
+        stream_terms.cliff_vest_percent = 100.0; // unset
+
+        let mut updated = stream.clone();
+        apply_stream_terms(&mut updated, &stream_terms);
+
+        assert_eq!(updated.stream_name, "bonus");
+        assert_eq!(updated.beneficiary_address, stream_terms.beneficiary_address);
+        assert_eq!(updated.rate_amount, 2.0);
+        assert_eq!(updated.rate_cliff_in_seconds, 50);
+        assert_eq!(updated.rate_interval_in_seconds, stream.rate_interval_in_seconds);
+        assert_eq!(updated.treasurer_address, stream.treasurer_address);
+        assert_eq!(updated.cliff_vest_percent, 10.0);
+    }
+
+    #[test]
+    fn updated_terms_are_checked_against_the_unvested_balance() {
+        let mut stream = new_stream_terms();
+        stream.total_deposits = 100.0;
+        stream.total_withdrawals = 20.0;
+        stream.escrow_vested_amount_snap = 30.0;
+        assert_eq!(check_updated_stream_terms(&stream), Ok(()));
+
+        // The 50 unvested last 500 seconds at 0.1 per second
+        let mut cliff = stream.clone();
+        cliff.rate_cliff_in_seconds = 501;
+        assert_eq!(check_updated_stream_terms(&cliff), Err(StreamError::CliffExceedsDuration));
+
+        let mut percent = stream.clone();
+        percent.cliff_vest_percent = 101.0;
+        assert_eq!(check_updated_stream_terms(&percent), Err(StreamError::InvalidCliffPercent));
+
+        let mut dead = stream.clone();
+        dead.rate_amount = 0.0;
+        assert_eq!(check_updated_stream_terms(&dead), Err(StreamError::StreamCannotVest));
+    }
+
+    #[test]
+    fn new_stream_cliff_percent_is_checked() {
+        for (percent, valid) in [(-1.0, false), (0.0, true), (50.0, true), (100.0, true), (101.0, false)].iter() {
+            let mut stream = new_stream_terms();
+            stream.cliff_vest_percent = *percent;
+            assert_eq!(check_new_stream_terms(&stream, 100.0, 1_000).is_ok(), *valid, "{}", percent);
+        }
+    }
+
+    #[test]
+    fn transfer_is_rejected_over_the_source_balance() {
+        let source = Pubkey::new_unique();
+        let mint = TestAccount::mint(6);
+
+        let mut accounts = vec![
+            TestAccount::signer(&source),
+            TestAccount::token_account(&mint.key, &source, 1_000_000),
+            TestAccount::token_account(&mint.key, &Pubkey::new_unique(), 0),
+            mint,
+            TestAccount::new(&system_program::id(), Vec::new()),
+            TestAccount::token_account(&Pubkey::new_unique(), &Pubkey::new_unique(), 0),
+            TestAccount::program(&spl_token::id())
+        ];
+
+        assert_eq!(
+            Processor::process_transfer(&infos(&mut accounts), &crate::id(), 1.5),
+            Err(StreamError::InsufficientBalance.into())
+        );
+        assert_eq!(Processor::process_transfer(&infos(&mut accounts), &crate::id(), 1.0), Ok(()));
+    }
+
+    #[test]
+    fn only_the_treasurer_transfers_stream_ownership() {
+        set_clock(2_000);
+        let program_id = crate::id();
+        let treasurer = Pubkey::new_unique();
+        let new_treasurer = Pubkey::new_unique();
+        let mut stream = new_stream_terms();
+        stream.initialized = true;
+        stream.treasurer_address = treasurer;
+
+        let mut accounts = vec![
+            TestAccount::signer(&Pubkey::new_unique()),
+            TestAccount::stream(&program_id, &stream),
+            TestAccount::new(&system_program::id(), Vec::new())
+        ];
+
+        assert_eq!(
+            Processor::process_transfer_stream_ownership(&infos(&mut accounts), &program_id, new_treasurer),
+            Err(StreamError::InstructionNotAuthorized.into())
+        );
+
+        accounts[0] = TestAccount::signer(&treasurer);
+        assert_eq!(
+            Processor::process_transfer_stream_ownership(&infos(&mut accounts), &program_id, Pubkey::default()),
+            Err(StreamError::InvalidArgument.into())
+        );
+        assert_eq!(Processor::process_transfer_stream_ownership(&infos(&mut accounts), &program_id, new_treasurer), Ok(()));
+
+        let stream = accounts[1].unpack_stream();
+        assert_eq!(stream.treasurer_address, new_treasurer);
+        assert_eq!(stream.last_activity_utc, 2_000);
+    }
+
+    #[test]
+    fn state_accounts_owned_by_an_other_program_are_rejected() {
+        set_clock(2_000);
+        let program_id = crate::id();
+        let treasurer = Pubkey::new_unique();
+        let mut stream = new_stream_terms();
+        stream.initialized = true;
+        stream.treasurer_address = treasurer;
+
+        let mut foreign_stream = TestAccount::stream(&program_id, &stream);
+        foreign_stream.owner = Pubkey::new_unique();
+
+        let mut accounts = vec![
+            TestAccount::signer(&treasurer),
+            foreign_stream,
+            TestAccount::new(&system_program::id(), Vec::new())
+        ];
+
+        assert_eq!(
+            Processor::process_transfer_stream_ownership(&infos(&mut accounts), &program_id, Pubkey::new_unique()),
+            Err(StreamError::IncorrectProgramId.into())
+        );
+        assert_eq!(accounts[1].unpack_stream().treasurer_address, treasurer);
+    }
+
+    #[test]
+    fn get_version_returns_the_program_version() {
+        set_clock(0);
+        let program_id = crate::id();
+        let mut accounts = vec![TestAccount::new(&system_program::id(), Vec::new())];
+
+        assert_eq!(Processor::process(&program_id, &infos(&mut accounts), &StreamInstruction::GetVersion.pack()), Ok(()));
+        assert_eq!(RETURN_DATA.with(|return_data| return_data.borrow().clone()), PROGRAM_VERSION.to_vec());
+    }
+
+    // A funded stream paying 1 token per 10 seconds from 1_000 after a 100 seconds cliff, out of a
+    // treasury at its derived address
+    fn funded_stream(program_id: &Pubkey) -> (Stream, Treasury) {
+        let mut treasury = Treasury::default();
+        treasury.initialized = true;
+        treasury.treasury_block_height = 1;
+        treasury.treasury_base_address = Pubkey::new_unique();
+
+        let mut stream = new_stream_terms();
+        stream.initialized = true;
+        stream.treasurer_address = treasury.treasury_base_address;
+        stream.beneficiary_address = Pubkey::new_unique();
+        stream.treasury_address = find_treasury_address(&treasury.treasury_base_address, 1, program_id).0;
+        stream.total_deposits = 100.0;
+        stream.escrow_vested_amount_snap_block_time = 1_000;
+        stream.stream_resumed_block_time = 1_000;
+
+        (stream, treasury)
+    }
+
+    // Accounts of a withdrawal from `stream` into a token account owned by `token_owner`
+    fn withdraw_accounts(program_id: &Pubkey, stream: &Stream, treasury: &Treasury, token_owner: &Pubkey) -> Vec<TestAccount> {
+        let mint = TestAccount::mint(6);
+        let mint_key = mint.key;
+
+        vec![
+            TestAccount::signer(&stream.beneficiary_address),
+            TestAccount::token_account(&mint_key, token_owner, 0),
+            mint,
+            TestAccount { key: stream.treasury_address, ..TestAccount::treasury(program_id, treasury) },
+            TestAccount::token_account(&mint_key, &stream.treasury_address, 1_000_000_000),
+            TestAccount::stream(program_id, stream),
+            TestAccount::new(&system_program::id(), Vec::new()),
+            TestAccount::token_account(&mint_key, &Pubkey::new_unique(), 0),
+            TestAccount::program(program_id),
+            TestAccount::program(&spl_token::id())
+        ]
+    }
+
+    #[test]
+    fn withdrawals_go_only_to_a_token_account_of_the_beneficiary() {
+        set_clock(1_200);
+        let program_id = crate::id();
+        let (stream, treasury) = funded_stream(&program_id);
+
+        let mut accounts = withdraw_accounts(&program_id, &stream, &treasury, &Pubkey::new_unique());
+        assert_eq!(
+            Processor::process_withdraw(&infos(&mut accounts), &program_id, Some(5.0)),
+            Err(StreamError::InvalidBeneficiaryTokenAccount.into())
+        );
+
+        let mut accounts = withdraw_accounts(&program_id, &stream, &treasury, &stream.beneficiary_address);
+        assert_eq!(Processor::process_withdraw(&infos(&mut accounts), &program_id, Some(5.0)), Ok(()));
+        assert_eq!(accounts[5].unpack_stream().total_withdrawals, 5.0);
+    }
+
+    #[test]
+    fn withdrawals_keep_a_paused_stream_paused() {
+        set_clock(1_200);
+        let program_id = crate::id();
+        let (mut stream, treasury) = funded_stream(&program_id);
+        stream.escrow_vested_amount_snap = 10.0;
+        stream.escrow_vested_amount_snap_block_time = 1_150;
+        stream.pause_reason = PauseReason::Manual as u8;
+
+        let mut accounts = withdraw_accounts(&program_id, &stream, &treasury, &stream.beneficiary_address);
+        assert_eq!(Processor::process_withdraw(&infos(&mut accounts), &program_id, Some(5.0)), Ok(()));
+
+        let stream = accounts[5].unpack_stream();
+        assert!(!stream.is_streaming());
+        assert_eq!(stream.pause_reason, PauseReason::Manual as u8);
+        assert_eq!(stream.stream_resumed_block_time, 1_000);
+        assert_eq!(stream.escrow_vested_amount_snap, 5.0);
+    }
+
+    #[test]
+    fn state_accounts_of_an_other_layout_are_rejected() {
+        set_clock(2_000);
+        let program_id = crate::id();
+        let treasurer = Pubkey::new_unique();
+        let mut stream = new_stream_terms();
+        stream.initialized = true;
+        stream.treasurer_address = treasurer;
+
+        let mut padded_stream = TestAccount::stream(&program_id, &stream);
+        padded_stream.data.push(0);
+
+        let mut accounts = vec![
+            TestAccount::signer(&treasurer),
+            padded_stream,
+            TestAccount::new(&system_program::id(), Vec::new())
+        ];
+
+        assert_eq!(
+            Processor::process_transfer_stream_ownership(&infos(&mut accounts), &program_id, Pubkey::new_unique()),
+            Err(StreamError::AccountLayoutMismatch.into())
+        );
+
+        let mut treasury = TestAccount::new(&program_id, vec![0u8; Treasury::LEN]);
+        assert_eq!(require_treasury_layout(&treasury.info()), Ok(()));
+        treasury.data.truncate(Treasury::LEGACY_LEN);
+        assert_eq!(require_treasury_layout(&treasury.info()), Ok(()));
+        treasury.data.push(0);
+        assert_eq!(require_treasury_layout(&treasury.info()), Err(StreamError::AccountLayoutMismatch));
+    }
+
+    // Accounts of a pause or resume of `stream` signed by `initializer`
+    fn pause_accounts(program_id: &Pubkey, stream: &Stream, initializer: &Pubkey) -> Vec<TestAccount> {
+        vec![
+            TestAccount::signer(initializer),
+            TestAccount::stream(program_id, stream),
+            TestAccount::new(&system_program::id(), Vec::new()),
+            TestAccount::program(&system_program::id())
+        ]
+    }
+
+    #[test]
+    fn handlers_record_the_last_activity_time() {
+        let program_id = crate::id();
+        let (stream, _) = funded_stream(&program_id);
+        let mut accounts = pause_accounts(&program_id, &stream, &stream.treasurer_address);
+
+        set_clock(1_500);
+        assert_eq!(Processor::process_pause_stream(&infos(&mut accounts), &program_id), Ok(()));
+        assert_eq!(accounts[1].unpack_stream().last_activity_utc, 1_500);
+
+        set_clock(1_800);
+        assert_eq!(Processor::process_resume_stream(&infos(&mut accounts), &program_id), Ok(()));
+
+        let stream = accounts[1].unpack_stream();
+        assert_eq!(stream.last_activity_utc, 1_800);
+        assert_eq!(stream.seconds_since_activity(1_860), 60);
+        assert_eq!(stream.seconds_since_activity(1_700), 0);
+    }
+
+    #[test]
+    fn auto_pause_threshold_has_to_fit_in_the_funds_left() {
+        set_clock(1_000);
+        let program_id = crate::id();
+        let (stream, _) = funded_stream(&program_id);
+        let set_threshold = |seconds| {
+            let mut accounts = vec![
+                TestAccount::signer(&stream.treasurer_address),
+                TestAccount::stream(&program_id, &stream),
+                TestAccount::new(&system_program::id(), Vec::new())
+            ];
+
+            Processor::process_set_auto_pause_threshold(&infos(&mut accounts), &program_id, seconds)
+                .map(|_| accounts[1].unpack_stream().auto_pause_in_seconds)
+        };
+
+        // 100 tokens at 1 per 10 seconds last 1_000 seconds, shorter than the 100 seconds cliff is fine
+        assert_eq!(set_threshold(50), Ok(50));
+        assert_eq!(set_threshold(1_000), Ok(1_000));
+        assert_eq!(set_threshold(1_001), Err(StreamError::RateTooHighForFunding.into()));
+        assert_eq!(set_threshold(0), Ok(0));
+    }
+
+    #[test]
+    fn token_transfers_only_go_through_a_token_program() {
+        set_clock(1_200);
+        let program_id = crate::id();
+        let (stream, treasury) = funded_stream(&program_id);
+
+        let mut accounts = withdraw_accounts(&program_id, &stream, &treasury, &stream.beneficiary_address);
+        accounts[9] = TestAccount::program(&Pubkey::new_unique());
+        assert_eq!(
+            Processor::process_withdraw(&infos(&mut accounts), &program_id, Some(5.0)),
+            Err(StreamError::IncorrectProgramId.into())
+        );
+        assert_eq!(accounts[5].unpack_stream().total_withdrawals, 0.0);
+
+        accounts[9] = TestAccount::program(&spl_token_2022::id());
+        assert_eq!(Processor::process_withdraw(&infos(&mut accounts), &program_id, Some(5.0)), Ok(()));
+    }
+
+    #[cfg(feature = "verbose-logs")]
+    #[test]
+    fn verbose_logs_log_the_decoded_instruction() {
+        set_clock(0);
+        LOGS.with(|logs| logs.borrow_mut().clear());
+        let mut accounts = vec![TestAccount::new(&system_program::id(), Vec::new())];
+
+        assert_eq!(Processor::process(&crate::id(), &infos(&mut accounts), &StreamInstruction::GetVersion.pack()), Ok(()));
+        assert!(LOGS.with(|logs| logs.borrow().contains(&"Decoded instruction: GetVersion".to_string())));
+    }
+
+    #[test]
+    fn paused_streams_are_not_paused_again_nor_running_ones_resumed() {
+        set_clock(1_500);
+        let program_id = crate::id();
+        let (stream, _) = funded_stream(&program_id);
+        let mut accounts = pause_accounts(&program_id, &stream, &stream.beneficiary_address);
+
+        assert_eq!(
+            Processor::process_resume_stream(&infos(&mut accounts), &program_id),
+            Err(StreamError::StreamAlreadyRunning.into())
+        );
+        assert_eq!(Processor::process_pause_stream(&infos(&mut accounts), &program_id), Ok(()));
+        assert_eq!(
+            Processor::process_pause_stream(&infos(&mut accounts), &program_id),
+            Err(StreamError::StreamAlreadyPaused.into())
+        );
+
+        let paused = accounts[1].unpack_stream();
+        assert!(!paused.is_streaming());
+        assert_eq!(paused.pause_reason, PauseReason::Manual as u8);
+    }
+
+    #[test]
+    fn withdraw_max_withdraws_everything_withdrawable() {
+        set_clock(1_200);
+        let program_id = crate::id();
+        let (stream, treasury) = funded_stream(&program_id);
+        let mut accounts = withdraw_accounts(&program_id, &stream, &treasury, &stream.beneficiary_address);
+
+        // 10 vested in the 100 seconds since the cliff end
+        assert_eq!(Processor::process_withdraw(&infos(&mut accounts), &program_id, None), Ok(()));
+
+        let withdrawn = accounts[5].unpack_stream();
+        assert!((withdrawn.total_withdrawals - 10.0).abs() < 1e-9);
+        assert_eq!(withdrawn.escrow_vested_amount_snap, 0.0);
+        assert_eq!(withdrawn.withdrawable_amount(1_200, 6), Ok(0));
+
+        // Nothing left to withdraw until more vests
+        assert_eq!(
+            Processor::process_withdraw(&infos(&mut accounts), &program_id, Some(0.5)),
+            Err(StreamError::WithdrawalExceedsVested.into())
+        );
+    }
+
+    #[test]
+    fn set_auto_pause_threshold_resumes_streams_it_keeps_running() {
+        set_clock(1_600);
+        let program_id = crate::id();
+        let treasurer = Pubkey::new_unique();
+        let set_threshold = |stream: &mut Stream, seconds| {
+            stream.treasurer_address = treasurer;
+            stream.initialized = true;
+            let mut accounts = vec![
+                TestAccount::signer(&treasurer),
+                TestAccount::stream(&program_id, stream),
+                TestAccount::new(&system_program::id(), Vec::new())
+            ];
+
+            assert_eq!(Processor::process_set_auto_pause_threshold(&infos(&mut accounts), &program_id, seconds), Ok(()));
+            accounts[1].unpack_stream()
+        };
+
+        let resumed = set_threshold(&mut auto_paused_stream(), 500);
+        assert!(resumed.is_streaming());
+        assert_eq!(resumed.pause_reason, PauseReason::NotPaused as u8);
+        assert_eq!(resumed.auto_pause_in_seconds, 500);
+
+        let mut manual = auto_paused_stream();
+        manual.pause_reason = PauseReason::Manual as u8;
+        let still_paused = set_threshold(&mut manual, 500);
+        assert!(!still_paused.is_streaming());
+        assert_eq!(still_paused.pause_reason, PauseReason::Manual as u8);
+    }
+
+    #[test]
+    fn transfer_fees_are_read_from_the_mint_extensions() {
+        let mut mint = TestAccount::mint(6);
+        assert_eq!(mint_transfer_fee(&mint.info(), 1_000, 0).unwrap(), (6, None));
+
+        let mut mint = TestAccount::token_2022_mint(6, &[]);
+        assert_eq!(mint_transfer_fee(&mint.info(), 1_000, 0).unwrap(), (6, None));
+
+        let mut mint = TestAccount::token_2022_mint(9, &[ExtensionType::TransferFeeConfig]);
+        assert_eq!(mint_transfer_fee(&mint.info(), 1_000, 0).unwrap(), (9, Some(10)));
+        assert_eq!(mint_transfer_fee(&mint.info(), 1_000_000, 0).unwrap(), (9, Some(50)));
+
+        let mut mint = TestAccount::token_2022_mint(6, &[ExtensionType::NonTransferable]);
+        assert_eq!(
+            mint_transfer_fee(&mint.info(), 1_000, 0).unwrap_err(),
+            StreamError::UnsupportedMintExtension.into()
+        );
+
+        let mut mint = TestAccount::token_2022_mint(6, &[]);
+        mint.owner = Pubkey::new_unique();
+        assert_eq!(mint_transfer_fee(&mint.info(), 1_000, 0).unwrap_err(), StreamError::IncorrectProgramId.into());
+    }
+
+    #[test]
+    fn stream_totals_are_checked_on_base_units() {
+        let pow = 1_000_000.0;
+
+        assert_eq!(checked_add_amount(0.1, 0.2, pow).unwrap(), 0.3);
+        assert_eq!(checked_sub_amount(0.3, 0.1, pow).unwrap(), 0.2);
+        assert_eq!(checked_sub_amount(0.1, 0.2, pow).unwrap_err(), StreamError::AmountOverflow);
+        assert_eq!(checked_add_amount(1.8e13, 1.8e13, pow).unwrap_err(), StreamError::AmountOverflow);
+        assert_eq!(checked_add_amount(1.0, -1.0, pow).unwrap_err(), StreamError::AmountOverflow);
+        assert_eq!(checked_add_amount(f64::NAN, 1.0, pow).unwrap_err(), StreamError::AmountOverflow);
+    }
+
+    // Accounts of a recovery from `stream` by a contributor holding `pool_tokens` of the 100 treasury
+    // pool tokens minted
+    fn recover_accounts(program_id: &Pubkey, stream: &Stream, treasury: &Treasury, pool_tokens: u64) -> Vec<TestAccount> {
+        let contributor = Pubkey::new_unique();
+        let mint = TestAccount::mint(6);
+        let mint_key = mint.key;
+        let mut pool_mint = TestAccount::mint(6);
+        let pool_mint_state = spl_token::state::Mint {
+            mint_authority: COption::Some(stream.treasury_address),
+            supply: 100_000_000,
+            decimals: 6,
+            is_initialized: true,
+            freeze_authority: COption::None
+        };
+        pool_mint_state.pack_into_slice(&mut pool_mint.data);
+        let pool_mint_key = pool_mint.key;
+        let treasury = Treasury { treasury_mint_address: pool_mint_key, ..treasury.clone() };
+        let contributor_pool_token_address = spl_associated_token_account::get_associated_token_address_with_program_id(
+            &contributor,
+            &pool_mint_key,
+            &spl_token::id()
+        );
+
+        vec![
+            TestAccount::signer(&contributor),
+            TestAccount::token_account(&mint_key, &contributor, 0),
+            TestAccount {
+                key: contributor_pool_token_address,
+                ..TestAccount::token_account(&pool_mint_key, &contributor, pool_tokens * 1_000_000)
+            },
+            mint,
+            TestAccount { key: stream.treasury_address, ..TestAccount::treasury(program_id, &treasury) },
+            TestAccount::token_account(&mint_key, &stream.treasury_address, 1_000_000_000),
+            pool_mint,
+            TestAccount::stream(program_id, stream),
+            TestAccount::new(&system_program::id(), Vec::new()),
+            TestAccount::token_account(&mint_key, &Pubkey::new_unique(), 0),
+            TestAccount::program(program_id),
+            TestAccount::program(&spl_token::id())
+        ]
+    }
+
+    #[test]
+    fn contributors_recover_at_most_their_share_of_the_unvested_funds() {
+        set_clock(1_200);
+        let program_id = crate::id();
+        let (stream, treasury) = funded_stream(&program_id);
+
+        // 10 of the 100 pool tokens are worth 9 of the 90 unvested
+        let mut accounts = recover_accounts(&program_id, &stream, &treasury, 10);
+        assert_eq!(
+            Processor::process_recover_funds(&infos(&mut accounts), &program_id, 20.0),
+            Err(StreamError::RecoverExceedsUnvested.into())
+        );
+
+        assert_eq!(Processor::process_recover_funds(&infos(&mut accounts), &program_id, 5.0), Ok(()));
+        assert!(accounts[7].unpack_stream().total_deposits < 100.0);
+    }
+
+    #[test]
+    fn funds_are_recovered_only_from_the_treasury_of_the_stream() {
+        set_clock(1_200);
+        let program_id = crate::id();
+        let (mut stream, treasury) = funded_stream(&program_id);
+        let mut accounts = recover_accounts(&program_id, &stream, &treasury, 10);
+
+        stream.treasury_address = Pubkey::new_unique();
+        accounts[7] = TestAccount::stream(&program_id, &stream);
+
+        assert_eq!(
+            Processor::process_recover_funds(&infos(&mut accounts), &program_id, 5.0),
+            Err(StreamError::InvalidTreasuryAccount.into())
+        );
+    }
+
+    #[test]
+    fn new_streams_that_can_never_vest_are_rejected() {
+        let mut stream = new_stream_terms();
+        stream.rate_amount = 0.0;
+
+        assert_eq!(check_new_stream_terms(&stream, 100.0, 1_000), Err(StreamError::StreamCannotVest));
+
+        stream.cliff_vest_amount = 5.0;
+        assert_eq!(check_new_stream_terms(&stream, 100.0, 1_000), Ok(()));
+    }
+
+    #[test]
+    fn handlers_reject_missing_accounts() {
+        set_clock(1_200);
+        let program_id = crate::id();
+        let instructions = [
+            StreamInstruction::RecoverFunds { recover_amount: 1.0 },
+            StreamInstruction::Withdraw { withdrawal_amount: 1.0 },
+            StreamInstruction::WithdrawMax,
+            StreamInstruction::PauseStream,
+            StreamInstruction::ResumeStream,
+            StreamInstruction::CloseStream,
+            StreamInstruction::CloseTreasury,
+            StreamInstruction::ExpireProposal
+        ];
+
+        for instruction in instructions.iter()
+        {
+            assert_eq!(
+                Processor::process(&program_id, &[], &instruction.pack()),
+                Err(ProgramError::NotEnoughAccountKeys),
+                "{}",
+                instruction
+            );
+        }
+
+        let (stream, treasury) = funded_stream(&program_id);
+        let mut accounts = withdraw_accounts(&program_id, &stream, &treasury, &stream.beneficiary_address);
+        accounts.pop();
+
+        assert_eq!(
+            Processor::process_withdraw(&infos(&mut accounts), &program_id, None),
+            Err(ProgramError::NotEnoughAccountKeys)
+        );
+    }
+
+    #[test]
+    fn pool_tokens_are_only_minted_and_burned_on_the_treasury_mint() {
+        set_clock(1_200);
+        let program_id = crate::id();
+        let (stream, treasury) = funded_stream(&program_id);
+        let mut accounts = recover_accounts(&program_id, &stream, &treasury, 10);
+
+        // A mint the treasury pool also has the authority of, but not the treasury mint
+        let treasury_mint_key = accounts[6].key;
+        accounts[6] = TestAccount::new(&spl_token::id(), accounts[6].data.clone());
+
+        assert_eq!(
+            Processor::process_recover_funds(&infos(&mut accounts), &program_id, 5.0),
+            Err(StreamError::InvalidTreasuryPool.into())
+        );
+
+        let treasury = Treasury { treasury_mint_address: treasury_mint_key, ..treasury };
+        let mut programs = vec![
+            TestAccount::program(&spl_associated_token_account::id()),
+            TestAccount::program(&system_program::id()),
+            TestAccount::rent()
+        ];
+        let programs = infos(&mut programs);
+        let accounts = infos(&mut accounts);
+
+        assert_eq!(
+            Processor::mint_pool_tokens(
+                &accounts[0],
+                &accounts[2],
+                &accounts[4],
+                &accounts[6],
+                &accounts[11],
+                &programs[0],
+                &programs[1],
+                &programs[2],
+                &treasury,
+                &stream,
+                0,
+                5.0,
+                1_000.0
+            ),
+            Err(StreamError::InvalidTreasuryPool.into())
+        );
+    }
+
+    #[test]
+    fn donated_streams_change_both_roles_and_drop_the_beneficiary_settings() {
+        set_clock(1_200);
+        let program_id = crate::id();
+        let (mut stream, _treasury) = funded_stream(&program_id);
+        stream.beneficiary_token_address = Pubkey::new_unique();
+        stream.withdraw_hook_program = Some(Pubkey::new_unique());
+        let (new_treasurer, new_beneficiary) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let mut accounts = vec![
+            TestAccount::signer(&stream.treasurer_address),
+            TestAccount::signer(&stream.beneficiary_address),
+            TestAccount::stream(&program_id, &stream),
+            TestAccount::new(&system_program::id(), Vec::new())
+        ];
+
+        assert_eq!(
+            Processor::process_donate_stream(&infos(&mut accounts), &program_id, new_treasurer, new_treasurer),
+            Err(StreamError::InvalidArgument.into())
+        );
+
+        accounts[1].is_signer = false;
+        assert_eq!(
+            Processor::process_donate_stream(&infos(&mut accounts), &program_id, new_treasurer, new_beneficiary),
+            Err(StreamError::MissingInstructionSignature.into())
+        );
+
+        accounts[1].is_signer = true;
+        assert_eq!(
+            Processor::process_donate_stream(&infos(&mut accounts), &program_id, new_treasurer, new_beneficiary),
+            Ok(())
+        );
+
+        let donated = accounts[2].unpack_stream();
+        assert_eq!(donated.treasurer_address, new_treasurer);
+        assert_eq!(donated.beneficiary_address, new_beneficiary);
+        assert_eq!(donated.beneficiary_token_address, Pubkey::default());
+        assert_eq!(donated.withdraw_hook_program, None);
+        assert_eq!(donated.total_deposits, stream.total_deposits);
+    }
+
+    #[test]
+    fn legacy_treasuries_are_not_closed() {
+        let program_id = crate::id();
+        let (stream, treasury) = funded_stream(&program_id);
+        let mut treasury_account = TestAccount { key: stream.treasury_address, ..TestAccount::treasury(&program_id, &treasury) };
+        treasury_account.data.truncate(Treasury::LEGACY_LEN);
+        let mint = Pubkey::new_unique();
+        let mut accounts = vec![
+            TestAccount::signer(&treasury.treasury_base_address),
+            treasury_account,
+            TestAccount::token_account(&mint, &stream.treasury_address, 0),
+            TestAccount::new(&system_program::id(), Vec::new())
+        ];
+
+        assert_eq!(
+            Processor::process_close_treasury(&infos(&mut accounts), &program_id),
+            Err(StreamError::AccountLayoutMismatch.into())
+        );
+    }
+
+    #[test]
+    fn new_streams_are_only_credited_for_the_funding_the_treasury_received() {
+        set_clock(1_000);
+        let program_id = crate::id();
+        let (mut stream, treasury) = funded_stream(&program_id);
+        stream.total_deposits = 0.0;
+        let mint = TestAccount::mint(6);
+        let mint_key = mint.key;
+        let msp_ops = Pubkey::new_unique();
+        let token_address = |owner: &Pubkey| spl_associated_token_account::get_associated_token_address_with_program_id(
+            owner,
+            &mint_key,
+            &spl_token::id()
+        );
+        let mut accounts = vec![
+            TestAccount::signer(&stream.treasurer_address),
+            TestAccount { key: stream.treasury_address, ..TestAccount::treasury(&program_id, &treasury) },
+            mint,
+            TestAccount::signer(&msp_ops),
+            TestAccount::program(&system_program::id()),
+            TestAccount::rent(),
+            TestAccount::token_account(&mint_key, &stream.treasurer_address, 1_000_000_000),
+            TestAccount { key: token_address(&stream.treasury_address), ..TestAccount::token_account(&mint_key, &stream.treasury_address, 0) },
+            TestAccount { key: token_address(&msp_ops), ..TestAccount::token_account(&mint_key, &msp_ops, 0) },
+            TestAccount::program(&spl_token::id()),
+            TestAccount::program(&Pubkey::default()),
+            TestAccount::program(&Pubkey::default()),
+            TestAccount::program(&spl_associated_token_account::id())
+        ];
+        let accounts = infos(&mut accounts);
+        let clock = Clock::get().unwrap();
+
+        // The token transfer does not run here, nothing reaches the treasury
+        assert_eq!(
+            Processor::fund_new_stream(
+                &mut accounts[6..].iter(),
+                &program_id,
+                &accounts[0],
+                &accounts[1],
+                &accounts[2],
+                &accounts[3],
+                &accounts[4],
+                &accounts[5],
+                &mut stream,
+                10.0,
+                &clock
+            ),
+            Err(StreamError::FundingNotReceived.into())
+        );
+        assert_eq!(stream.total_deposits, 0.0);
+    }
+
+    // Accounts of a contribution to `stream` without a treasury pool
+    fn add_funds_accounts(program_id: &Pubkey, stream: &Stream, treasury: &Treasury) -> Vec<TestAccount> {
+        let contributor = Pubkey::new_unique();
+        let msp_ops = Pubkey::new_unique();
+        let mint = TestAccount::mint(6);
+        let mint_key = mint.key;
+        let token_address = |owner: &Pubkey| spl_associated_token_account::get_associated_token_address_with_program_id(
+            owner,
+            &mint_key,
+            &spl_token::id()
+        );
+
+        vec![
+            TestAccount::signer(&contributor),
+            TestAccount::token_account(&mint_key, &contributor, 1_000_000_000),
+            TestAccount::program(&Pubkey::default()),
+            mint,
+            TestAccount { key: stream.treasury_address, ..TestAccount::treasury(program_id, treasury) },
+            TestAccount { key: token_address(&stream.treasury_address), ..TestAccount::token_account(&mint_key, &stream.treasury_address, 0) },
+            TestAccount::program(&Pubkey::default()),
+            TestAccount::stream(program_id, stream),
+            TestAccount::signer(&msp_ops),
+            TestAccount { key: token_address(&msp_ops), ..TestAccount::token_account(&mint_key, &msp_ops, 0) },
+            TestAccount::program(program_id),
+            TestAccount::program(&spl_token::id()),
+            TestAccount::program(&spl_associated_token_account::id()),
+            TestAccount::program(&system_program::id()),
+            TestAccount::rent()
+        ]
+    }
+
+    #[test]
+    fn top_ups_have_to_push_the_depletion_past_the_minimum() {
+        set_clock(1_200);
+        let program_id = crate::id();
+        let (stream, treasury) = funded_stream(&program_id);
+
+        // 90 left at 0.1 per second deplete at 2_100, topping up 50 (less the fee) moves it past 2_500
+        let mut accounts = add_funds_accounts(&program_id, &stream, &treasury);
+        assert_eq!(Processor::process_top_up(&infos(&mut accounts), &program_id, 50_000_000, 2_500), Ok(()));
+
+        let topped_up = accounts[7].unpack_stream();
+        assert!(topped_up.total_deposits > 149.0 && topped_up.total_deposits <= 150.0);
+        assert_eq!(topped_up.treasury_estimated_depletion_utc, topped_up.estimated_depletion_utc(1_200));
+
+        let mut accounts = add_funds_accounts(&program_id, &stream, &treasury);
+        assert_eq!(
+            Processor::process_top_up(&infos(&mut accounts), &program_id, 50_000_000, 3_000),
+            Err(StreamError::DepletionTooSoon.into())
+        );
+    }
+
+    // Accounts of the closing of `stream` by `initializer`, paying each party into its own token account
+    fn close_accounts(program_id: &Pubkey, stream: &Stream, treasury: &Treasury, initializer: &Pubkey) -> Vec<TestAccount> {
+        let mint = TestAccount::mint(6);
+        let mint_key = mint.key;
+
+        vec![
+            TestAccount::signer(initializer),
+            TestAccount::token_account(&mint_key, &stream.treasurer_address, 0),
+            TestAccount::token_account(&mint_key, &stream.beneficiary_address, 0),
+            mint,
+            TestAccount { key: stream.treasury_address, ..TestAccount::treasury(program_id, treasury) },
+            TestAccount::token_account(&mint_key, &stream.treasury_address, 1_000_000_000),
+            TestAccount::stream(program_id, stream),
+            TestAccount::new(&system_program::id(), Vec::new()),
+            TestAccount::token_account(&mint_key, &Pubkey::new_unique(), 0),
+            TestAccount::program(program_id),
+            TestAccount::program(&spl_token::id()),
+            TestAccount::program(&system_program::id())
+        ]
+    }
+
+    #[test]
+    fn closed_streams_pay_each_party_into_its_own_token_account() {
+        set_clock(1_200);
+        let program_id = crate::id();
+        let (stream, mut treasury) = funded_stream(&program_id);
+        treasury.streams_count = 1;
+
+        // Whoever closes the stream, the token accounts have to be the ones of the treasurer and the beneficiary
+        let mut accounts = close_accounts(&program_id, &stream, &treasury, &stream.beneficiary_address);
+        accounts[1] = TestAccount::token_account(&accounts[3].key, &stream.beneficiary_address, 0);
+        assert_eq!(
+            Processor::process_close_stream(&infos(&mut accounts), &program_id),
+            Err(StreamError::InvalidTreasurerTokenAccount.into())
+        );
+
+        let mut accounts = close_accounts(&program_id, &stream, &treasury, &stream.treasurer_address);
+        accounts[2] = TestAccount::token_account(&accounts[3].key, &stream.treasurer_address, 0);
+        assert_eq!(
+            Processor::process_close_stream(&infos(&mut accounts), &program_id),
+            Err(StreamError::InvalidBeneficiaryTokenAccount.into())
+        );
+
+        let mut accounts = close_accounts(&program_id, &stream, &treasury, &stream.beneficiary_address);
+        assert_eq!(Processor::process_close_stream(&infos(&mut accounts), &program_id), Ok(()));
+        assert!(!accounts[6].unpack_stream().initialized);
+        assert_eq!(accounts[6].lamports, 0);
+        assert_eq!(Treasury::unpack_any_len(&accounts[4].data).unwrap().streams_count, 0);
+    }
+
+    #[test]
+    fn updates_are_not_proposed_while_one_is_pending() {
+        set_clock(1_200);
+        let program_id = crate::id();
+        let (stream, _treasury) = funded_stream(&program_id);
+        let mut stream_terms = StreamTerms::default();
+        stream_terms.initialized = true;
+        stream_terms.proposed_by = stream.beneficiary_address;
+        let mut stream_terms_data = vec![0u8; StreamTerms::LEN];
+        stream_terms.pack_into_slice(&mut stream_terms_data);
+        let mut accounts = vec![
+            TestAccount::signer(&stream.treasurer_address),
+            TestAccount::new(&program_id, stream_terms_data),
+            TestAccount::new(&system_program::id(), Vec::new()),
+            TestAccount::stream(&program_id, &stream),
+            TestAccount::new(&system_program::id(), Vec::new()),
+            TestAccount::program(&system_program::id())
+        ];
+
+        assert_eq!(
+            Processor::process_propose_update(
+                &infos(&mut accounts),
+                &program_id,
+                stream.treasurer_address,
+                String::new(),
+                Pubkey::default(),
+                Pubkey::default(),
+                Pubkey::default(),
+                2.0,
+                0,
+                0,
+                0.0,
+                0.0,
+                0,
+                3_600
+            ),
+            Err(StreamError::UpdateAlreadyPending.into())
+        );
+    }
 }
@@ -1,10 +1,12 @@
 // Program objects, (de)serializing state
 
-use std::string::String;
+use std::{ cmp, convert::TryFrom, string::String };
 
 use solana_program::{
+    hash::hash,
     pubkey::Pubkey,
     program_error::ProgramError,    
+    rent::Rent,
     program_pack::{ IsInitialized, Pack, Sealed }
 };
 
@@ -15,23 +17,175 @@ use arrayref::{
     array_refs, 
 };
 
-use crate::error::{ StreamError, TreasuryError };
+#[cfg(feature = "borsh")]
+use borsh::{ BorshDeserialize, BorshSerialize };
+
+#[cfg(feature = "serde")]
+use serde::{ Deserialize, Serialize };
+
+use crate::{
+    error::{ StreamError, TreasuryError },
+    amount::to_base_amount,
+    constants::{ MAX_STREAM_NAME_LEN, SECONDS_PER_DAY, SECONDS_PER_MONTH }
+};
+
+// Serializes `Pubkey` fields as base58 strings instead of byte arrays
+#[cfg(feature = "serde")]
+mod pubkey_base58 {
+    use std::str::FromStr;
+    use serde::{ de, Deserialize, Deserializer, Serializer };
+    use solana_program::pubkey::Pubkey;
+
+    pub fn serialize<S: Serializer>(pubkey: &Pubkey, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&pubkey.to_string())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Pubkey, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        Pubkey::from_str(&encoded).map_err(de::Error::custom)
+    }
+
+    pub mod option {
+        use std::str::FromStr;
+        use serde::{ de, Deserialize, Deserializer, Serializer };
+        use solana_program::pubkey::Pubkey;
+
+        pub fn serialize<S: Serializer>(pubkey: &Option<Pubkey>, serializer: S) -> Result<S::Ok, S::Error> {
+            match pubkey {
+                Some(pubkey) => serializer.serialize_some(&pubkey.to_string()),
+                None => serializer.serialize_none()
+            }
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Pubkey>, D::Error> {
+            Option::<String>::deserialize(deserializer)?
+                .map(|encoded| Pubkey::from_str(&encoded).map_err(de::Error::custom))
+                .transpose()
+        }
+    }
+}
+
+// Relative tolerance used when comparing f64 stream amounts
+const LEDGER_EPSILON: f64 = 1e-9;
+
+// The longest prefix of the name that fits in `MAX_STREAM_NAME_LEN` bytes without splitting a char
+fn truncate_stream_name(stream_name: &str) -> &str {
+    let mut len = stream_name.len().min(MAX_STREAM_NAME_LEN);
+
+    while !stream_name.is_char_boundary(len) {
+        len -= 1;
+    }
+
+    &stream_name[..len]
+}
+
+// Copies the name and zero pads the rest of the field. The handlers reject names over
+// `MAX_STREAM_NAME_LEN` bytes, a longer one set off-chain is truncated instead of overflowing the field
+fn pack_stream_name(stream_name: &str, output: &mut [u8; 32]) {
+    let stream_name = truncate_stream_name(stream_name);
+
+    output.fill(0);
+    output[..stream_name.len()].copy_from_slice(stream_name.as_bytes());
+}
+
+// Reads the name back without the zero padding added by `pack_stream_name`
+fn unpack_stream_name(input: &[u8; 32]) -> String {
+    let len = input.iter().rposition(|b| *b != 0).map_or(0, |i| i + 1);
+
+    String::from_utf8_lossy(&input[..len]).to_string()
+}
+
+// Little-endian u32 length followed by the name bytes, zero padded, the same length prefixed
+// encoding the instructions use
+fn pack_prefixed_stream_name(stream_name: &str, output: &mut [u8; 36]) {
+    let (len_output, name_output) = mut_array_refs![output, 4, 32];
+    let stream_name = truncate_stream_name(stream_name);
+
+    *len_output = (stream_name.len() as u32).to_le_bytes();
+    pack_stream_name(stream_name, name_output);
+}
+
+// Fails on a length over 32 bytes or bytes that are not valid UTF-8
+fn unpack_prefixed_stream_name(input: &[u8; 36]) -> Result<String, ProgramError> {
+    let (len, name) = array_refs![input, 4, 32];
+    let len = u32::from_le_bytes(*len) as usize;
+
+    if len > name.len() {
+        return Err(StreamError::InvalidStreamData.into());
+    }
+
+    String::from_utf8(name[..len].to_vec()).map_err(|_| StreamError::InvalidStreamData.into())
+}
+
+// Presence byte followed by the key (zeroed when there is none)
+fn pack_optional_pubkey(pubkey: &Option<Pubkey>, output: &mut [u8; 33]) {
+    let (presence_output, pubkey_output) = mut_array_refs![output, 1, 32];
+
+    match pubkey {
+        Some(pubkey) => {
+            presence_output[0] = 1;
+            pubkey_output.copy_from_slice(pubkey.as_ref());
+        },
+        None => {
+            presence_output[0] = 0;
+            pubkey_output.fill(0);
+        }
+    }
+}
+
+fn unpack_optional_pubkey(input: &[u8; 33]) -> Result<Option<Pubkey>, ProgramError> {
+    let (presence, pubkey) = array_refs![input, 1, 32];
+
+    match presence {
+        [0] => Ok(None),
+        [1] => Ok(Some(Pubkey::new_from_array(*pubkey))),
+        _ => Err(StreamError::InvalidStreamData.into())
+    }
+}
+
+/// Layout version of the Stream account. Stream and StreamTerms versions are numbered from one
+/// sequence, a number is never used by both, so the leading version byte also tells the account
+/// type apart (see `decode_account`). Bump it on every layout change and teach `unpack_any_version`
+/// the previous layout. Version 0 is the layout without a version byte, 1 to 5 were never released
+pub const CURRENT_VERSION: u8 = 6;
+
+/// Layout version of the StreamTerms account, from the same sequence as `CURRENT_VERSION`
+pub const STREAM_TERMS_VERSION: u8 = 8;
+
+// A freshly allocated (zeroed) account has no version yet
+fn check_version(version: u8, expected_version: u8, initialized: bool) -> Result<u8, ProgramError> {
+    if version != expected_version && (initialized || version != 0) {
+        return Err(StreamError::UnsupportedStateVersion.into());
+    }
+
+    Ok(version)
+}
 
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "borsh", derive(BorshSerialize, BorshDeserialize))]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct StreamTerms {
+    pub version: u8,
     pub initialized: bool,
+    #[cfg_attr(feature = "serde", serde(with = "pubkey_base58"))]
     pub proposed_by: Pubkey,
+    #[cfg_attr(feature = "serde", serde(with = "pubkey_base58"))]
     pub stream_id: Pubkey,
     pub stream_name: String,
+    #[cfg_attr(feature = "serde", serde(with = "pubkey_base58"))]
     pub treasurer_address: Pubkey,
+    #[cfg_attr(feature = "serde", serde(with = "pubkey_base58"))]
     pub beneficiary_address: Pubkey,
+    #[cfg_attr(feature = "serde", serde(with = "pubkey_base58"))]
     pub associated_token_address: Pubkey,
     pub rate_amount: f64,
     pub rate_interval_in_seconds: u64,
     pub rate_cliff_in_seconds: u64,
     pub cliff_vest_amount: f64,
     pub cliff_vest_percent: f64,
-    pub auto_pause_in_seconds: u64
+    pub auto_pause_in_seconds: u64,
+    pub proposed_at_utc: u64,
+    pub expiration_in_seconds: u64 // seconds after `proposed_at_utc` the proposal can be expired
 }
 
 impl Sealed for StreamTerms {}
@@ -45,6 +199,7 @@ impl IsInitialized for StreamTerms {
 impl Default for StreamTerms {
     fn default() -> Self {
         Self {
+            version: STREAM_TERMS_VERSION,
             initialized: false,
             proposed_by: Pubkey::default(),
             stream_id: Pubkey::default(),
@@ -57,17 +212,78 @@ impl Default for StreamTerms {
             rate_cliff_in_seconds: 0,
             cliff_vest_amount: 0.0,
             cliff_vest_percent: 100.0,
-            auto_pause_in_seconds: 0
+            auto_pause_in_seconds: 0,
+            proposed_at_utc: 0,
+            expiration_in_seconds: 0
+        }
+    }
+}
+
+impl StreamTerms {
+
+    /// Lamports a stream terms account needs to be rent exempt
+    pub fn rent_exempt_lamports(rent: &Rent) -> u64 {
+        rent.minimum_balance(Self::LEN)
+    }
+
+    /// Whether the proposal can be expired at `now_utc`, `expiration_in_seconds` after it was proposed
+    pub fn is_expired(&self, now_utc: u64) -> bool {
+        now_utc >= self.proposed_at_utc.saturating_add(self.expiration_in_seconds)
+    }
+
+    /// Names of the `stream` fields that approving these terms would change. Follows `AnswerUpdate`:
+    /// unset terms (default key, 0, or 100 for the cliff percent) leave the stream field as is
+    pub fn diff(&self, stream: &Stream) -> Vec<&'static str> {
+        let mut fields = Vec::with_capacity(9);
+        let unset_key = Pubkey::default();
+
+        if self.treasurer_address != unset_key && self.treasurer_address != stream.treasurer_address {
+            fields.push("treasurer_address");
+        }
+
+        if self.beneficiary_address != unset_key && self.beneficiary_address != stream.beneficiary_address {
+            fields.push("beneficiary_address");
+        }
+
+        if self.associated_token_address != unset_key && self.associated_token_address != stream.beneficiary_associated_token {
+            fields.push("associated_token_address");
+        }
+
+        if self.rate_amount != 0.0 && self.rate_amount != stream.rate_amount {
+            fields.push("rate_amount");
+        }
+
+        if self.rate_interval_in_seconds != 0 && self.rate_interval_in_seconds != stream.rate_interval_in_seconds {
+            fields.push("rate_interval_in_seconds");
+        }
+
+        if self.rate_cliff_in_seconds != 0 && self.rate_cliff_in_seconds != stream.rate_cliff_in_seconds {
+            fields.push("rate_cliff_in_seconds");
+        }
+
+        if self.cliff_vest_amount != 0.0 && self.cliff_vest_amount != stream.cliff_vest_amount {
+            fields.push("cliff_vest_amount");
+        }
+
+        if self.cliff_vest_percent != 100.0 && self.cliff_vest_percent != stream.cliff_vest_percent {
+            fields.push("cliff_vest_percent");
+        }
+
+        if self.auto_pause_in_seconds != 0 && self.auto_pause_in_seconds != stream.auto_pause_in_seconds {
+            fields.push("auto_pause_in_seconds");
         }
+
+        fields
     }
 }
 
 impl Pack for StreamTerms {
-    const LEN: usize = 241;
+    const LEN: usize = 262;
 
     fn pack_into_slice(&self, output: &mut [u8]) {
         let output = array_mut_ref![output, 0, StreamTerms::LEN];
         let (
+            version_output,
             initialized_output,
             proposed_by_output,
             stream_id_output,
@@ -80,11 +296,14 @@ impl Pack for StreamTerms {
             rate_cliff_in_seconds_output,
             cliff_vest_amount_output,
             cliff_vest_percent_output,
-            auto_pause_in_seconds_output
+            auto_pause_in_seconds_output,
+            proposed_at_utc_output,
+            expiration_in_seconds_output
             
-        ) = mut_array_refs![output, 1, 32, 32, 32, 32, 32, 32, 8, 8, 8, 8, 8, 8];
+        ) = mut_array_refs![output, 1, 1, 32, 32, 36, 32, 32, 32, 8, 8, 8, 8, 8, 8, 8, 8];
 
         let StreamTerms {
+            version,
             initialized,
             proposed_by,
             stream_id,
@@ -97,14 +316,17 @@ impl Pack for StreamTerms {
             rate_cliff_in_seconds,
             cliff_vest_amount,
             cliff_vest_percent,
-            auto_pause_in_seconds
+            auto_pause_in_seconds,
+            proposed_at_utc,
+            expiration_in_seconds
 
         } = self;
 
+        version_output[0] = *version;
         initialized_output[0] = *initialized as u8;
         proposed_by_output.copy_from_slice(proposed_by.as_ref());
         stream_id_output.copy_from_slice(stream_id.as_ref());
-        stream_name_output.copy_from_slice(stream_name.as_ref());
+        pack_prefixed_stream_name(stream_name, stream_name_output);
         treasurer_address_output.copy_from_slice(treasurer_address.as_ref());
         beneficiary_address_output.copy_from_slice(beneficiary_address.as_ref());
         associated_token_address_output.copy_from_slice(associated_token_address.as_ref());
@@ -114,11 +336,14 @@ impl Pack for StreamTerms {
         *cliff_vest_amount_output = cliff_vest_amount.to_le_bytes();
         *cliff_vest_percent_output = cliff_vest_percent.to_le_bytes();
         *auto_pause_in_seconds_output = auto_pause_in_seconds.to_le_bytes();
+        *proposed_at_utc_output = proposed_at_utc.to_le_bytes();
+        *expiration_in_seconds_output = expiration_in_seconds.to_le_bytes();
     }
     
     fn unpack_from_slice(input: &[u8]) -> Result<Self, ProgramError> {
         let input = array_ref![input, 0, StreamTerms::LEN];
         let (
+            version,
             initialized,
             proposed_by,
             stream_id,
@@ -131,9 +356,11 @@ impl Pack for StreamTerms {
             rate_cliff_in_seconds,
             cliff_vest_amount,
             cliff_vest_percent,
-            auto_pause_in_seconds
+            auto_pause_in_seconds,
+            proposed_at_utc,
+            expiration_in_seconds
             
-        ) = array_refs![input, 1, 32, 32, 32, 32, 32, 32, 8, 8, 8, 8, 8, 8];
+        ) = array_refs![input, 1, 1, 32, 32, 36, 32, 32, 32, 8, 8, 8, 8, 8, 8, 8, 8];
 
         let initialized = match initialized {
             [0] => false,
@@ -141,11 +368,14 @@ impl Pack for StreamTerms {
             _ => return Err(StreamError::InvalidStreamData.into()),
         };
 
+        let version = check_version(version[0], STREAM_TERMS_VERSION, initialized)?;
+
         Ok(StreamTerms {
+            version,
             initialized, 
             proposed_by: Pubkey::new_from_array(*proposed_by),
             stream_id: Pubkey::new_from_array(*stream_id),
-            stream_name: String::from_utf8_lossy(stream_name).to_string(),
+            stream_name: unpack_prefixed_stream_name(stream_name)?,
             treasurer_address: Pubkey::new_from_array(*treasurer_address),
             beneficiary_address: Pubkey::new_from_array(*beneficiary_address),
             associated_token_address: Pubkey::new_from_array(*associated_token_address),
@@ -154,15 +384,21 @@ impl Pack for StreamTerms {
             rate_cliff_in_seconds: u64::from_le_bytes(*rate_cliff_in_seconds),
             cliff_vest_amount: f64::from_le_bytes(*cliff_vest_amount),
             cliff_vest_percent: f64::from_le_bytes(*cliff_vest_percent),
-            auto_pause_in_seconds: u64::from_le_bytes(*auto_pause_in_seconds)
+            auto_pause_in_seconds: u64::from_le_bytes(*auto_pause_in_seconds),
+            proposed_at_utc: u64::from_le_bytes(*proposed_at_utc),
+            expiration_in_seconds: u64::from_le_bytes(*expiration_in_seconds)
         })
     }
 }
 
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "borsh", derive(BorshSerialize, BorshDeserialize))]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Stream {
+    pub version: u8,
     pub initialized: bool,
     pub stream_name: String,
+    #[cfg_attr(feature = "serde", serde(with = "pubkey_base58"))]
     pub treasurer_address: Pubkey,
     pub rate_amount: f64,
     pub rate_interval_in_seconds: u64,
@@ -171,8 +407,11 @@ pub struct Stream {
     pub rate_cliff_in_seconds: u64,
     pub cliff_vest_amount: f64,
     pub cliff_vest_percent: f64,
+    #[cfg_attr(feature = "serde", serde(with = "pubkey_base58"))]
     pub beneficiary_address: Pubkey,
+    #[cfg_attr(feature = "serde", serde(with = "pubkey_base58"))]
     pub beneficiary_associated_token: Pubkey,
+    #[cfg_attr(feature = "serde", serde(with = "pubkey_base58"))]
     pub treasury_address: Pubkey,
     pub treasury_estimated_depletion_utc: u64,
     pub total_deposits: f64,
@@ -182,7 +421,14 @@ pub struct Stream {
     pub escrow_vested_amount_snap_block_time: u64,
     pub stream_resumed_block_height: u64,
     pub stream_resumed_block_time: u64,
-    pub auto_pause_in_seconds: u64
+    pub auto_pause_in_seconds: u64,
+    #[cfg_attr(feature = "serde", serde(with = "pubkey_base58"))]
+    pub beneficiary_token_address: Pubkey,
+    pub last_activity_utc: u64,
+    #[cfg_attr(feature = "serde", serde(with = "pubkey_base58::option"))]
+    pub withdraw_hook_program: Option<Pubkey>,
+    pub client_ref: [u8; 16], // zeroes when the stream was created without an idempotency key
+    pub pause_reason: u8 // a `PauseReason`
 }
 
 impl Sealed for Stream {}
@@ -196,6 +442,7 @@ impl IsInitialized for Stream {
 impl Default for Stream {
     fn default() -> Self {
         Self {
+            version: CURRENT_VERSION,
             initialized: false,
             stream_name: String::default(),
             treasurer_address: Pubkey::default(),             
@@ -217,17 +464,411 @@ impl Default for Stream {
             escrow_vested_amount_snap_block_time: 0,
             stream_resumed_block_height: 0,
             stream_resumed_block_time: 0,
-            auto_pause_in_seconds: 0
+            auto_pause_in_seconds: 0,
+            beneficiary_token_address: Pubkey::default(),
+            last_activity_utc: 0,
+            withdraw_hook_program: None,
+            client_ref: [0; 16],
+            pause_reason: PauseReason::NotPaused as u8
+        }
+    }
+}
+
+/// Lifecycle status of a stream, as derived by `Stream::status`
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum StreamStatus {
+    /// The stream has not started yet
+    Scheduled,
+    /// Funds are vesting at the stream rate
+    Streaming,
+    /// The stream was paused and has funds left to stream
+    Paused,
+    /// Every deposited amount has vested, nothing is left to stream
+    Depleted,
+    /// The stream account was closed
+    Closed
+}
+
+/// Why a stream was last paused, stored in `Stream::pause_reason`
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[repr(u8)]
+pub enum PauseReason {
+    /// The stream is running (or was never paused)
+    NotPaused = 0,
+    /// Paused by the `PauseStream` instruction
+    Manual = 1,
+    /// Paused by the program when the stream ran out of funds
+    AutoLowFunds = 2
+}
+
+impl PauseReason {
+
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(Self::NotPaused),
+            1 => Some(Self::Manual),
+            2 => Some(Self::AutoLowFunds),
+            _ => None
         }
     }
 }
 
+impl Stream {
+    /// Data length of the version 0 stream accounts
+    pub const LEGACY_LEN: usize = 289;
+
+    /// Lamports a stream account needs to be rent exempt
+    pub fn rent_exempt_lamports(rent: &Rent) -> u64 {
+        rent.minimum_balance(Self::LEN)
+    }
+
+    /// Status of the stream at `now_utc`
+    pub fn status(&self, now_utc: u64) -> StreamStatus {
+        if !self.initialized
+        {
+            return StreamStatus::Closed;
+        }
+
+        if now_utc < self.start_utc
+        {
+            return StreamStatus::Scheduled;
+        }
+
+        if self.locked_amount(now_utc) <= 0.0
+        {
+            return StreamStatus::Depleted;
+        }
+
+        if !self.is_streaming()
+        {
+            return StreamStatus::Paused;
+        }
+
+        StreamStatus::Streaming
+    }
+
+    /// Decodes a batch of stream accounts data, one result per account
+    pub fn unpack_many(accounts: &[&[u8]]) -> Vec<Result<Stream, ProgramError>> {
+        accounts
+            .iter()
+            .map(|data| {
+                if data.len() < Stream::LEN {
+                    return Err(StreamError::InvalidStreamData.into());
+                }
+
+                Stream::unpack_from_slice(data)
+            })
+            .collect()
+    }
+
+    /// Decodes stream account data written by any known layout version, each version gets its own arm here.
+    /// Older layouts come back as a `CURRENT_VERSION` stream, the fields they lack zeroed
+    pub fn unpack_any_version(data: &[u8]) -> Result<Stream, ProgramError> {
+        match data.first() {
+            // Version 0 has no version byte, it starts with the initialized flag and the current
+            // layout appended its fields after it
+            Some(0) | Some(1) if data.len() == Stream::LEGACY_LEN => {
+                let mut current = [0u8; Stream::LEN];
+                current[0] = CURRENT_VERSION;
+                current[1..=Stream::LEGACY_LEN].copy_from_slice(data);
+
+                Stream::unpack_from_slice(&current)
+            },
+            Some(0) | Some(&CURRENT_VERSION) if data.len() >= Stream::LEN => Stream::unpack_from_slice(data),
+            Some(0) | Some(1) | Some(&CURRENT_VERSION) => Err(StreamError::InvalidStreamData.into()),
+            Some(_) => Err(StreamError::UnsupportedStateVersion.into()),
+            None => Err(StreamError::InvalidStreamData.into())
+        }
+    }
+
+    /// SHA-256 of the packed account data, for caches to detect a changed stream without comparing fields
+    pub fn content_hash(&self) -> [u8; 32] {
+        let mut data = [0u8; Stream::LEN];
+        self.pack_into_slice(&mut data);
+
+        hash(&data).to_bytes()
+    }
+
+    /// Amount streamed per second
+    pub fn rate_per_second(&self) -> f64 {
+        if self.rate_interval_in_seconds == 0 {
+            return 0.0;
+        }
+
+        self.rate_amount / (self.rate_interval_in_seconds as f64)
+    }
+
+    /// Amount streamed per day
+    pub fn rate_per_day(&self) -> f64 {
+        self.rate_per_second() * (SECONDS_PER_DAY as f64)
+    }
+
+    /// Amount streamed per month, taking a month as 30 days
+    pub fn rate_per_month(&self) -> f64 {
+        self.rate_per_second() * (SECONDS_PER_MONTH as f64)
+    }
+
+    /// Whether the stream terms can ever pay out: false with no rate and no cliff vest
+    pub fn is_payable(&self) -> bool {
+        self.rate_per_second() > 0.0 || self.cliff_vest_amount > 0.0 || self.cliff_vest_percent > 0.0
+    }
+
+    /// Whether the stream was resumed after its last pause snapshot
+    pub fn is_streaming(&self) -> bool {
+        self.stream_resumed_block_time >= self.escrow_vested_amount_snap_block_time
+    }
+
+    /// When the funds left in the stream run out at the current rate, `u64::MAX` if the stream is paused
+    pub fn estimated_depletion_utc(&self, now_utc: u64) -> u64 {
+        let rate = self.rate_per_second();
+
+        if !self.is_streaming() || rate <= 0.0
+        {
+            return u64::MAX;
+        }
+
+        let locked_amount = self.locked_amount(now_utc);
+
+        if locked_amount <= 0.0
+        {
+            return now_utc;
+        }
+
+        now_utc.saturating_add((locked_amount / rate) as u64)
+    }
+
+    /// When the cliff ends and the stream starts vesting: `start_utc` plus `rate_cliff_in_seconds`
+    pub fn cliff_end_utc(&self) -> u64 {
+        self.start_utc.saturating_add(self.rate_cliff_in_seconds)
+    }
+
+    /// Whether `now_utc` is before the end of the cliff, when nothing can be withdrawn yet
+    pub fn in_cliff_period(&self, now_utc: u64) -> bool {
+        now_utc < self.cliff_end_utc()
+    }
+
+    /// Amount released as a lump at the end of the cliff: `cliff_vest_amount`, or else
+    /// `cliff_vest_percent` of the total deposits
+    pub fn cliff_amount(&self) -> f64 {
+        if self.cliff_vest_amount > 0.0
+        {
+            return self.cliff_vest_amount;
+        }
+
+        self.total_deposits * self.cliff_vest_percent / 100f64
+    }
+
+    /// Total amount vested by `now_utc` (withdrawn or not), in base units of the stream mint with
+    /// `decimals`. Nothing vests before the end of the cliff, which releases `cliff_amount` once,
+    /// then it accrues at the stream rate
+    pub fn vested_amount(&self, now_utc: u64, decimals: u8) -> Result<u64, StreamError> {
+        to_base_amount(self.vested_ui_amount(now_utc), 10f64.powi(decimals as i32))
+    }
+
+    // Same as `vested_amount` in the UI units the stream totals are kept in
+    fn vested_ui_amount(&self, now_utc: u64) -> f64 {
+
+        if self.in_cliff_period(now_utc)
+        {
+            return 0.0;
+        }
+
+        self.total_withdrawals + self.escrow_vested_amount(now_utc)
+    }
+
+    /// Vested and not withdrawn amount at `now_utc` as the processor handlers compute it: the
+    /// `escrow_vested_amount_snap` plus the rate accrued since the latest of the snapshot, the
+    /// last resume (nothing accrues while paused) and the cliff end, capped to the escrow balance.
+    /// Past the cliff, the `vested_amount` is `total_withdrawals` plus this amount in UI units.
+    /// Returns UI units (`f64`) like every other amount of the stream, not base units, and accrues
+    /// from the snapshot time since the rate is per second (the snapshot block height is not used)
+    pub fn vested_amount_from_snapshot(&self, now_utc: u64) -> f64 {
+        self.escrow_vested_amount(now_utc)
+    }
+
+    /// Amount that would have vested by `now_utc` if the stream had never been paused
+    /// (rate × elapsed since `start_utc`), to compare against `vested_amount`
+    pub fn theoretical_vested(&self, now_utc: u64) -> f64 {
+        self.vested_without_pauses(self.start_utc, now_utc)
+    }
+
+    /// Seconds elapsed between the last handler that modified the stream and `now_utc`
+    pub fn seconds_since_activity(&self, now_utc: u64) -> u64 {
+        now_utc.saturating_sub(self.last_activity_utc)
+    }
+
+    /// Amount the beneficiary can withdraw at `now_utc` (vested and not withdrawn yet), in base
+    /// units of the stream mint with `decimals` like `vested_amount`
+    pub fn withdrawable_amount(&self, now_utc: u64, decimals: u8) -> Result<u64, StreamError> {
+        to_base_amount(self.withdrawable_ui_amount(now_utc), 10f64.powi(decimals as i32))
+    }
+
+    // Same as `withdrawable_amount` in the UI units the stream totals are kept in
+    pub(crate) fn withdrawable_ui_amount(&self, now_utc: u64) -> f64 {
+        (self.vested_ui_amount(now_utc) - self.total_withdrawals).max(0.0)
+    }
+
+    /// Fails with `WithdrawalExceedsVested` when `withdrawal_amount` (UI units) is over the
+    /// `withdrawable_amount` at `now_utc`, compared in base units of a mint with `decimals` as the
+    /// `Withdraw` handler does
+    pub fn check_withdrawal(&self, withdrawal_amount: f64, now_utc: u64, decimals: u8) -> Result<(), StreamError> {
+        if to_base_amount(withdrawal_amount, 10f64.powi(decimals as i32))? > self.withdrawable_amount(now_utc, decimals)?
+        {
+            return Err(StreamError::WithdrawalExceedsVested);
+        }
+
+        Ok(())
+    }
+
+    /// Amount deposited that has not vested yet by `now_utc`
+    pub fn locked_amount(&self, now_utc: u64) -> f64 {
+        (self.total_deposits - self.total_withdrawals - self.withdrawable_ui_amount(now_utc)).max(0.0)
+    }
+
+    /// Whether the stream accounting holds at `now_utc`: the withdrawals fit in the deposits, the
+    /// `escrow_vested_amount_snap` fits in what is left of them and, past the cliff, the `vested_amount`
+    /// is at least what was withdrawn and at most what was deposited
+    pub fn ledger_is_balanced(&self, now_utc: u64) -> bool {
+        let tolerance = LEDGER_EPSILON * self.total_deposits.abs().max(1.0);
+        let escrow_amount = self.total_deposits - self.total_withdrawals;
+
+        if self.total_withdrawals < -tolerance || escrow_amount < -tolerance
+        {
+            return false;
+        }
+
+        if self.escrow_vested_amount_snap < -tolerance || self.escrow_vested_amount_snap > escrow_amount + tolerance
+        {
+            return false;
+        }
+
+        if self.in_cliff_period(now_utc)
+        {
+            return true;
+        }
+
+        let vested_amount = self.vested_ui_amount(now_utc);
+
+        vested_amount >= self.total_withdrawals - tolerance && vested_amount <= self.total_deposits + tolerance
+    }
+
+    /// Full `rate_interval_in_seconds` payouts the funds left at `now_utc` can cover at the
+    /// stream rate. 0 when the rate is 0 (there are no payouts)
+    pub fn remaining_intervals(&self, now_utc: u64) -> u64 {
+        if self.rate_amount <= 0.0 || self.rate_interval_in_seconds == 0
+        {
+            return 0;
+        }
+
+        // Tolerate the f64 error on exact multiples (e.g. 0.3 / 0.1)
+        let intervals = self.locked_amount(now_utc) / self.rate_amount * (1.0 + LEDGER_EPSILON);
+
+        intervals.floor() as u64
+    }
+
+    /// Whether a running stream has less than `auto_pause_in_seconds` of streaming left at `now_utc`
+    pub fn should_auto_pause(&self, now_utc: u64) -> bool {
+        let rate = self.rate_per_second();
+
+        if !self.is_streaming() || self.auto_pause_in_seconds == 0 || rate <= 0.0
+        {
+            return false;
+        }
+
+        self.locked_amount(now_utc) < rate * (self.auto_pause_in_seconds as f64)
+    }
+
+    /// Largest withdrawal at `now_utc` that still leaves `auto_pause_in_seconds` of streaming in reserve
+    pub fn max_withdrawal_without_pause(&self, now_utc: u64) -> f64 {
+        let reserve = self.rate_per_second() * (self.auto_pause_in_seconds as f64);
+
+        (self.withdrawable_ui_amount(now_utc) - reserve).max(0.0)
+    }
+
+    /// Amount a contributor holding `contributor_pool_tokens` of the `pool_supply` treasury pool tokens
+    /// can recover at `now_utc`: their pro-rata share of what has not vested to the beneficiary yet
+    pub fn recoverable_amount(&self, contributor_pool_tokens: f64, pool_supply: f64, now_utc: u64) -> f64 {
+        if pool_supply <= 0.0
+        {
+            return 0.0;
+        }
+
+        let share = (contributor_pool_tokens / pool_supply).min(1.0).max(0.0);
+
+        share * self.locked_amount(now_utc)
+    }
+
+    /// Treasury pool tokens `AddFunds` mints for a contribution of `amount` to a treasury holding
+    /// `treasury_balance`, both in pool token base units (the UI amounts times 10^`TREASURY_MINT_DECIMALS`).
+    /// The contributor gets the share of the `pool_supply` their contribution adds to the treasury,
+    /// 1:1 on the first deposit (or while the treasury holds nothing)
+    pub fn pool_tokens_for_contribution(&self, amount: u64, pool_supply: u64, treasury_balance: u64) -> Result<u64, StreamError> {
+        if pool_supply == 0 || treasury_balance == 0
+        {
+            return Ok(amount);
+        }
+
+        let pool_tokens = (amount as u128)
+            .checked_mul(pool_supply as u128)
+            .and_then(|tokens| tokens.checked_div(treasury_balance as u128))
+            .ok_or(StreamError::Overflow)?;
+
+        u64::try_from(pool_tokens).map_err(|_| StreamError::Overflow)
+    }
+
+    /// Amount that would have vested by `now_utc` if the stream started at `new_start_utc`: the
+    /// cliff amount at the new cliff end, then the rate from there on. The snapshots and pauses of
+    /// the current schedule don't apply to another one, so none are taken into account
+    pub fn vested_if_start(&self, new_start_utc: u64, now_utc: u64) -> f64 {
+        self.vested_without_pauses(new_start_utc, now_utc)
+    }
+
+    fn vested_without_pauses(&self, start_utc: u64, now_utc: u64) -> f64 {
+
+        let cliff_end_utc = start_utc.saturating_add(self.rate_cliff_in_seconds);
+
+        if now_utc < cliff_end_utc
+        {
+            return 0.0;
+        }
+
+        let elapsed_time = (now_utc - cliff_end_utc) as f64;
+
+        (self.cliff_amount() + self.rate_per_second() * elapsed_time).min(self.total_deposits)
+    }
+
+    // The rate accrues from the cliff end on. A snapshot taken before the cliff end can not hold the
+    // cliff amount yet so it is added here, a later one already does and it is not counted twice
+    fn escrow_vested_amount(&self, now_utc: u64) -> f64 {
+
+        let cliff_end_utc = self.cliff_end_utc();
+        let is_running = self.is_streaming() as u64;
+        let rate = self.rate_per_second() * (is_running as f64);
+        let marker_block_time = cmp::max(
+            cmp::max(self.stream_resumed_block_time, self.escrow_vested_amount_snap_block_time),
+            cliff_end_utc
+        );
+        let elapsed_time = now_utc.saturating_sub(marker_block_time) as f64;
+        let mut escrow_vested_amount = self.escrow_vested_amount_snap + rate * elapsed_time;
+
+        if now_utc >= cliff_end_utc && self.escrow_vested_amount_snap_block_time < cliff_end_utc
+        {
+            escrow_vested_amount += self.cliff_amount();
+        }
+
+        escrow_vested_amount.min(self.total_deposits - self.total_withdrawals)
+    }
+}
+
 impl Pack for Stream {
-    const LEN: usize = 289;
+    const LEN: usize = 380;
 
     fn pack_into_slice(&self, output: &mut [u8]) {
         let output = array_mut_ref![output, 0, Stream::LEN];
         let (
+            version_output,
             initialized_output,
             stream_name_output,
             treasurer_address_output,
@@ -249,11 +890,17 @@ impl Pack for Stream {
             escrow_vested_amount_snap_block_time_output,
             stream_resumed_block_height_output,
             stream_resumed_block_time_output,
-            auto_pause_in_seconds_output
+            auto_pause_in_seconds_output,
+            beneficiary_token_address_output,
+            last_activity_utc_output,
+            withdraw_hook_program_output,
+            client_ref_output,
+            pause_reason_output
             
-        ) = mut_array_refs![output, 1, 32, 32, 8, 8, 8, 8, 8, 8, 8, 32, 32, 32, 8, 8, 8, 8, 8, 8, 8, 8, 8];
+        ) = mut_array_refs![output, 1, 1, 32, 32, 8, 8, 8, 8, 8, 8, 8, 32, 32, 32, 8, 8, 8, 8, 8, 8, 8, 8, 8, 32, 8, 33, 16, 1];
 
         let Stream {
+            version,
             initialized,
             stream_name,
             treasurer_address,
@@ -275,12 +922,18 @@ impl Pack for Stream {
             escrow_vested_amount_snap_block_time,
             stream_resumed_block_height,
             stream_resumed_block_time,
-            auto_pause_in_seconds
+            auto_pause_in_seconds,
+            beneficiary_token_address,
+            last_activity_utc,
+            withdraw_hook_program,
+            client_ref,
+            pause_reason
 
         } = self;
 
+        version_output[0] = *version;
         initialized_output[0] = *initialized as u8;
-        stream_name_output.copy_from_slice(stream_name.as_ref());
+        pack_stream_name(stream_name, stream_name_output);
         treasurer_address_output.copy_from_slice(treasurer_address.as_ref());
         *rate_amount_output = rate_amount.to_le_bytes();
         *rate_interval_in_seconds_output = rate_interval_in_seconds.to_le_bytes();
@@ -301,11 +954,17 @@ impl Pack for Stream {
         *stream_resumed_block_height_output = stream_resumed_block_height.to_le_bytes();
         *stream_resumed_block_time_output = stream_resumed_block_time.to_le_bytes();
         *auto_pause_in_seconds_output = auto_pause_in_seconds.to_le_bytes();
+        beneficiary_token_address_output.copy_from_slice(beneficiary_token_address.as_ref());
+        *last_activity_utc_output = last_activity_utc.to_le_bytes();
+        pack_optional_pubkey(withdraw_hook_program, withdraw_hook_program_output);
+        *client_ref_output = *client_ref;
+        pause_reason_output[0] = *pause_reason;
     }
     
     fn unpack_from_slice(input: &[u8]) -> Result<Self, ProgramError> {
         let input = array_ref![input, 0, Stream::LEN];
         let (
+            version,
             initialized,
             stream_name,
             treasurer_address,
@@ -327,9 +986,14 @@ impl Pack for Stream {
             escrow_vested_amount_snap_block_time,
             stream_resumed_block_height,
             stream_resumed_block_time,
-            auto_pause_in_seconds
+            auto_pause_in_seconds,
+            beneficiary_token_address,
+            last_activity_utc,
+            withdraw_hook_program,
+            client_ref,
+            pause_reason
             
-        ) = array_refs![input, 1, 32, 32, 8, 8, 8, 8, 8, 8, 8, 32, 32, 32, 8, 8, 8, 8, 8, 8, 8, 8, 8];
+        ) = array_refs![input, 1, 1, 32, 32, 8, 8, 8, 8, 8, 8, 8, 32, 32, 32, 8, 8, 8, 8, 8, 8, 8, 8, 8, 32, 8, 33, 16, 1];
 
         let initialized = match initialized {
             [0] => false,
@@ -337,9 +1001,16 @@ impl Pack for Stream {
             _ => return Err(StreamError::InvalidStreamData.into()),
         };
 
+        let version = check_version(version[0], CURRENT_VERSION, initialized)?;
+
+        if PauseReason::from_u8(pause_reason[0]).is_none() {
+            return Err(StreamError::InvalidStreamData.into());
+        }
+
         Ok(Stream {
+            version,
             initialized, 
-            stream_name: String::from_utf8_lossy(stream_name).to_string(),
+            stream_name: unpack_stream_name(stream_name),
             treasurer_address: Pubkey::new_from_array(*treasurer_address),                   
             rate_amount: f64::from_le_bytes(*rate_amount),
             rate_interval_in_seconds: u64::from_le_bytes(*rate_interval_in_seconds),
@@ -359,11 +1030,148 @@ impl Pack for Stream {
             escrow_vested_amount_snap_block_time: u64::from_le_bytes(*escrow_vested_amount_snap_block_time),
             stream_resumed_block_height: u64::from_le_bytes(*stream_resumed_block_height),
             stream_resumed_block_time: u64::from_le_bytes(*stream_resumed_block_time),
-            auto_pause_in_seconds: u64::from_le_bytes(*auto_pause_in_seconds)
+            auto_pause_in_seconds: u64::from_le_bytes(*auto_pause_in_seconds),
+            beneficiary_token_address: Pubkey::new_from_array(*beneficiary_token_address),
+            last_activity_utc: u64::from_le_bytes(*last_activity_utc),
+            withdraw_hook_program: unpack_optional_pubkey(withdraw_hook_program)?,
+            client_ref: *client_ref,
+            pause_reason: pause_reason[0]
         })
     }
 }
 
+// Borsh encoding for off-chain clients. The on-chain account data keeps the `Pack` layout,
+// the only size differences being the stream name: borsh writes a u32 length followed by the
+// name bytes (4 + len) instead of the fixed 32 bytes field (36 with its length for StreamTerms),
+// and an unset withdraw hook program which borsh writes as a single 0 byte instead of 33 bytes.
+
+#[cfg(feature = "borsh")]
+impl StreamTerms {
+
+    pub fn try_from_borsh(input: &[u8]) -> Result<Self, ProgramError> {
+        Ok(Self::try_from_slice(input).map_err(StreamError::from)?)
+    }
+
+    pub fn to_borsh(&self) -> Result<Vec<u8>, ProgramError> {
+        Ok(self.try_to_vec().map_err(StreamError::from)?)
+    }
+}
+
+#[cfg(feature = "borsh")]
+impl Stream {
+
+    pub fn try_from_borsh(input: &[u8]) -> Result<Self, ProgramError> {
+        Ok(Self::try_from_slice(input).map_err(StreamError::from)?)
+    }
+
+    pub fn to_borsh(&self) -> Result<Vec<u8>, ProgramError> {
+        Ok(self.try_to_vec().map_err(StreamError::from)?)
+    }
+}
+
+/// Read-only view over packed stream account data, reading single fields at their `Pack` offsets
+/// without decoding the whole account
+#[derive(Clone, Copy, Debug)]
+pub struct StreamView<'a>(&'a [u8; Stream::LEN]);
+
+impl<'a> StreamView<'a> {
+
+    pub fn new(data: &'a [u8]) -> Result<Self, ProgramError> {
+        if data.len() < Stream::LEN {
+            return Err(StreamError::InvalidStreamData.into());
+        }
+
+        Ok(StreamView(array_ref![data, 0, Stream::LEN]))
+    }
+
+    fn pubkey_at(&self, offset: usize) -> Pubkey {
+        Pubkey::new_from_array(*array_ref![self.0, offset, 32])
+    }
+
+    fn u64_at(&self, offset: usize) -> u64 {
+        u64::from_le_bytes(*array_ref![self.0, offset, 8])
+    }
+
+    fn f64_at(&self, offset: usize) -> f64 {
+        f64::from_le_bytes(*array_ref![self.0, offset, 8])
+    }
+
+    pub fn version(&self) -> u8 {
+        self.0[0]
+    }
+
+    pub fn is_initialized(&self) -> bool {
+        self.0[1] == 1
+    }
+
+    /// The zero padded stream name bytes
+    pub fn stream_name_bytes(&self) -> &'a [u8; 32] {
+        array_ref![self.0, 2, 32]
+    }
+
+    pub fn treasurer_address(&self) -> Pubkey {
+        self.pubkey_at(34)
+    }
+
+    pub fn rate_amount(&self) -> f64 {
+        self.f64_at(66)
+    }
+
+    pub fn rate_interval_in_seconds(&self) -> u64 {
+        self.u64_at(74)
+    }
+
+    pub fn start_utc(&self) -> u64 {
+        self.u64_at(90)
+    }
+
+    pub fn beneficiary_address(&self) -> Pubkey {
+        self.pubkey_at(122)
+    }
+
+    pub fn beneficiary_associated_token(&self) -> Pubkey {
+        self.pubkey_at(154)
+    }
+
+    pub fn treasury_address(&self) -> Pubkey {
+        self.pubkey_at(186)
+    }
+
+    pub fn total_deposits(&self) -> f64 {
+        self.f64_at(226)
+    }
+
+    pub fn total_withdrawals(&self) -> f64 {
+        self.f64_at(234)
+    }
+
+    pub fn escrow_vested_amount_snap_block_time(&self) -> u64 {
+        self.u64_at(258)
+    }
+
+    pub fn stream_resumed_block_time(&self) -> u64 {
+        self.u64_at(274)
+    }
+
+    /// Same as `Stream::is_streaming`
+    pub fn is_streaming(&self) -> bool {
+        self.stream_resumed_block_time() >= self.escrow_vested_amount_snap_block_time()
+    }
+
+    pub fn last_activity_utc(&self) -> u64 {
+        self.u64_at(322)
+    }
+}
+
+/// Sum of what `beneficiary` can withdraw at `now_utc` from the `streams` paying them
+pub fn total_claimable(streams: &[Stream], beneficiary: &Pubkey, now_utc: u64) -> f64 {
+    streams
+        .iter()
+        .filter(|stream| stream.initialized && stream.beneficiary_address.eq(beneficiary))
+        .map(|stream| stream.withdrawable_ui_amount(now_utc))
+        .sum()
+}
+
 /// Treasury
 
 #[derive(Clone, Debug)]
@@ -371,7 +1179,10 @@ pub struct Treasury {
     pub initialized: bool,
     pub treasury_block_height: u64,
     pub treasury_mint_address: Pubkey,
-    pub treasury_base_address: Pubkey
+    pub treasury_base_address: Pubkey,
+    pub funding_increment: u64,
+    pub streams_count: u64,
+    pub max_streams: u64 // 0 for unlimited
 }
 
 impl Sealed for Treasury {}
@@ -388,13 +1199,16 @@ impl Default for Treasury {
             initialized: false,
             treasury_block_height: 0,
             treasury_mint_address: Pubkey::default(),
-            treasury_base_address: Pubkey::default()
+            treasury_base_address: Pubkey::default(),
+            funding_increment: 0,
+            streams_count: 0,
+            max_streams: 0
         }
     }
 }
 
 impl Pack for Treasury {
-    const LEN: usize = 73;
+    const LEN: usize = 97;
 
     fn pack_into_slice(&self, output: &mut [u8]) {
         let output = array_mut_ref![output, 0, Treasury::LEN];
@@ -402,15 +1216,21 @@ impl Pack for Treasury {
             initialized_output,
             treasury_block_height_output,
             treasury_mint_address_output,
-            treasury_base_address_output
+            treasury_base_address_output,
+            funding_increment_output,
+            streams_count_output,
+            max_streams_output
             
-        ) = mut_array_refs![output, 1, 8, 32, 32];
+        ) = mut_array_refs![output, 1, 8, 32, 32, 8, 8, 8];
 
         let Treasury {
             initialized,
             treasury_block_height,
             treasury_mint_address,
-            treasury_base_address
+            treasury_base_address,
+            funding_increment,
+            streams_count,
+            max_streams
 
         } = self;
 
@@ -418,6 +1238,9 @@ impl Pack for Treasury {
         *treasury_block_height_output = treasury_block_height.to_le_bytes();
         treasury_mint_address_output.copy_from_slice(treasury_mint_address.as_ref());
         treasury_base_address_output.copy_from_slice(treasury_base_address.as_ref());
+        *funding_increment_output = funding_increment.to_le_bytes();
+        *streams_count_output = streams_count.to_le_bytes();
+        *max_streams_output = max_streams.to_le_bytes();
     }
     
     fn unpack_from_slice(input: &[u8]) -> Result<Self, ProgramError> {
@@ -426,9 +1249,12 @@ impl Pack for Treasury {
             initialized,
             treasury_block_height,
             treasury_mint_address,
-            treasury_base_address
+            treasury_base_address,
+            funding_increment,
+            streams_count,
+            max_streams
 
-        ) = array_refs![input, 1, 8, 32, 32];
+        ) = array_refs![input, 1, 8, 32, 32, 8, 8, 8];
 
         let initialized = match initialized {
             [0] => false,
@@ -440,7 +1266,908 @@ impl Pack for Treasury {
             initialized,             
             treasury_block_height: u64::from_le_bytes(*treasury_block_height),
             treasury_mint_address: Pubkey::new_from_array(*treasury_mint_address),
-            treasury_base_address: Pubkey::new_from_array(*treasury_base_address)
+            treasury_base_address: Pubkey::new_from_array(*treasury_base_address),
+            funding_increment: u64::from_le_bytes(*funding_increment),
+            streams_count: u64::from_le_bytes(*streams_count),
+            max_streams: u64::from_le_bytes(*max_streams)
         })
     }
+}
+
+impl Treasury {
+    /// Data length of the treasury accounts created before `funding_increment`, `streams_count` and
+    /// `max_streams` were added
+    pub const LEGACY_LEN: usize = 73;
+
+    /// Decodes a treasury of the current or the legacy layout, a legacy treasury reads as having no
+    /// funding increment, no streams count and no `max_streams` cap
+    pub fn unpack_any_len(input: &[u8]) -> Result<Self, ProgramError> {
+        if input.len() >= Treasury::LEN {
+            return Treasury::unpack_from_slice(input);
+        }
+
+        if input.len() != Treasury::LEGACY_LEN {
+            return Err(TreasuryError::InvalidTreasuryData.into());
+        }
+
+        let mut current = [0u8; Treasury::LEN];
+        current[..Treasury::LEGACY_LEN].copy_from_slice(input);
+
+        Treasury::unpack_from_slice(&current)
+    }
+
+    /// Packs into a treasury of the current or the legacy layout. The legacy layout has no room for
+    /// a funding increment nor a `max_streams` cap, and does not keep the `streams_count`
+    pub fn pack_any_len(&self, output: &mut [u8]) -> Result<(), ProgramError> {
+        if output.len() >= Treasury::LEN {
+            self.pack_into_slice(output);
+            return Ok(());
+        }
+
+        if output.len() != Treasury::LEGACY_LEN || self.funding_increment != 0 || self.max_streams != 0 {
+            return Err(TreasuryError::InvalidTreasuryData.into());
+        }
+
+        let mut current = [0u8; Treasury::LEN];
+        self.pack_into_slice(&mut current);
+        output.copy_from_slice(&current[..Treasury::LEGACY_LEN]);
+
+        Ok(())
+    }
+}
+
+/// Fraction of `treasury_balance` committed to `streams` at `now_utc` (locked plus withdrawable
+/// amounts). Values above 1.0 mean the treasury is undercollateralized
+pub fn treasury_utilization(streams: &[Stream], treasury_balance: f64, now_utc: u64) -> f64 {
+    let committed: f64 = streams
+        .iter()
+        .filter(|stream| stream.initialized)
+        .map(|stream| stream.locked_amount(now_utc) + stream.withdrawable_ui_amount(now_utc))
+        .sum();
+
+    if treasury_balance <= 0.0
+    {
+        return if committed > 0.0 { f64::INFINITY } else { 0.0 };
+    }
+
+    committed / treasury_balance
+}
+
+/// Any of the accounts owned by the program
+#[derive(Clone, Debug)]
+pub enum MspAccount {
+    Stream(Stream),
+    Terms(StreamTerms),
+    Treasury(Treasury)
+}
+
+/// Decodes a program account, picking the layout from its leading byte: the version of a stream or
+/// stream terms account, or the initialized flag (0 or 1) of a treasury or of a version 0 stream,
+/// which its length tells apart
+pub fn decode_account(input: &[u8]) -> Result<MspAccount, ProgramError> {
+    let discriminant = *input.first().ok_or(StreamError::InvalidStreamData)?;
+    let len = match discriminant {
+        CURRENT_VERSION => Stream::LEN,
+        STREAM_TERMS_VERSION => StreamTerms::LEN,
+        0 | 1 => Treasury::LEGACY_LEN,
+        _ => return Err(StreamError::UnsupportedStateVersion.into())
+    };
+
+    if input.len() < len {
+        return Err(StreamError::InvalidStreamData.into());
+    }
+
+    match discriminant {
+        CURRENT_VERSION => Ok(MspAccount::Stream(Stream::unpack_from_slice(input)?)),
+        STREAM_TERMS_VERSION => Ok(MspAccount::Terms(StreamTerms::unpack_from_slice(input)?)),
+        _ if input.len() == Stream::LEGACY_LEN => Ok(MspAccount::Stream(Stream::unpack_any_version(input)?)),
+        _ => Ok(MspAccount::Treasury(Treasury::unpack_any_len(input)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A stream account as written before the version byte was added (version 0, 289 bytes)
+    const STREAM_V0_FIXTURE: [u8; 289] = [
+        0x01, 0x70, 0x61, 0x79, 0x72, 0x6f, 0x6c, 0x6c, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11,
+        0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11,
+        0x11, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xf8, 0x3f, 0x3c, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x10, 0x5e, 0x5f, 0x00, 0x00, 0x00, 0x00, 0x64, 0x10, 0x5e, 0x5f, 0x00, 0x00, 0x00,
+        0x00, 0x10, 0x0e, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x24,
+        0x40, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x22, 0x22, 0x22, 0x22, 0x22, 0x22, 0x22,
+        0x22, 0x22, 0x22, 0x22, 0x22, 0x22, 0x22, 0x22, 0x22, 0x22, 0x22, 0x22, 0x22, 0x22, 0x22, 0x22,
+        0x22, 0x22, 0x22, 0x22, 0x22, 0x22, 0x22, 0x22, 0x22, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33,
+        0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33,
+        0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x44, 0x44, 0x44, 0x44, 0x44, 0x44, 0x44,
+        0x44, 0x44, 0x44, 0x44, 0x44, 0x44, 0x44, 0x44, 0x44, 0x44, 0x44, 0x44, 0x44, 0x44, 0x44, 0x44,
+        0x44, 0x44, 0x44, 0x44, 0x44, 0x44, 0x44, 0x44, 0x44, 0x00, 0xf1, 0x53, 0x65, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x40, 0x8f, 0x40, 0x00, 0x00, 0x00, 0x00, 0x00, 0x40, 0x6f,
+        0x40, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x29, 0x40, 0x80, 0x4a, 0x5d, 0x05, 0x00, 0x00, 0x00,
+        0x00, 0xa0, 0x96, 0x5f, 0x5f, 0x00, 0x00, 0x00, 0x00, 0x81, 0x4a, 0x5d, 0x05, 0x00, 0x00, 0x00,
+        0x00, 0xa1, 0x96, 0x5f, 0x5f, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00
+    ];
+
+    // 100 tokens at 1 token every 10 seconds from 1_000, with a 100 seconds cliff releasing 5 tokens,
+    // funded at creation
+    fn funded_stream() -> Stream {
+        let mut stream = Stream::default();
+        stream.initialized = true;
+        stream.rate_amount = 1.0;
+        stream.rate_interval_in_seconds = 10;
+        stream.start_utc = 1_000;
+        stream.rate_cliff_in_seconds = 100;
+        stream.cliff_vest_amount = 5.0;
+        stream.total_deposits = 100.0;
+        stream.escrow_vested_amount_snap_block_time = 1_000;
+        stream.stream_resumed_block_time = 1_000;
+        stream
+    }
+
+    #[test]
+    fn vested_amount_before_start_at_cliff_mid_stream_and_depleted() {
+        let stream = funded_stream();
+
+        assert_eq!(stream.vested_ui_amount(500), 0.0);
+        assert_eq!(stream.vested_ui_amount(1_099), 0.0);
+        assert_eq!(stream.vested_ui_amount(1_100), 5.0);
+        assert!((stream.vested_ui_amount(1_200) - 15.0).abs() < 1e-9);
+        assert_eq!(stream.vested_ui_amount(2_050), 100.0);
+        assert_eq!(stream.vested_ui_amount(5_000), 100.0);
+    }
+
+    #[test]
+    fn vested_and_withdrawable_amounts_are_in_base_units() {
+        let mut stream = funded_stream();
+
+        assert_eq!(stream.vested_amount(1_099, 6), Ok(0));
+        assert_eq!(stream.vested_amount(1_100, 6), Ok(5_000_000));
+        assert_eq!(stream.vested_amount(1_103, 6), Ok(5_300_000));
+        assert_eq!(stream.vested_amount(1_103, 0), Ok(5));
+        assert_eq!(stream.vested_amount(5_000, 9), Ok(100_000_000_000));
+
+        stream.total_withdrawals = 5.0;
+        stream.escrow_vested_amount_snap = 0.0;
+        stream.escrow_vested_amount_snap_block_time = 1_100;
+        stream.stream_resumed_block_time = 1_100;
+
+        assert_eq!(stream.vested_amount(1_200, 6), Ok(15_000_000));
+        assert_eq!(stream.withdrawable_amount(1_200, 6), Ok(10_000_000));
+        assert_eq!(stream.withdrawable_amount(1_099, 6), Ok(0));
+
+        // The rounded up maximum can be withdrawn, a base unit more can't
+        assert_eq!(stream.withdrawable_amount(1_107, 0), Ok(1));
+        assert_eq!(stream.check_withdrawal(1.0, 1_107, 0), Ok(()));
+        assert_eq!(stream.check_withdrawal(2.0, 1_107, 0), Err(StreamError::WithdrawalExceedsVested));
+    }
+
+    #[test]
+    fn depletion_is_estimated_only_while_streaming() {
+        let mut stream = funded_stream();
+        assert!(stream.is_streaming());
+
+        // 85 locked at 1_200 run out 850 seconds later, when all 100 have vested
+        assert_eq!(stream.estimated_depletion_utc(1_200), 2_050);
+        assert_eq!(stream.vested_ui_amount(2_050), 100.0);
+        assert_eq!(stream.estimated_depletion_utc(3_000), 3_000);
+
+        stream.escrow_vested_amount_snap_block_time = 1_300;
+        assert!(!stream.is_streaming());
+        assert_eq!(stream.estimated_depletion_utc(1_400), u64::MAX);
+
+        stream.stream_resumed_block_time = 1_300;
+        assert!(stream.is_streaming());
+
+        stream.rate_amount = 0.0;
+        assert_eq!(stream.estimated_depletion_utc(1_400), u64::MAX);
+    }
+
+    #[test]
+    fn theoretical_vested_ignores_pauses() {
+        let mut stream = funded_stream();
+        assert_eq!(stream.theoretical_vested(1_099), 0.0);
+        assert_eq!(stream.theoretical_vested(1_100), 5.0);
+        assert!((stream.theoretical_vested(1_500) - 45.0).abs() < 1e-9);
+        assert_eq!(stream.theoretical_vested(9_000), 100.0);
+
+        // Paused at 1_200 with 15 vested
+        stream.escrow_vested_amount_snap = 15.0;
+        stream.escrow_vested_amount_snap_block_time = 1_200;
+        assert_eq!(stream.vested_ui_amount(1_500), 15.0);
+        assert!((stream.theoretical_vested(1_500) - 45.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn max_withdrawal_without_pause_keeps_the_auto_pause_reserve() {
+        let mut stream = funded_stream();
+
+        // 15 withdrawable at 1_200
+        assert!((stream.max_withdrawal_without_pause(1_200) - 15.0).abs() < 1e-9);
+
+        stream.auto_pause_in_seconds = 50;
+        assert!((stream.max_withdrawal_without_pause(1_200) - 10.0).abs() < 1e-9);
+
+        stream.auto_pause_in_seconds = 500;
+        assert_eq!(stream.max_withdrawal_without_pause(1_200), 0.0);
+        assert_eq!(stream.max_withdrawal_without_pause(1_050), 0.0);
+    }
+
+    #[test]
+    fn a_clock_behind_the_snapshot_accrues_nothing() {
+        let mut stream = funded_stream();
+        stream.escrow_vested_amount_snap = 10.0;
+        stream.escrow_vested_amount_snap_block_time = 1_200;
+        stream.stream_resumed_block_time = 1_200;
+
+        assert_eq!(stream.vested_amount_from_snapshot(1_200), 10.0);
+        assert_eq!(stream.vested_amount_from_snapshot(1_150), 10.0);
+        assert_eq!(stream.vested_amount_from_snapshot(0), 10.0);
+        assert!((stream.vested_amount_from_snapshot(1_210) - 11.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn vested_if_start_vests_from_the_new_start() {
+        let stream = funded_stream();
+        assert!((stream.vested_if_start(1_000, 1_500) - stream.vested_ui_amount(1_500)).abs() < 1e-9);
+
+        // Earlier: the cliff ends at 900 instead of 1_100, 200 seconds more accrue
+        assert!((stream.vested_if_start(800, 1_200) - 35.0).abs() < 1e-9);
+        assert!((stream.vested_ui_amount(1_200) - 15.0).abs() < 1e-9);
+        assert_eq!(stream.vested_if_start(0, 5_000), 100.0);
+
+        // Later: still in the new cliff, then 5 seconds into the rate
+        assert_eq!(stream.vested_if_start(1_150, 1_200), 0.0);
+        assert_eq!(stream.vested_if_start(1_150, 1_250), 5.0);
+        assert!((stream.vested_if_start(1_150, 1_300) - 10.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn content_hash_is_stable_and_follows_every_field() {
+        let stream = funded_stream();
+        assert_eq!(stream.content_hash(), stream.clone().content_hash());
+
+        let mut changes: Vec<Stream> = Vec::new();
+        let mut changed = |change: fn(&mut Stream)| {
+            let mut other = stream.clone();
+            change(&mut other);
+            changes.push(other);
+        };
+
+        changed(|s| s.stream_name = "payroll".to_string());
+        changed(|s| s.treasurer_address = Pubkey::new_unique());
+        changed(|s| s.rate_amount = 2.0);
+        changed(|s| s.rate_interval_in_seconds = 11);
+        changed(|s| s.start_utc = 1_001);
+        changed(|s| s.rate_cliff_in_seconds = 101);
+        changed(|s| s.cliff_vest_amount = 6.0);
+        changed(|s| s.cliff_vest_percent = 1.0);
+        changed(|s| s.beneficiary_address = Pubkey::new_unique());
+        changed(|s| s.beneficiary_associated_token = Pubkey::new_unique());
+        changed(|s| s.treasury_address = Pubkey::new_unique());
+        changed(|s| s.total_deposits = 101.0);
+        changed(|s| s.total_withdrawals = 1.0);
+        changed(|s| s.escrow_vested_amount_snap = 1.0);
+        changed(|s| s.escrow_vested_amount_snap_block_time = 1_001);
+        changed(|s| s.stream_resumed_block_time = 1_001);
+        changed(|s| s.auto_pause_in_seconds = 1);
+        changed(|s| s.last_activity_utc = 1);
+        changed(|s| s.pause_reason = PauseReason::Manual as u8);
+
+        for other in &changes {
+            assert_ne!(other.content_hash(), stream.content_hash(), "{:?}", other);
+        }
+    }
+
+    #[test]
+    fn in_cliff_period_before_at_and_after_the_cliff_end() {
+        let stream = funded_stream();
+        assert_eq!(stream.cliff_end_utc(), 1_100);
+
+        assert!(stream.in_cliff_period(500));
+        assert!(stream.in_cliff_period(1_099));
+        assert!(!stream.in_cliff_period(1_100));
+        assert!(!stream.in_cliff_period(1_101));
+
+        let mut no_cliff = funded_stream();
+        no_cliff.rate_cliff_in_seconds = 0;
+        assert!(no_cliff.in_cliff_period(999));
+        assert!(!no_cliff.in_cliff_period(1_000));
+
+        let mut far = funded_stream();
+        far.start_utc = u64::MAX - 10;
+        assert_eq!(far.cliff_end_utc(), u64::MAX);
+    }
+
+    #[test]
+    fn cliff_amount_vests_once_at_the_cliff_end() {
+        let stream = funded_stream();
+        let jump = stream.vested_ui_amount(1_100) - stream.vested_ui_amount(1_099);
+        assert_eq!(jump, stream.cliff_amount());
+
+        // The rate accrues from the cliff end, the cliff seconds are not pro-rated
+        assert!((stream.vested_ui_amount(1_110) - 6.0).abs() < 1e-9);
+        assert!((stream.vested_ui_amount(1_150) - 10.0).abs() < 1e-9);
+
+        let mut percent = funded_stream();
+        percent.cliff_vest_amount = 0.0;
+        percent.cliff_vest_percent = 20.0;
+        assert_eq!(percent.cliff_amount(), 20.0);
+        assert_eq!(percent.vested_ui_amount(1_099), 0.0);
+        assert_eq!(percent.vested_ui_amount(1_100), 20.0);
+        assert!((percent.vested_ui_amount(1_110) - 21.0).abs() < 1e-9);
+
+        let mut no_cliff_amount = funded_stream();
+        no_cliff_amount.cliff_vest_amount = 0.0;
+        assert_eq!(no_cliff_amount.vested_ui_amount(1_100), 0.0);
+        assert!((no_cliff_amount.vested_ui_amount(1_110) - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn unpack_any_version_decodes_the_v0_fixture() {
+        let stream = Stream::unpack_any_version(&STREAM_V0_FIXTURE).unwrap();
+
+        assert_eq!(stream.version, CURRENT_VERSION);
+        assert!(stream.initialized);
+        assert_eq!(stream.stream_name, "payroll");
+        assert_eq!(stream.treasurer_address, Pubkey::new_from_array([0x11; 32]));
+        assert_eq!(stream.rate_amount, 1.5);
+        assert_eq!(stream.rate_interval_in_seconds, 60);
+        assert_eq!(stream.funded_on_utc, 1_600_000_000);
+        assert_eq!(stream.start_utc, 1_600_000_100);
+        assert_eq!(stream.rate_cliff_in_seconds, 3_600);
+        assert_eq!(stream.cliff_vest_amount, 10.0);
+        assert_eq!(stream.cliff_vest_percent, 0.0);
+        assert_eq!(stream.beneficiary_address, Pubkey::new_from_array([0x22; 32]));
+        assert_eq!(stream.beneficiary_associated_token, Pubkey::new_from_array([0x33; 32]));
+        assert_eq!(stream.treasury_address, Pubkey::new_from_array([0x44; 32]));
+        assert_eq!(stream.treasury_estimated_depletion_utc, 1_700_000_000);
+        assert_eq!(stream.total_deposits, 1_000.0);
+        assert_eq!(stream.total_withdrawals, 250.0);
+        assert_eq!(stream.escrow_vested_amount_snap, 12.5);
+        assert_eq!(stream.escrow_vested_amount_snap_block_height, 90_000_000);
+        assert_eq!(stream.escrow_vested_amount_snap_block_time, 1_600_100_000);
+        assert_eq!(stream.stream_resumed_block_height, 90_000_001);
+        assert_eq!(stream.stream_resumed_block_time, 1_600_100_001);
+        assert_eq!(stream.auto_pause_in_seconds, 0);
+
+        // Added after version 0
+        assert_eq!(stream.beneficiary_token_address, Pubkey::default());
+        assert_eq!(stream.last_activity_utc, 0);
+        assert_eq!(stream.withdraw_hook_program, None);
+        assert_eq!(stream.client_ref, [0; 16]);
+        assert_eq!(stream.pause_reason, PauseReason::NotPaused as u8);
+    }
+
+    #[test]
+    fn pool_tokens_are_minted_one_to_one_on_the_first_deposit() {
+        let stream = funded_stream();
+
+        assert_eq!(stream.pool_tokens_for_contribution(1_500_000, 0, 0), Ok(1_500_000));
+    }
+
+    #[test]
+    fn pool_tokens_are_minted_pro_rata_of_the_treasury_balance() {
+        let stream = funded_stream();
+
+        // The treasury doubled since the 1_000_000 pool tokens were minted, a unit buys half a pool token
+        assert_eq!(stream.pool_tokens_for_contribution(500_000, 1_000_000, 2_000_000), Ok(250_000));
+        assert_eq!(stream.pool_tokens_for_contribution(500_000, 1_000_000, 500_000), Ok(1_000_000));
+        assert_eq!(stream.pool_tokens_for_contribution(u64::MAX, u64::MAX, u64::MAX), Ok(u64::MAX));
+        assert_eq!(stream.pool_tokens_for_contribution(u64::MAX, 2, 1), Err(StreamError::Overflow));
+    }
+
+    #[test]
+    fn pool_tokens_are_minted_one_to_one_into_an_empty_treasury() {
+        let stream = funded_stream();
+
+        assert_eq!(stream.pool_tokens_for_contribution(500_000, 1_000_000, 0), Ok(500_000));
+    }
+
+    #[cfg(feature = "borsh")]
+    #[test]
+    fn borsh_round_trips_what_pack_round_trips() {
+        let mut stream = Stream::unpack_any_version(&STREAM_V0_FIXTURE).unwrap();
+        stream.withdraw_hook_program = Some(Pubkey::new_unique());
+        stream.client_ref = [7; 16];
+
+        let decoded = Stream::try_from_borsh(&stream.to_borsh().unwrap()).unwrap();
+        assert_eq!(decoded.content_hash(), stream.content_hash());
+
+        // "payroll" takes 4 + 7 bytes instead of 32, the unset hook 1 byte instead of 33
+        stream.withdraw_hook_program = None;
+        assert_eq!(stream.to_borsh().unwrap().len(), Stream::LEN - (32 - 11) - 32);
+
+        let mut terms = StreamTerms::default();
+        terms.initialized = true;
+        terms.stream_name = "ñandú".to_string();
+        terms.rate_amount = 2.5;
+        terms.proposed_at_utc = 1_000;
+
+        let decoded = StreamTerms::try_from_borsh(&terms.to_borsh().unwrap()).unwrap();
+        assert_eq!(decoded.stream_name, terms.stream_name);
+        assert_eq!(decoded.rate_amount, 2.5);
+        assert_eq!(decoded.proposed_at_utc, 1_000);
+
+        assert!(Stream::try_from_borsh(&[0u8; 3]).is_err());
+    }
+
+    #[test]
+    fn unpack_many_decodes_each_account_on_its_own() {
+        let stream = funded_stream();
+        let mut data = [0u8; Stream::LEN];
+        stream.pack_into_slice(&mut data);
+
+        let mut other_version = data;
+        other_version[0] = STREAM_TERMS_VERSION;
+
+        let unpacked = Stream::unpack_many(&[&data, &data[..Stream::LEN - 1], &other_version, &[]]);
+
+        assert_eq!(unpacked.len(), 4);
+        assert_eq!(unpacked[0].as_ref().unwrap().total_deposits, stream.total_deposits);
+        assert_eq!(unpacked[1].as_ref().unwrap_err(), &StreamError::InvalidStreamData.into());
+        assert_eq!(unpacked[2].as_ref().unwrap_err(), &StreamError::UnsupportedStateVersion.into());
+        assert_eq!(unpacked[3].as_ref().unwrap_err(), &StreamError::InvalidStreamData.into());
+        assert!(Stream::unpack_many(&[]).is_empty());
+    }
+
+    #[test]
+    fn stream_view_reads_what_unpack_decodes() {
+        let mut stream = Stream::unpack_any_version(&STREAM_V0_FIXTURE).unwrap();
+        stream.last_activity_utc = 1_600_200_000;
+
+        let mut data = [0u8; Stream::LEN];
+        stream.pack_into_slice(&mut data);
+
+        let view = StreamView::new(&data).unwrap();
+        let unpacked = Stream::unpack_from_slice(&data).unwrap();
+
+        assert_eq!(view.version(), unpacked.version);
+        assert_eq!(view.is_initialized(), unpacked.initialized);
+        assert_eq!(&view.stream_name_bytes()[..7], unpacked.stream_name.as_bytes());
+        assert_eq!(view.treasurer_address(), unpacked.treasurer_address);
+        assert_eq!(view.rate_amount(), unpacked.rate_amount);
+        assert_eq!(view.rate_interval_in_seconds(), unpacked.rate_interval_in_seconds);
+        assert_eq!(view.start_utc(), unpacked.start_utc);
+        assert_eq!(view.beneficiary_address(), unpacked.beneficiary_address);
+        assert_eq!(view.beneficiary_associated_token(), unpacked.beneficiary_associated_token);
+        assert_eq!(view.treasury_address(), unpacked.treasury_address);
+        assert_eq!(view.total_deposits(), unpacked.total_deposits);
+        assert_eq!(view.total_withdrawals(), unpacked.total_withdrawals);
+        assert_eq!(view.escrow_vested_amount_snap_block_time(), unpacked.escrow_vested_amount_snap_block_time);
+        assert_eq!(view.stream_resumed_block_time(), unpacked.stream_resumed_block_time);
+        assert_eq!(view.is_streaming(), unpacked.is_streaming());
+        assert_eq!(view.last_activity_utc(), unpacked.last_activity_utc);
+
+        assert!(StreamView::new(&data[..Stream::LEN - 1]).is_err());
+    }
+
+    #[test]
+    fn legacy_treasuries_decode_without_a_cap_and_keep_their_layout() {
+        let mut treasury = Treasury::default();
+        treasury.initialized = true;
+        treasury.treasury_block_height = 7;
+        treasury.treasury_base_address = Pubkey::new_unique();
+        treasury.funding_increment = 100;
+        treasury.streams_count = 2;
+        treasury.max_streams = 5;
+
+        let mut current = [0u8; Treasury::LEN];
+        treasury.pack_into_slice(&mut current);
+        let legacy = &current[..Treasury::LEGACY_LEN];
+
+        let decoded = Treasury::unpack_any_len(legacy).unwrap();
+        assert_eq!(decoded.treasury_block_height, 7);
+        assert_eq!(decoded.treasury_base_address, treasury.treasury_base_address);
+        assert_eq!(decoded.funding_increment, 0);
+        assert_eq!(decoded.streams_count, 0);
+        assert_eq!(decoded.max_streams, 0);
+        assert!(matches!(decode_account(legacy).unwrap(), MspAccount::Treasury(_)));
+
+        let mut legacy_output = [0u8; Treasury::LEGACY_LEN];
+        assert!(treasury.pack_any_len(&mut legacy_output).is_err());
+        assert!(Treasury { max_streams: 0, ..treasury.clone() }.pack_any_len(&mut legacy_output).is_err());
+        assert!(Treasury { funding_increment: 0, ..treasury.clone() }.pack_any_len(&mut legacy_output).is_err());
+
+        // The streams count is not stored
+        let counted = Treasury { streams_count: 3, ..decoded.clone() };
+        counted.pack_any_len(&mut legacy_output).unwrap();
+        assert_eq!(&legacy_output[..], legacy);
+
+        assert!(Treasury::unpack_any_len(&current[..Treasury::LEGACY_LEN - 1]).is_err());
+        assert_eq!(Treasury::unpack_any_len(&current).unwrap().max_streams, 5);
+    }
+
+    #[test]
+    fn unpack_any_version_picks_the_v0_layout_by_length() {
+        let mut uninitialized = STREAM_V0_FIXTURE;
+        uninitialized[0] = 0;
+        assert!(!Stream::unpack_any_version(&uninitialized).unwrap().initialized);
+
+        // Neither version 0 nor the current layout
+        assert!(Stream::unpack_any_version(&STREAM_V0_FIXTURE[..Stream::LEGACY_LEN - 1]).is_err());
+        assert!(Stream::unpack_any_version(&[&STREAM_V0_FIXTURE[..], &[0; 33]].concat()).is_err());
+        assert!(Stream::unpack_any_version(&[&[2], &STREAM_V0_FIXTURE[..]].concat()).is_err());
+
+        let mut current = [0u8; Stream::LEN];
+        Stream::unpack_any_version(&STREAM_V0_FIXTURE).unwrap().pack_into_slice(&mut current);
+        assert_eq!(Stream::unpack_any_version(&current).unwrap().total_withdrawals, 250.0);
+    }
+
+    #[test]
+    fn snapshot_and_scratch_vesting_agree() {
+        let scratch = funded_stream();
+
+        // Snapshots taken before, at and after the cliff end, as a withdrawal of nothing would
+        for snap_time in [1_050, 1_100, 1_300].iter() {
+            let mut snapped = funded_stream();
+            snapped.escrow_vested_amount_snap = scratch.vested_amount_from_snapshot(*snap_time);
+            snapped.escrow_vested_amount_snap_block_time = *snap_time;
+            snapped.stream_resumed_block_time = *snap_time;
+
+            for now in [*snap_time, 1_500, 2_050, 5_000].iter() {
+                assert!((snapped.vested_ui_amount(*now) - scratch.vested_ui_amount(*now)).abs() < 1e-9, "{} {}", snap_time, now);
+                assert!((snapped.vested_ui_amount(*now) - scratch.theoretical_vested(*now)).abs() < 1e-9, "{} {}", snap_time, now);
+            }
+        }
+
+        // Paused at 1_300 and resumed at 1_500, the 200 seconds paused don't vest
+        let mut paused = funded_stream();
+        paused.escrow_vested_amount_snap = scratch.vested_amount_from_snapshot(1_300);
+        paused.escrow_vested_amount_snap_block_time = 1_300;
+        paused.stream_resumed_block_time = 1_500;
+
+        assert!((paused.vested_ui_amount(1_600) - (scratch.theoretical_vested(1_600) - 20.0)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn ledger_balances_before_start_in_cliff_mid_stream_and_depleted() {
+        let mut stream = funded_stream();
+
+        for now in [500, 1_099, 1_100, 1_200, 2_050, 5_000].iter() {
+            assert!(stream.ledger_is_balanced(*now), "{}", now);
+        }
+
+        // Withdraw 20 at 1_250 (25 vested), snapshotting the 5 left as the processor does
+        stream.total_withdrawals = 20.0;
+        stream.escrow_vested_amount_snap = 5.0;
+        stream.escrow_vested_amount_snap_block_time = 1_250;
+        stream.stream_resumed_block_time = 1_250;
+
+        for now in [1_250, 1_500, 2_050, 5_000].iter() {
+            assert!(stream.ledger_is_balanced(*now), "{}", now);
+            assert!(stream.vested_ui_amount(*now) <= 100.0);
+        }
+
+        assert_eq!(stream.withdrawable_ui_amount(5_000), 80.0);
+        assert_eq!(stream.locked_amount(5_000), 0.0);
+    }
+
+    #[test]
+    fn ledger_is_unbalanced_by_inconsistent_balances() {
+        let mut overdrawn = funded_stream();
+        overdrawn.total_withdrawals = 101.0;
+        assert!(!overdrawn.ledger_is_balanced(1_200));
+
+        let mut oversnapped = funded_stream();
+        oversnapped.total_withdrawals = 90.0;
+        oversnapped.escrow_vested_amount_snap = 20.0;
+        oversnapped.escrow_vested_amount_snap_block_time = 1_500;
+        assert!(!oversnapped.ledger_is_balanced(1_500));
+
+        let mut negative = funded_stream();
+        negative.escrow_vested_amount_snap = -1.0;
+        assert!(!negative.ledger_is_balanced(1_200));
+    }
+
+    #[test]
+    fn stream_name_round_trips_without_padding() {
+        let mut stream = Stream::default();
+
+        for name in ["", "payroll", "ñandú streaming", "exactly thirty two bytes long!!!"] {
+            stream.stream_name = name.to_string();
+
+            let mut data = [0u8; Stream::LEN];
+            stream.pack_into_slice(&mut data);
+
+            assert_eq!(Stream::unpack_from_slice(&data).unwrap().stream_name, name);
+        }
+    }
+
+    #[test]
+    fn unpack_stream_name_trims_the_padding_and_replaces_invalid_utf8() {
+        let mut field = [0u8; 32];
+        assert_eq!(unpack_stream_name(&field), "");
+
+        field[..7].copy_from_slice(b"payroll");
+        assert_eq!(unpack_stream_name(&field), "payroll");
+
+        // Only the trailing zeros are padding
+        field[3] = 0;
+        assert_eq!(unpack_stream_name(&field), "pay\0oll");
+
+        field[..2].copy_from_slice(&[0xff, 0xfe]);
+        assert_eq!(unpack_stream_name(&field), "\u{fffd}\u{fffd}y\0oll");
+    }
+
+    #[test]
+    fn unpack_prefixed_stream_name_rejects_bad_lengths_and_invalid_utf8() {
+        let mut field = [0u8; 36];
+        pack_prefixed_stream_name("payroll", &mut field);
+        assert_eq!(unpack_prefixed_stream_name(&field).unwrap(), "payroll");
+
+        // The length, not the padding, delimits the name
+        field[..4].copy_from_slice(&3u32.to_le_bytes());
+        assert_eq!(unpack_prefixed_stream_name(&field).unwrap(), "pay");
+        field[..4].copy_from_slice(&32u32.to_le_bytes());
+        assert_eq!(unpack_prefixed_stream_name(&field).unwrap(), format!("payroll{}", "\0".repeat(25)));
+
+        field[..4].copy_from_slice(&33u32.to_le_bytes());
+        assert_eq!(unpack_prefixed_stream_name(&field).unwrap_err(), StreamError::InvalidStreamData.into());
+
+        field[..4].copy_from_slice(&2u32.to_le_bytes());
+        field[4..6].copy_from_slice(&[0xff, 0xfe]);
+        assert_eq!(unpack_prefixed_stream_name(&field).unwrap_err(), StreamError::InvalidStreamData.into());
+    }
+
+    #[test]
+    fn prefixed_stream_name_round_trips() {
+        let mut terms = StreamTerms::default();
+        terms.stream_name = "ñandú".to_string();
+
+        let mut data = [0u8; StreamTerms::LEN];
+        terms.pack_into_slice(&mut data);
+
+        assert_eq!(StreamTerms::unpack_from_slice(&data).unwrap().stream_name, "ñandú");
+    }
+
+    #[test]
+    fn pack_truncates_names_over_max_len_at_a_char_boundary() {
+        let mut stream = Stream::default();
+        stream.stream_name = "x".repeat(MAX_STREAM_NAME_LEN + 1);
+
+        let mut data = [0u8; Stream::LEN];
+        stream.pack_into_slice(&mut data);
+        assert_eq!(Stream::unpack_from_slice(&data).unwrap().stream_name, "x".repeat(MAX_STREAM_NAME_LEN));
+
+        // 31 bytes and a 2 bytes char, which does not fit whole
+        stream.stream_name = format!("{}ñ", "x".repeat(MAX_STREAM_NAME_LEN - 1));
+        stream.pack_into_slice(&mut data);
+        assert_eq!(Stream::unpack_from_slice(&data).unwrap().stream_name, "x".repeat(MAX_STREAM_NAME_LEN - 1));
+
+        let mut terms = StreamTerms::default();
+        terms.stream_name = stream.stream_name.clone();
+
+        let mut terms_data = [0u8; StreamTerms::LEN];
+        terms.pack_into_slice(&mut terms_data);
+        assert_eq!(StreamTerms::unpack_from_slice(&terms_data).unwrap().stream_name, "x".repeat(MAX_STREAM_NAME_LEN - 1));
+    }
+
+    #[test]
+    fn withdrawals_up_to_the_withdrawable_amount_pass() {
+        let mut stream = funded_stream();
+        stream.total_withdrawals = 5.0;
+        stream.escrow_vested_amount_snap = 0.0;
+        stream.escrow_vested_amount_snap_block_time = 1_100;
+        stream.stream_resumed_block_time = 1_100;
+
+        // 10 vested since the last withdrawal
+        assert!((stream.withdrawable_ui_amount(1_200) - 10.0).abs() < 1e-9);
+        assert_eq!(stream.check_withdrawal(9.5, 1_200, 6), Ok(()));
+        assert_eq!(stream.check_withdrawal(stream.withdrawable_ui_amount(1_200), 1_200, 6), Ok(()));
+        assert_eq!(stream.check_withdrawal(10.5, 1_200, 6), Err(StreamError::WithdrawalExceedsVested));
+    }
+
+    #[test]
+    fn stream_terms_round_trip_and_expire_after_their_expiration() {
+        let mut terms = StreamTerms::default();
+        terms.initialized = true;
+        terms.proposed_at_utc = 1_000;
+        terms.expiration_in_seconds = 3_600;
+
+        let mut data = [0u8; StreamTerms::LEN];
+        terms.pack_into_slice(&mut data);
+        let decoded = StreamTerms::unpack_from_slice(&data).unwrap();
+
+        assert_eq!(decoded.proposed_at_utc, 1_000);
+        assert_eq!(decoded.expiration_in_seconds, 3_600);
+        assert!(!decoded.is_expired(4_599));
+        assert!(decoded.is_expired(4_600));
+    }
+
+    #[test]
+    fn decode_account_picks_the_layout_from_the_leading_byte() {
+        let mut stream = Stream::default();
+        stream.initialized = true;
+        stream.rate_amount = 2.5;
+        let mut stream_data = vec![0u8; Stream::LEN];
+        stream.pack_into_slice(&mut stream_data);
+
+        let mut terms = StreamTerms::default();
+        terms.initialized = true;
+        terms.proposed_at_utc = 42;
+        let mut terms_data = vec![0u8; StreamTerms::LEN];
+        terms.pack_into_slice(&mut terms_data);
+
+        let mut treasury = Treasury::default();
+        treasury.initialized = true;
+        treasury.streams_count = 3;
+        let mut treasury_data = vec![0u8; Treasury::LEN];
+        treasury.pack_into_slice(&mut treasury_data);
+
+        match decode_account(&stream_data).unwrap() {
+            MspAccount::Stream(decoded) => assert_eq!(decoded.rate_amount, 2.5),
+            other => panic!("decoded {:?}", other)
+        }
+
+        match decode_account(&terms_data).unwrap() {
+            MspAccount::Terms(decoded) => assert_eq!(decoded.proposed_at_utc, 42),
+            other => panic!("decoded {:?}", other)
+        }
+
+        match decode_account(&treasury_data).unwrap() {
+            MspAccount::Treasury(decoded) => assert_eq!(decoded.streams_count, 3),
+            other => panic!("decoded {:?}", other)
+        }
+
+        // A version 0 stream starts with its initialized flag like a treasury, its length tells it apart
+        match decode_account(&STREAM_V0_FIXTURE).unwrap() {
+            MspAccount::Stream(decoded) => assert_eq!(decoded.stream_name, "payroll"),
+            other => panic!("decoded {:?}", other)
+        }
+
+        // Same lengths, a padded buffer still decodes by its leading byte
+        stream_data.extend_from_slice(&[0u8; 8]);
+        assert!(matches!(decode_account(&stream_data), Ok(MspAccount::Stream(_))));
+        assert!(decode_account(&terms_data[..StreamTerms::LEN - 1]).is_err());
+        assert!(decode_account(&[0xff; Stream::LEN]).is_err());
+    }
+
+    #[test]
+    fn unpack_rejects_an_other_version() {
+        let mut stream = Stream::default();
+        stream.initialized = true;
+
+        let mut data = [0u8; Stream::LEN];
+        stream.pack_into_slice(&mut data);
+        data[0] = STREAM_TERMS_VERSION;
+
+        assert_eq!(Stream::unpack_from_slice(&data).unwrap_err(), StreamError::UnsupportedStateVersion.into());
+    }
+
+    #[test]
+    fn status_follows_the_stream_lifecycle() {
+        let mut stream = funded_stream();
+
+        assert_eq!(stream.status(500), StreamStatus::Scheduled);
+        assert_eq!(stream.status(1_500), StreamStatus::Streaming);
+        assert_eq!(stream.status(5_000), StreamStatus::Depleted);
+
+        stream.escrow_vested_amount_snap = 45.0;
+        stream.escrow_vested_amount_snap_block_time = 1_500;
+        assert_eq!(stream.status(3_000), StreamStatus::Paused);
+
+        stream.initialized = false;
+        assert_eq!(stream.status(3_000), StreamStatus::Closed);
+    }
+
+    #[test]
+    fn treasury_utilization_counts_what_initialized_streams_still_owe() {
+        let mut withdrawn = funded_stream();
+        withdrawn.total_withdrawals = 20.0;
+        let mut closed = funded_stream();
+        closed.initialized = false;
+        let streams = [funded_stream(), withdrawn, closed];
+
+        assert_eq!(treasury_utilization(&streams, 360.0, 1_500), 0.5);
+        assert_eq!(treasury_utilization(&streams, 90.0, 1_500), 2.0);
+        assert_eq!(treasury_utilization(&streams, 0.0, 1_500), f64::INFINITY);
+        assert_eq!(treasury_utilization(&[], 0.0, 1_500), 0.0);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn streams_serialize_their_addresses_as_base58() {
+        let mut stream = funded_stream();
+        stream.beneficiary_address = Pubkey::new_unique();
+
+        let json = serde_json::to_value(&stream).unwrap();
+        assert_eq!(json["beneficiary_address"], stream.beneficiary_address.to_string());
+
+        let decoded: Stream = serde_json::from_value(json).unwrap();
+        assert_eq!(decoded.beneficiary_address, stream.beneficiary_address);
+        assert_eq!(decoded.total_deposits, stream.total_deposits);
+    }
+
+    #[test]
+    fn streams_without_a_rate_or_a_cliff_vest_are_not_payable() {
+        let mut stream = funded_stream();
+        assert!(stream.is_payable());
+
+        stream.rate_amount = 0.0;
+        assert!(stream.is_payable());
+
+        stream.cliff_vest_amount = 0.0;
+        assert!(!stream.is_payable());
+
+        stream.cliff_vest_percent = 10.0;
+        assert!(stream.is_payable());
+
+        stream.cliff_vest_percent = 0.0;
+        stream.rate_amount = 1.0;
+        stream.rate_interval_in_seconds = 0;
+        assert!(!stream.is_payable());
+    }
+
+    #[test]
+    fn remaining_intervals_count_the_full_payouts_left() {
+        let mut stream = funded_stream();
+        assert_eq!(stream.remaining_intervals(1_500), 55);
+        assert_eq!(stream.remaining_intervals(1_505), 54);
+        assert_eq!(stream.remaining_intervals(5_000), 0);
+
+        stream.total_deposits = 0.3;
+        stream.rate_amount = 0.1;
+        stream.cliff_vest_amount = 0.0;
+        assert_eq!(stream.remaining_intervals(500), 3);
+
+        stream.rate_amount = 0.0;
+        assert_eq!(stream.remaining_intervals(500), 0);
+    }
+
+    #[test]
+    fn rent_exempt_lamports_cover_two_years_of_the_account_length() {
+        let rent = Rent::default();
+
+        // 3_480 lamports per byte year, the account header is 128 bytes
+        assert_eq!(Stream::rent_exempt_lamports(&rent), (Stream::LEN as u64 + 128) * 3_480 * 2);
+        assert_eq!(StreamTerms::rent_exempt_lamports(&rent), (StreamTerms::LEN as u64 + 128) * 3_480 * 2);
+    }
+
+    #[test]
+    fn total_claimable_sums_the_initialized_streams_of_the_beneficiary() {
+        let beneficiary = Pubkey::new_unique();
+        let mut paying = funded_stream();
+        paying.beneficiary_address = beneficiary;
+        let mut closed = paying.clone();
+        closed.initialized = false;
+        let other = funded_stream();
+
+        // 15 vested on each stream by 1_200
+        let streams = [paying.clone(), paying, closed, other];
+        assert!((total_claimable(&streams, &beneficiary, 1_200) - 30.0).abs() < 1e-9);
+        assert_eq!(total_claimable(&[], &beneficiary, 1_200), 0.0);
+    }
+
+    #[test]
+    fn rates_per_day_and_month_scale_the_rate_per_second() {
+        let mut stream = funded_stream();
+
+        // 1 token every 10 seconds
+        assert!((stream.rate_per_day() - 8_640.0).abs() < 1e-9);
+        assert!((stream.rate_per_month() - 259_200.0).abs() < 1e-9);
+
+        stream.rate_interval_in_seconds = 0;
+        assert_eq!(stream.rate_per_day(), 0.0);
+        assert_eq!(stream.rate_per_month(), 0.0);
+    }
+
+    #[test]
+    fn should_auto_pause_when_less_than_the_threshold_is_left() {
+        let mut stream = funded_stream();
+        assert!(!stream.should_auto_pause(2_000));
+
+        // 100 seconds at 0.1 per second, 10 tokens
+        stream.auto_pause_in_seconds = 100;
+        assert!(!stream.should_auto_pause(1_500));
+        assert!(!stream.should_auto_pause(1_950));
+        assert!(stream.should_auto_pause(1_960));
+
+        stream.escrow_vested_amount_snap = 45.0;
+        stream.escrow_vested_amount_snap_block_time = 1_500;
+        assert!(!stream.should_auto_pause(1_960));
+    }
 }
\ No newline at end of file